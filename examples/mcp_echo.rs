@@ -0,0 +1,98 @@
+//! A minimal MCP server over stdio, for trying out Quill's MCP client.
+//!
+//! Build it with `cargo build --example mcp_echo` and add it to the config:
+//!
+//! ```json
+//! { "mcp_servers": { "echo": { "command": "target/debug/examples/mcp_echo" } } }
+//! ```
+
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+fn main() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    for line in stdin.lock().lines() {
+        let Ok(request) = serde_json::from_str::<Value>(&line?) else {
+            continue;
+        };
+        // Notifications have no id and get no reply.
+        let Some(id) = request.get("id").cloned() else {
+            continue;
+        };
+        let params = request.get("params").cloned().unwrap_or_default();
+        let response = match request.get("method").and_then(Value::as_str).unwrap_or_default() {
+            "initialize" => Ok(json!({
+                "protocolVersion": "2024-11-05",
+                "capabilities": { "tools": {}, "resources": {} },
+                "serverInfo": { "name": "echo", "version": "0.1.0" },
+            })),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": [
+                {
+                    "name": "echo",
+                    "description": "Returns the given text unchanged.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": { "text": { "type": "string" } },
+                        "required": ["text"],
+                    },
+                },
+                {
+                    "name": "add",
+                    "description": "Adds two numbers.",
+                    "inputSchema": {
+                        "type": "object",
+                        "properties": { "a": { "type": "number" }, "b": { "type": "number" } },
+                        "required": ["a", "b"],
+                    },
+                },
+                {
+                    "name": "exit",
+                    "description": "Exits without replying, like a server that crashes.",
+                    "inputSchema": { "type": "object", "properties": {} },
+                },
+            ] })),
+            "tools/call" => call_tool(&params),
+            "resources/list" => Ok(json!({ "resources": [
+                { "uri": "echo://readme", "name": "readme", "description": "What this server does" },
+            ] })),
+            "resources/read" => match params.get("uri").and_then(Value::as_str) {
+                Some(uri @ "echo://readme") => Ok(json!({ "contents": [
+                    { "uri": uri, "mimeType": "text/plain", "text": "The echo server repeats text and adds numbers." },
+                ] })),
+                _ => Err("unknown resource"),
+            },
+            _ => Err("method not found"),
+        };
+        let message = match response {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32601, "message": e } }),
+        };
+        writeln!(stdout, "{}", message)?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+fn call_tool(params: &Value) -> Result<Value, &'static str> {
+    let args = params.get("arguments").cloned().unwrap_or_default();
+    let text = match params.get("name").and_then(Value::as_str) {
+        Some("echo") => args.get("text").and_then(Value::as_str).unwrap_or_default().to_string(),
+        Some("add") => {
+            let number = |key: &str| args.get(key).and_then(Value::as_f64);
+            match (number("a"), number("b")) {
+                (Some(a), Some(b)) => (a + b).to_string(),
+                _ => {
+                    return Ok(json!({
+                        "content": [{ "type": "text", "text": "a and b must be numbers" }],
+                        "isError": true,
+                    }))
+                }
+            }
+        }
+        Some("exit") => std::process::exit(1),
+        _ => return Err("unknown tool"),
+    };
+    Ok(json!({ "content": [{ "type": "text", "text": text }] }))
+}
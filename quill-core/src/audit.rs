@@ -0,0 +1,213 @@
+//! Audit log of tool calls: what the model asked for, who approved it, how
+//! long it ran and what it changed.
+//!
+//! Each call is one JSON object per line in `audit.jsonl` under the user's
+//! data directory. When the file passes `MAX_BYTES` it is rotated to
+//! `audit.1.jsonl`, `audit.2.jsonl` and so on, keeping `KEEP_ROTATED` old files.
+
+use crate::tools::ToolCall;
+use crate::utils::format_file_size;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+const MAX_BYTES: u64 = 5 * 1024 * 1024;
+const KEEP_ROTATED: usize = 3;
+/// String arguments longer than this, such as file contents, are cut.
+const MAX_ARGUMENT_CHARS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    /// Approved by the user.
+    Accepted,
+    /// Ran without asking because of an allow rule.
+    Auto,
+    /// Refused by the user.
+    Denied,
+    /// Refused by a deny rule.
+    Blocked,
+    /// Called by an MCP client through `quill mcp-serve`, which does its own approval.
+    Client,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub session: String,
+    pub tool: String,
+    pub target: String,
+    pub arguments: Value,
+    pub decision: Decision,
+    /// The rule or setting behind an automatic decision.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Not set for calls that did not run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result_bytes: Option<usize>,
+    #[serde(default)]
+    pub error: bool,
+    /// Paths created, changed, moved or removed, relative to the working directory.
+    #[serde(default)]
+    pub files_changed: Vec<String>,
+    #[serde(skip)]
+    started: Option<Instant>,
+}
+
+impl Entry {
+    /// Records the outcome of a call that ran.
+    pub fn finish(&mut self, result: &str, changed: &[PathBuf], working_directory: &Path) {
+        self.duration_ms = self.started.map(|started| started.elapsed().as_millis() as u64);
+        self.result_bytes = Some(result.len());
+        self.error = result.starts_with("[TOOL ERROR");
+        self.files_changed = changed
+            .iter()
+            .map(|path| path.strip_prefix(working_directory).unwrap_or(path).display().to_string())
+            .collect();
+    }
+
+    /// One line for the `/audit` viewer.
+    pub fn summary(&self) -> String {
+        let mut line = format!(
+            "{} {:<8} {} {}",
+            self.timestamp.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S"),
+            format!("{:?}", self.decision).to_lowercase(),
+            self.tool,
+            self.target
+        );
+        let mut details = Vec::new();
+        if let Some(ms) = self.duration_ms {
+            details.push(format!("{} ms", ms));
+        }
+        if let Some(bytes) = self.result_bytes {
+            details.push(format_file_size(bytes as u64));
+        }
+        if self.error {
+            details.push("failed".to_string());
+        }
+        if !self.files_changed.is_empty() {
+            details.push(format!("changed {}", self.files_changed.join(", ")));
+        }
+        if let Some(reason) = &self.reason {
+            details.push(reason.clone());
+        }
+        if !details.is_empty() {
+            line.push_str(&format!(" ({})", details.join("; ")));
+        }
+        line
+    }
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    session: String,
+}
+
+impl AuditLog {
+    /// A log in `data_dir`, shared by every session.
+    pub fn new(data_dir: &Path) -> Self {
+        AuditLog {
+            path: data_dir.join("audit.jsonl"),
+            session: uuid::Uuid::new_v4().simple().to_string()[..8].to_string(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Starts an entry for a call; its duration is measured from here.
+    pub fn entry(&self, tool_call: &ToolCall, decision: Decision, reason: Option<String>) -> Entry {
+        let mut arguments = tool_call.arguments();
+        shorten_strings(&mut arguments);
+        Entry {
+            timestamp: chrono::Utc::now(),
+            session: self.session.clone(),
+            tool: tool_call.name().to_string(),
+            target: tool_call.target(),
+            arguments,
+            decision,
+            reason,
+            duration_ms: None,
+            result_bytes: None,
+            error: false,
+            files_changed: Vec::new(),
+            started: Some(Instant::now()),
+        }
+    }
+
+    /// Appends an entry, rotating the log first if it has grown too large.
+    pub fn write(&self, entry: &Entry) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
+        }
+        if fs::metadata(&self.path).is_ok_and(|meta| meta.len() >= MAX_BYTES) {
+            self.rotate()?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("cannot open {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+        Ok(())
+    }
+
+    /// The last `count` entries, oldest first, reading rotated files as needed.
+    pub fn recent(&self, count: usize) -> Result<Vec<Entry>> {
+        let mut entries: Vec<Entry> = Vec::new();
+        for index in 0..=KEEP_ROTATED {
+            let content = match fs::read_to_string(self.rotated(index)) {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+                Err(e) => return Err(e.into()),
+            };
+            let mut older: Vec<Entry> = content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+            older.append(&mut entries);
+            entries = older;
+            if entries.len() >= count {
+                break;
+            }
+        }
+        let skip = entries.len().saturating_sub(count);
+        Ok(entries.split_off(skip))
+    }
+
+    fn rotate(&self) -> Result<()> {
+        let _ = fs::remove_file(self.rotated(KEEP_ROTATED));
+        for index in (1..=KEEP_ROTATED).rev() {
+            let from = self.rotated(index - 1);
+            if from.exists() {
+                fs::rename(&from, self.rotated(index)).with_context(|| format!("cannot rotate {}", from.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `audit.jsonl` for 0, `audit.N.jsonl` for older files.
+    fn rotated(&self, index: usize) -> PathBuf {
+        if index == 0 {
+            self.path.clone()
+        } else {
+            self.path.with_file_name(format!("audit.{}.jsonl", index))
+        }
+    }
+}
+
+fn shorten_strings(value: &mut Value) {
+    match value {
+        Value::String(s) if s.chars().count() > MAX_ARGUMENT_CHARS => {
+            let kept: String = s.chars().take(MAX_ARGUMENT_CHARS).collect();
+            *s = format!("{}... [{} bytes]", kept, s.len());
+        }
+        Value::Array(items) => items.iter_mut().for_each(shorten_strings),
+        Value::Object(map) => map.values_mut().for_each(shorten_strings),
+        _ => {}
+    }
+}
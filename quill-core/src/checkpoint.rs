@@ -0,0 +1,328 @@
+//! Snapshots of the workspace taken before each tool-driven write, so edits
+//! can be undone without relying on git.
+//!
+//! Every accepted writing call gets a checkpoint holding the previous state of
+//! each path it touches: missing, a file or a whole directory tree. Copies are
+//! kept under the data directory, one folder per session, and restoring
+//! a checkpoint puts those paths back exactly as they were.
+
+use crate::tools::Changes;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Sessions older than this are deleted when a new session starts.
+const KEEP_SESSIONS_FOR: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Saved {
+    /// The path did not exist; restoring removes whatever is there.
+    Missing,
+    File,
+    Dir,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    pub path: PathBuf,
+    pub saved: Saved,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Checkpoint {
+    pub id: usize,
+    /// The user turn during which the call was accepted (1-based).
+    pub turn: usize,
+    pub tool: String,
+    pub target: String,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub snapshots: Vec<Snapshot>,
+}
+
+pub struct Checkpoints {
+    dir: PathBuf,
+    pub checkpoints: Vec<Checkpoint>,
+    /// First line of each user message, indexed by turn - 1.
+    pub turns: Vec<String>,
+    next_id: usize,
+}
+
+impl Checkpoints {
+    /// Creates an empty store for a new session under `data_dir`.
+    pub fn new(data_dir: &Path) -> Self {
+        let root = data_dir.join("checkpoints");
+        prune_old_sessions(&root);
+        let session = format!("{}-{}", chrono::Utc::now().format("%Y%m%d-%H%M%S"), uuid::Uuid::new_v4().simple());
+        Checkpoints {
+            dir: root.join(session),
+            checkpoints: Vec::new(),
+            turns: Vec::new(),
+            next_id: 1,
+        }
+    }
+
+    /// The turn new checkpoints belong to; 0 before the first user message.
+    pub fn turn(&self) -> usize {
+        self.turns.len()
+    }
+
+    pub fn begin_turn(&mut self, message: &str) {
+        self.turns.push(message.lines().next().unwrap_or_default().to_string());
+    }
+
+    /// Saves the current state of every path `changes` will touch. Must be
+    /// called before `changes.write()`.
+    pub fn record(&mut self, tool: &str, target: &str, changes: &Changes) -> Result<()> {
+        let id = self.next_id;
+        let dir = self.dir.join(id.to_string());
+        fs::create_dir_all(&dir).with_context(|| format!("cannot create checkpoint directory {}", dir.display()))?;
+        let mut snapshots: Vec<Snapshot> = Vec::new();
+        for path in changes.paths() {
+            let path = first_missing_ancestor(&path);
+            if snapshots.iter().any(|s| s.path == path) {
+                continue;
+            }
+            let blob = dir.join(snapshots.len().to_string());
+            let saved = save(&path, &blob).with_context(|| format!("cannot snapshot {}", path.display()))?;
+            snapshots.push(Snapshot { path, saved });
+        }
+        let checkpoint = Checkpoint {
+            id,
+            turn: self.turn(),
+            tool: tool.to_string(),
+            target: target.to_string(),
+            timestamp: chrono::Utc::now(),
+            snapshots,
+        };
+        fs::write(dir.join("manifest.json"), serde_json::to_string_pretty(&checkpoint)?)?;
+        self.checkpoints.push(checkpoint);
+        self.next_id += 1;
+        Ok(())
+    }
+
+    /// Reverts the last `count` checkpoints, newest first, and returns them.
+    pub fn undo(&mut self, count: usize) -> Result<Vec<Checkpoint>> {
+        let keep = self.checkpoints.len().saturating_sub(count);
+        self.restore_after(keep)
+    }
+
+    /// Restores the workspace to how it was before `turn` started.
+    pub fn restore_turn(&mut self, turn: usize) -> Result<Vec<Checkpoint>> {
+        let keep = self.checkpoints.iter().take_while(|c| c.turn < turn).count();
+        self.restore_after(keep)
+    }
+
+    /// Reverts every checkpoint from index `keep` onwards. Stops at the first
+    /// failure, leaving that checkpoint and the older ones in place.
+    fn restore_after(&mut self, keep: usize) -> Result<Vec<Checkpoint>> {
+        let mut restored = Vec::new();
+        while self.checkpoints.len() > keep {
+            let Some(checkpoint) = self.checkpoints.last().cloned() else {
+                break;
+            };
+            let dir = self.dir.join(checkpoint.id.to_string());
+            for (i, snapshot) in checkpoint.snapshots.iter().enumerate().rev() {
+                restore(&snapshot.path, snapshot.saved, &dir.join(i.to_string()))
+                    .with_context(|| format!("cannot restore {} (checkpoint #{})", snapshot.path.display(), checkpoint.id))?;
+            }
+            let _ = fs::remove_dir_all(&dir);
+            self.checkpoints.pop();
+            restored.push(checkpoint);
+        }
+        Ok(restored)
+    }
+}
+
+/// For a path that does not exist yet, the outermost parent directory that
+/// does not exist either, since writing the path will create it as well.
+fn first_missing_ancestor(path: &Path) -> PathBuf {
+    let mut missing = path.to_path_buf();
+    while let Some(parent) = missing.parent() {
+        if parent.as_os_str().is_empty() || fs::symlink_metadata(parent).is_ok() || fs::symlink_metadata(&missing).is_ok() {
+            break;
+        }
+        missing = parent.to_path_buf();
+    }
+    missing
+}
+
+fn save(path: &Path, blob: &Path) -> Result<Saved> {
+    match fs::symlink_metadata(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Saved::Missing),
+        Err(e) => Err(e.into()),
+        Ok(meta) if meta.is_dir() => {
+            copy_tree(path, blob)?;
+            Ok(Saved::Dir)
+        }
+        Ok(_) => {
+            copy_entry(path, blob)?;
+            Ok(Saved::File)
+        }
+    }
+}
+
+fn restore(path: &Path, saved: Saved, blob: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+    if saved == Saved::Missing {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match saved {
+        Saved::Dir => copy_tree(blob, path),
+        _ => copy_entry(blob, path),
+    }
+}
+
+/// Copies a file, keeping its permissions, or recreates a symlink.
+fn copy_entry(from: &Path, to: &Path) -> Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    Ok(())
+}
+
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    fs::set_permissions(to, fs::metadata(from)?.permissions())?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_tree(&entry.path(), &target)?;
+        } else {
+            copy_entry(&entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn prune_old_sessions(root: &Path) {
+    let Ok(entries) = fs::read_dir(root) else {
+        return;
+    };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let stale = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .is_ok_and(|modified| now.duration_since(modified).unwrap_or_default() > KEEP_SESSIONS_FOR);
+        if stale {
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{FileChange, FsOp};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("quill-test-{}-{}", name, uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_file(path: &Path, content: &str) -> Changes {
+        let before = fs::read_to_string(path).ok();
+        Changes {
+            ops: Vec::new(),
+            files: vec![FileChange { path: path.to_path_buf(), before, after: Some(content.to_string()) }],
+            summary: String::new(),
+        }
+    }
+
+    /// Records a checkpoint for `changes`, then applies them.
+    fn apply(checkpoints: &mut Checkpoints, changes: &Changes) {
+        checkpoints.record("test", "", changes).unwrap();
+        changes.write().unwrap();
+    }
+
+    #[test]
+    fn restores_a_modified_file() {
+        let dir = temp_dir("checkpoint-file");
+        let file = dir.join("notes.txt");
+        fs::write(&file, "before").unwrap();
+        let mut checkpoints = Checkpoints::new(&dir.join("data"));
+
+        apply(&mut checkpoints, &write_file(&file, "after"));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "after");
+        checkpoints.undo(1).unwrap();
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), "before");
+        assert!(checkpoints.checkpoints.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restores_a_deleted_directory_tree() {
+        let dir = temp_dir("checkpoint-tree");
+        let tree = dir.join("src");
+        fs::create_dir_all(tree.join("nested")).unwrap();
+        fs::write(tree.join("main.rs"), "fn main() {}").unwrap();
+        fs::write(tree.join("nested").join("lib.rs"), "pub fn f() {}").unwrap();
+        let mut checkpoints = Checkpoints::new(&dir.join("data"));
+
+        let changes = Changes {
+            ops: vec![FsOp::Remove { path: tree.clone(), is_dir: true }],
+            files: Vec::new(),
+            summary: String::new(),
+        };
+        apply(&mut checkpoints, &changes);
+        assert!(!tree.exists());
+        checkpoints.undo(1).unwrap();
+
+        assert_eq!(fs::read_to_string(tree.join("main.rs")).unwrap(), "fn main() {}");
+        assert_eq!(fs::read_to_string(tree.join("nested").join("lib.rs")).unwrap(), "pub fn f() {}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restoring_removes_paths_that_were_missing() {
+        let dir = temp_dir("checkpoint-missing");
+        let file = dir.join("new").join("deeper").join("notes.txt");
+        let mut checkpoints = Checkpoints::new(&dir.join("data"));
+
+        apply(&mut checkpoints, &write_file(&file, "created"));
+        assert!(file.exists());
+        let restored = checkpoints.undo(1).unwrap();
+
+        assert_eq!(restored[0].snapshots[0].path, dir.join("new"));
+        assert_eq!(restored[0].snapshots[0].saved, Saved::Missing);
+        assert!(!dir.join("new").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn undo_reverts_the_newest_checkpoints_first() {
+        let dir = temp_dir("checkpoint-undo");
+        let file = dir.join("notes.txt");
+        fs::write(&file, "v1").unwrap();
+        let mut checkpoints = Checkpoints::new(&dir.join("data"));
+        for version in ["v2", "v3", "v4"] {
+            apply(&mut checkpoints, &write_file(&file, version));
+        }
+
+        let restored = checkpoints.undo(2).unwrap();
+
+        assert_eq!(restored.iter().map(|c| c.id).collect::<Vec<_>>(), [3, 2]);
+        assert_eq!(fs::read_to_string(&file).unwrap(), "v2");
+        assert_eq!(checkpoints.checkpoints.iter().map(|c| c.id).collect::<Vec<_>>(), [1]);
+        checkpoints.undo(5).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "v1");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+//! Slash commands: typing `/name arguments` runs a command instead of sending
+//! the text to the model.
+//!
+//! Besides the built-in commands, users can add prompt templates as Markdown
+//! files named after the command, in `~/.config/quill/commands` or in the
+//! project's `.quill/commands` (which wins). `/review src/app.rs` with a
+//! `review.md` template sends the file's text with `$ARGUMENTS` replaced by
+//! `src/app.rs`, or with the arguments appended if it has no placeholder.
+
+use crate::logging;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Built-in commands: name, arguments and description.
+const BUILTIN: &[(&str, &str, &str)] = &[
+    ("/help", "", "List the commands"),
+    ("/model", "[NAME]", "Show the models, or switch to NAME"),
+    ("/clear", "", "Forget the conversation so far"),
+    ("/new", "", "Start a new session; the current one stays saved"),
+    ("/save", "[NAME]", "Save the session now, optionally naming it"),
+    ("/load", "[ID]", "List saved sessions, or continue one"),
+    ("/export", "[FILE]", "Write the conversation to a Markdown file"),
+    ("/undo", "[N]", "Revert the last N tool edits"),
+    ("/checkpoints", "[TURN]", "List tool edits, or restore the files to before TURN"),
+    ("/system", "[TEXT]", "Show the system prompt, or replace it"),
+    ("/options", "[KEY [VALUE]]", "Show, set or clear model options such as temperature"),
+    ("/tokens", "", "Show how many tokens the conversation uses"),
+    ("/compact", "[FOCUS]", "Replace the conversation with a summary to free up context"),
+    ("/remember", "[-g] TEXT", "Save a fact for later sessions (-g: for every project)"),
+    ("/memory", "", "Manage the saved memories"),
+    ("/forget", "ID", "Delete a memory"),
+    ("/index", "", "Show the code index and look for changed files"),
+    ("/agent", "[on|off]", "Show or switch agent mode"),
+    ("/plan", "TASK", "Draft a plan for TASK and carry it out step by step"),
+    ("/audit", "[N]", "Show the last N tool calls from the audit log"),
+    ("/quit", "", "Exit Quill"),
+];
+
+/// A command as offered in the completion popup and `/help`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlashCommand {
+    pub name: String,
+    pub args: String,
+    pub description: String,
+}
+
+/// A user-defined command that expands to a prompt.
+#[derive(Debug, Clone)]
+pub struct Template {
+    /// With the leading `/`.
+    pub name: String,
+    /// The first line of the file.
+    pub description: String,
+    pub text: String,
+    pub path: PathBuf,
+}
+
+impl Template {
+    /// The message to send for `/name arguments`.
+    pub fn expand(&self, arguments: &str) -> String {
+        if self.text.contains("$ARGUMENTS") {
+            self.text.replace("$ARGUMENTS", arguments)
+        } else if arguments.is_empty() {
+            self.text.clone()
+        } else {
+            format!("{}\n\n{}", self.text.trim_end(), arguments)
+        }
+    }
+}
+
+/// Splits `/name arguments` into the name and the rest. Text whose first word
+/// only looks like a path, such as `/etc/hosts`, is not a command.
+pub fn parse(input: &str) -> Option<(&str, &str)> {
+    let input = input.trim();
+    let (name, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+    let valid = name.len() > 1 && name.starts_with('/') && !name[1..].contains('/');
+    valid.then(|| (name, rest.trim()))
+}
+
+pub fn is_builtin(name: &str) -> bool {
+    BUILTIN.iter().any(|(builtin, _, _)| *builtin == name)
+}
+
+/// Built-in commands and templates whose name starts with `prefix`.
+pub fn matching(prefix: &str, templates: &[Template]) -> Vec<SlashCommand> {
+    let builtin = BUILTIN.iter().map(|(name, args, description)| SlashCommand {
+        name: name.to_string(),
+        args: args.to_string(),
+        description: description.to_string(),
+    });
+    let templates = templates.iter().map(|template| SlashCommand {
+        name: template.name.clone(),
+        args: "[ARGS]".to_string(),
+        description: template.description.clone(),
+    });
+    builtin.chain(templates).filter(|command| command.name.starts_with(prefix)).collect()
+}
+
+/// Templates from the user's command directory and the project's. Files
+/// named after a built-in command are skipped.
+pub fn load_templates(working_directory: &Path) -> Vec<Template> {
+    let user_dir = dirs::config_dir().map(|dir| dir.join("quill").join("commands"));
+    load_templates_from(user_dir.as_deref(), working_directory)
+}
+
+/// Like `load_templates`, with the user's templates in `user_dir`.
+pub fn load_templates_from(user_dir: Option<&Path>, working_directory: &Path) -> Vec<Template> {
+    let dirs = [user_dir.map(Path::to_path_buf), Some(working_directory.join(".quill").join("commands"))];
+    let mut templates: Vec<Template> = Vec::new();
+    for dir in dirs.into_iter().flatten() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "md"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let name = format!("/{}", stem);
+            if is_builtin(&name) || stem.contains(char::is_whitespace) {
+                logging::warn("commands", "skipped a template", json!({ "path": path.display().to_string() }));
+                continue;
+            }
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => {
+                    logging::warn("commands", "cannot read a template", json!({ "path": path.display().to_string(), "error": e.to_string() }));
+                    continue;
+                }
+            };
+            let description = text
+                .lines()
+                .map(|line| line.trim_start_matches('#').trim())
+                .find(|line| !line.is_empty())
+                .map(|line| line.chars().take(60).collect())
+                .unwrap_or_default();
+            // A project template replaces the user's one of the same name
+            templates.retain(|template| template.name != name);
+            templates.push(Template { name, description, text, path });
+        }
+    }
+    templates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template(text: &str) -> Template {
+        Template { name: "/review".to_string(), description: String::new(), text: text.to_string(), path: PathBuf::new() }
+    }
+
+    #[test]
+    fn parse_splits_the_name_from_the_arguments() {
+        assert_eq!(parse("/help"), Some(("/help", "")));
+        assert_eq!(parse("  /review   src/app.rs  now "), Some(("/review", "src/app.rs  now")));
+        assert_eq!(parse("/etc/hosts"), None);
+        assert_eq!(parse("/etc/hosts is broken"), None);
+        assert_eq!(parse("/"), None);
+        assert_eq!(parse("hello /help"), None);
+    }
+
+    #[test]
+    fn expand_substitutes_or_appends_the_arguments() {
+        assert_eq!(template("Review $ARGUMENTS, then $ARGUMENTS again").expand("a.rs"), "Review a.rs, then a.rs again");
+        assert_eq!(template("Review $ARGUMENTS").expand(""), "Review ");
+        assert_eq!(template("Review this.\n").expand("a.rs"), "Review this.\n\na.rs");
+        assert_eq!(template("Review this.\n").expand(""), "Review this.\n");
+    }
+
+    #[test]
+    fn project_templates_override_the_users_and_builtins_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("quill-test-templates-{}", uuid::Uuid::new_v4().simple()));
+        let user = dir.join("user");
+        let project = dir.join("project");
+        let project_commands = project.join(".quill").join("commands");
+        fs::create_dir_all(&user).unwrap();
+        fs::create_dir_all(&project_commands).unwrap();
+        fs::write(user.join("review.md"), "# Review from the user\n").unwrap();
+        fs::write(user.join("explain.md"), "\n## Explain the code\n$ARGUMENTS").unwrap();
+        fs::write(user.join("help.md"), "Not the built-in help").unwrap();
+        fs::write(user.join("notes.txt"), "Not a template").unwrap();
+        fs::write(project_commands.join("review.md"), "Review from the project").unwrap();
+
+        let templates = load_templates_from(Some(&user), &project);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let loaded: Vec<(&str, &str)> = templates.iter().map(|t| (t.name.as_str(), t.description.as_str())).collect();
+        assert_eq!(loaded, [("/explain", "Explain the code"), ("/review", "Review from the project")]);
+        assert_eq!(templates[1].path, project_commands.join("review.md"));
+    }
+}
@@ -0,0 +1,362 @@
+//! User configuration, read from `~/.config/quill/config.json` (or the file
+//! named by `QUILL_CONFIG`).
+//!
+//! ```json
+//! {
+//!   "agent": { "enabled": true, "max_steps": 25, "max_tokens": 200000 },
+//!   "options": { "temperature": 0.2, "num_ctx": 16384 },
+//!   "index": { "enabled": true, "model": "nomic-embed-text", "retrieve": 3 },
+//!   "mcp_servers": {
+//!     "docs": { "command": "docs-mcp", "args": ["--stdio"], "env": { "DOCS_TOKEN": "..." } }
+//!   },
+//!   "permissions": {
+//!     "default": "ask",
+//!     "tools": { "read_directory": "allow", "glob": "allow" },
+//!     "rules": [
+//!       { "path": ".git/**", "action": "deny" },
+//!       { "tool": "read_file", "path": "src/**", "action": "allow" }
+//!     ]
+//!   }
+//! }
+//! ```
+
+use crate::mcp::ServerConfig;
+use crate::tools::{normalize, ToolCall};
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub agent: AgentSettings,
+    pub index: IndexSettings,
+    /// Model options sent with every request, such as `temperature`.
+    pub options: serde_json::Map<String, serde_json::Value>,
+    /// MCP servers started with Quill, by name. Their tools are called as `name.tool`.
+    pub mcp_servers: HashMap<String, ServerConfig>,
+    pub permissions: Permissions,
+}
+
+/// Limits for agent mode, where allowed tool calls run without asking and
+/// the model keeps going until it answers without calling a tool.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgentSettings {
+    /// When off, calls the permissions allow still wait for approval.
+    pub enabled: bool,
+    /// Tool calls run per user message before Quill asks again.
+    pub max_steps: usize,
+    /// Tokens (prompt and reply) used per user message before Quill asks again.
+    pub max_tokens: Option<u64>,
+}
+
+impl Default for AgentSettings {
+    fn default() -> Self {
+        AgentSettings {
+            enabled: true,
+            max_steps: 25,
+            max_tokens: None,
+        }
+    }
+}
+
+/// The embeddings index used by `semantic_search`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IndexSettings {
+    /// When off, no index is built and `semantic_search` is unavailable.
+    pub enabled: bool,
+    /// Ollama embedding model.
+    pub model: String,
+    /// Chunks of related code added to each message; 0 turns this off.
+    pub retrieve: usize,
+}
+
+impl Default for IndexSettings {
+    fn default() -> Self {
+        IndexSettings {
+            enabled: true,
+            model: "nomic-embed-text".to_string(),
+            retrieve: 3,
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        match std::env::var_os("QUILL_CONFIG") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => dirs::config_dir().map(|dir| dir.join("quill").join("config.json")),
+        }
+    }
+
+    /// Loads the config file. A missing file gives the defaults.
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Config::default());
+        };
+        match fs::metadata(&path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            _ => Self::load_from(&path),
+        }
+    }
+
+    /// Loads a config file that must exist.
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+        let mut config: Config = serde_json::from_str(&text).with_context(|| format!("invalid config {}", path.display()))?;
+        config.permissions.compile()?;
+        Ok(config)
+    }
+}
+
+/// What happens to a tool call before it runs. Ordered from least to most
+/// restrictive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// Run without asking.
+    Allow,
+    /// Show the call and wait for the user to accept or deny it.
+    #[default]
+    Ask,
+    /// Refuse the call and tell the model.
+    Deny,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Tool name the rule applies to; all tools if omitted. A trailing `*`
+    /// matches by prefix, e.g. `docs.*` for every tool of one MCP server.
+    pub tool: Option<String>,
+    /// Glob matched against each path the call touches, relative to the
+    /// working directory. `*` stays within one directory, `**` crosses them.
+    pub path: Option<String>,
+    pub action: Action,
+}
+
+impl Rule {
+    fn describe(&self) -> String {
+        match (&self.tool, &self.path) {
+            (Some(tool), Some(path)) => format!("rule {} {}", tool, path),
+            (Some(tool), None) => format!("rule {}", tool),
+            (None, Some(path)) => format!("rule {}", path),
+            (None, None) => "catch-all rule".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Permissions {
+    /// Used when no rule or tool entry matches.
+    pub default: Action,
+    /// Per-tool actions, by tool name.
+    pub tools: HashMap<String, Action>,
+    /// Checked in order before `tools`; the first matching rule wins.
+    pub rules: Vec<Rule>,
+    /// Compiled `path` globs of `rules`, filled in by `compile`.
+    #[serde(skip)]
+    matchers: Vec<Option<GlobMatcher>>,
+}
+
+/// The outcome for one call and the setting that produced it, for display.
+#[derive(Debug, Clone)]
+pub struct Decision {
+    pub action: Action,
+    pub reason: String,
+}
+
+impl Permissions {
+    fn compile(&mut self) -> Result<()> {
+        self.matchers = self
+            .rules
+            .iter()
+            .map(|rule| {
+                rule.path
+                    .as_deref()
+                    .map(|pattern| {
+                        GlobBuilder::new(pattern)
+                            .literal_separator(true)
+                            .build()
+                            .map(|g| g.compile_matcher())
+                            .with_context(|| format!("invalid path glob `{}` in permission rules", pattern))
+                    })
+                    .transpose()
+            })
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+
+    /// Decides what to do with `tool_call`. Calls touching several paths get
+    /// the most restrictive outcome across them. Tools in `session_allowed`
+    /// were allowed by the user for this session; rules still apply to them.
+    pub fn decide(&self, tool_call: &ToolCall, working_directory: &Path, session_allowed: &HashSet<String>) -> Decision {
+        let name = tool_call.name();
+        let decide_path = |path: Option<&str>| -> Decision {
+            for (rule, matcher) in self.rules.iter().zip(&self.matchers) {
+                if rule.tool.as_deref().is_some_and(|tool| !tool_matches(tool, name)) {
+                    continue;
+                }
+                let matches_path = match (matcher, path) {
+                    (None, _) => true,
+                    (Some(matcher), Some(path)) => matcher.is_match(path),
+                    (Some(_), None) => false,
+                };
+                if matches_path {
+                    return Decision { action: rule.action, reason: rule.describe() };
+                }
+            }
+            if session_allowed.contains(name) {
+                return Decision { action: Action::Allow, reason: "allowed for this session".to_string() };
+            }
+            match self.tools.get(name) {
+                Some(action) => Decision { action: *action, reason: format!("tools.{}", name) },
+                None => Decision { action: self.default, reason: "default".to_string() },
+            }
+        };
+        let paths = tool_call.paths();
+        if paths.is_empty() {
+            return decide_path(None);
+        }
+        paths
+            .iter()
+            .map(|path| decide_path(Some(&relative_path(working_directory, path))))
+            .max_by_key(|decision| decision.action)
+            .unwrap_or_else(|| decide_path(None))
+    }
+}
+
+fn tool_matches(pattern: &str, name: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => pattern == name,
+    }
+}
+
+/// `path` relative to the working directory, or absolute if it is outside.
+fn relative_path(working_directory: &Path, path: &str) -> String {
+    let path = normalize(&working_directory.join(path));
+    match path.strip_prefix(working_directory) {
+        Ok(rel) if rel.as_os_str().is_empty() => ".".to_string(),
+        Ok(rel) => rel.display().to_string(),
+        Err(_) => path.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn permissions(value: serde_json::Value) -> Permissions {
+        let mut permissions: Permissions = serde_json::from_value(value).unwrap();
+        permissions.compile().unwrap();
+        permissions
+    }
+
+    fn read(path: &str) -> ToolCall {
+        ToolCall::ReadFile { path: path.to_string(), start_line: None, end_line: None }
+    }
+
+    fn decide(permissions: &Permissions, tool_call: &ToolCall) -> (Action, String) {
+        let decision = permissions.decide(tool_call, Path::new("/workspace"), &HashSet::new());
+        (decision.action, decision.reason)
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let permissions = permissions(json!({
+            "rules": [
+                { "path": "src/secret.rs", "action": "deny" },
+                { "path": "src/**", "action": "allow" },
+                { "path": "src/secret.rs", "action": "allow" }
+            ]
+        }));
+        assert_eq!(decide(&permissions, &read("src/secret.rs")), (Action::Deny, "rule src/secret.rs".to_string()));
+        assert_eq!(decide(&permissions, &read("src/main.rs")), (Action::Allow, "rule src/**".to_string()));
+        assert_eq!(decide(&permissions, &read("README.md")), (Action::Ask, "default".to_string()));
+    }
+
+    #[test]
+    fn rules_and_tools_are_checked_before_the_default() {
+        let permissions = permissions(json!({
+            "default": "deny",
+            "tools": { "read_file": "allow" },
+            "rules": [{ "tool": "read_file", "path": "*.env", "action": "ask" }]
+        }));
+        assert_eq!(decide(&permissions, &read(".env")), (Action::Ask, "rule read_file *.env".to_string()));
+        assert_eq!(decide(&permissions, &read("src/main.rs")), (Action::Allow, "tools.read_file".to_string()));
+        let list = ToolCall::ReadDirectory { path: ".".to_string() };
+        assert_eq!(decide(&permissions, &list), (Action::Deny, "default".to_string()));
+    }
+
+    #[test]
+    fn tool_patterns_match_by_prefix() {
+        let permissions = permissions(json!({
+            "rules": [
+                { "tool": "docs.*", "action": "allow" },
+                { "tool": "read_*", "path": "src/*", "action": "allow" }
+            ]
+        }));
+        let mcp = |name: &str| ToolCall::Mcp { name: name.to_string(), arguments: serde_json::Map::new() };
+        assert_eq!(decide(&permissions, &mcp("docs.search")).0, Action::Allow);
+        assert_eq!(decide(&permissions, &mcp("docsearch.search")).0, Action::Ask);
+        assert_eq!(decide(&permissions, &read("src/main.rs")).0, Action::Allow);
+        assert_eq!(decide(&permissions, &ToolCall::ReadDirectory { path: "src/bin".to_string() }).0, Action::Allow);
+        // `*` in a path stays within one directory
+        assert_eq!(decide(&permissions, &read("src/bin/main.rs")).0, Action::Ask);
+    }
+
+    #[test]
+    fn calls_on_several_paths_take_the_most_restrictive_action() {
+        let permissions = permissions(json!({
+            "default": "allow",
+            "rules": [
+                { "path": "docs/**", "action": "ask" },
+                { "path": ".git/**", "action": "deny" }
+            ]
+        }));
+        let move_path = |from: &str, to: &str| ToolCall::MovePath { from: from.to_string(), to: to.to_string(), overwrite: false };
+        assert_eq!(decide(&permissions, &move_path("a.md", "docs/a.md")), (Action::Ask, "rule docs/**".to_string()));
+        assert_eq!(decide(&permissions, &move_path("docs/a.md", ".git/a.md")), (Action::Deny, "rule .git/**".to_string()));
+        assert_eq!(decide(&permissions, &move_path("a.md", "b.md")).0, Action::Allow);
+    }
+
+    #[test]
+    fn paths_outside_the_workspace_stay_absolute() {
+        assert_eq!(relative_path(Path::new("/workspace"), "src/../src/main.rs"), "src/main.rs");
+        assert_eq!(relative_path(Path::new("/workspace"), "."), ".");
+        assert_eq!(relative_path(Path::new("/workspace"), "../etc/passwd"), "/etc/passwd");
+        assert_eq!(relative_path(Path::new("/workspace"), "/etc/passwd"), "/etc/passwd");
+        let absolute = permissions(json!({
+            "default": "allow",
+            "rules": [{ "path": "/etc/**", "action": "deny" }]
+        }));
+        assert_eq!(decide(&absolute, &read("../etc/passwd")).0, Action::Deny);
+        // A workspace-relative glob does not match the same name outside it
+        let relative = permissions(json!({
+            "default": "allow",
+            "rules": [{ "path": "etc/**", "action": "deny" }]
+        }));
+        assert_eq!(decide(&relative, &read("../etc/passwd")).0, Action::Allow);
+    }
+
+    #[test]
+    fn session_allowed_tools_do_not_override_rules() {
+        let permissions = permissions(json!({
+            "tools": { "read_file": "deny" },
+            "rules": [{ "path": ".env", "action": "deny" }]
+        }));
+        let session_allowed = HashSet::from(["read_file".to_string()]);
+        let decide = |path: &str| permissions.decide(&read(path), Path::new("/workspace"), &session_allowed);
+        assert_eq!(decide(".env").action, Action::Deny);
+        let decision = decide("src/main.rs");
+        assert_eq!((decision.action, decision.reason.as_str()), (Action::Allow, "allowed for this session"));
+    }
+}
@@ -0,0 +1,713 @@
+//! A conversation with a model that can call tools: streaming replies, tool
+//! calls and their approval, agent runs, plan mode, checkpoints and saved
+//! sessions. Frontends drive it with `submit`, `poll` and the pending-call
+//! methods, and follow along through `messages` or `subscribe`.
+
+mod approval;
+mod retrieval;
+mod slash;
+
+use crate::audit::{self, AuditLog};
+use crate::checkpoint::Checkpoints;
+use crate::commands::{self, Template};
+use crate::config::Config;
+use crate::events::Event;
+use crate::index::{self, Index, SharedIndex};
+use crate::logging;
+use crate::mcp::McpServers;
+use crate::memory::{Author, MemoryStore};
+use crate::mentions;
+use crate::ollama::{ChatChunk, OllamaClient, Model};
+use crate::plan::{self, Phase, Plan, StepState};
+use crate::session::Session;
+use crate::shell::RunningCommand;
+use crate::tools::{self, Risk, ToolCall, ToolOutput};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use futures::StreamExt;
+use std::pin::Pin;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::mpsc;
+use serde_json::{json, Map, Value};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    User {
+        content: String,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    Assistant {
+        content: String,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    PendingToolCall {
+        tool_call: ToolCall,
+        original_message: String,
+        /// Diff of the files a writing tool will change, shown before approval.
+        preview: Option<String>,
+        risk: Risk,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    ToolCallResult {
+        result: String,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    ToolCallDenied {
+        tool_call: ToolCall,
+        original_message: String,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// One tool call run during an agent run, shown as a single line.
+    AgentStep {
+        number: usize,
+        tool: String,
+        target: String,
+        /// Why the call ran without asking, if it did.
+        auto: Option<String>,
+        status: StepStatus,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+    /// Output of a command such as `/undo`, shown in the chat but not sent to the model.
+    Info {
+        content: String,
+        timestamp: chrono::DateTime<chrono::Utc>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StepStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+/// Progress of the work started by one user message: every tool call the
+/// model makes until it answers without one.
+#[derive(Debug, Clone, Default)]
+pub struct AgentRun {
+    pub steps: usize,
+    /// Prompt and reply tokens reported by Ollama.
+    pub tokens: u64,
+    /// Set when the user presses Esc; nothing else runs without approval.
+    pub stopped: bool,
+}
+
+/// Tokens Ollama reported over the whole session, for `/tokens`.
+#[derive(Debug, Clone, Default)]
+pub struct TokenUsage {
+    /// The prompt of the last reply: roughly how much of the context window
+    /// the conversation fills.
+    pub last_prompt: u64,
+    pub prompt: u64,
+    pub reply: u64,
+    pub replies: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatTurn {
+    pub role: String, // "user" or "assistant"
+    pub content: String,
+}
+
+/// What a conversation starts with.
+pub struct Options {
+    pub client: OllamaClient,
+    pub system_prompt: String,
+    /// Model to use; defaults to the one the resumed session used, then to
+    /// the first one the client lists.
+    pub model: Option<String>,
+    pub config: Config,
+    /// Whether tool calls in replies are picked up and MCP servers started.
+    pub tools: bool,
+    pub working_directory: PathBuf,
+    /// Where sessions, memories, checkpoints, the audit log and the index
+    /// are kept; usually `utils::data_dir()`.
+    pub data_dir: PathBuf,
+    /// A saved session to continue.
+    pub resume: Option<Session>,
+}
+
+pub struct Conversation {
+    pub models: Vec<Model>,
+    pub selected_model_index: usize,
+    pub messages: VecDeque<Message>,
+    pub ollama_client: OllamaClient,
+    pub is_loading: bool,
+    pub error_message: Option<String>,
+    pub streaming_message: Option<String>, // For in-progress assistant message
+    pub stream: Option<Pin<Box<dyn futures::Stream<Item = Result<String>> + Send>>>,
+    /// The end of the last chunk when it stopped partway through a line.
+    stream_buffer: String,
+    pub working_directory: String,
+    /// Where sessions, memories, checkpoints, the audit log and the index are kept.
+    pub data_dir: PathBuf,
+    pub system_prompt: String,
+    /// Long-term memories of this project and global ones.
+    pub memory: MemoryStore,
+    /// IDs of the memories already added to a message in this conversation.
+    recalled: HashSet<String>,
+    /// Embeddings of the working directory's files, updated in the
+    /// background. `None` when tools or the index are turned off.
+    pub index: Option<SharedIndex>,
+    pub chat_history: Vec<ChatTurn>,
+    /// An accepted `run_command` call whose output is streamed into the chat.
+    pub running_command: Option<RunningCommand>,
+    /// Snapshots taken before each accepted write, for `/undo` and `/checkpoints`.
+    pub checkpoints: Checkpoints,
+    pub config: Config,
+    /// Tools the user allowed for the rest of the session from the approval prompt.
+    pub session_allowed: HashSet<String>,
+    pub run: AgentRun,
+    /// The plan being drafted, reviewed or carried out in plan mode.
+    pub plan: Option<Plan>,
+    /// Connected MCP servers whose tools are offered to the model.
+    pub mcp: McpServers,
+    pub audit: AuditLog,
+    /// Audit entry for the running command, written when it exits.
+    command_audit: Option<audit::Entry>,
+    /// When the reply being streamed was requested.
+    reply_started: Option<Instant>,
+    /// Whether tool calls in replies are picked up (off with `--no-tools`).
+    pub tools_enabled: bool,
+    /// Saved after every message so it can be resumed.
+    pub session: Session,
+    /// Set once a frontend subscribes to events.
+    events: Option<mpsc::UnboundedSender<Event>>,
+    /// Text a command wants put back in the input box for the user to edit,
+    /// such as a plan step from `/plan edit N`. Frontends take it after `submit`.
+    pub draft: Option<String>,
+    /// User-defined slash commands.
+    pub templates: Vec<Template>,
+    /// Model options sent with every request; `/options` changes them.
+    pub options: Map<String, Value>,
+    pub usage: TokenUsage,
+    /// Set while the model writes the summary asked for by `/compact`.
+    compacting: bool,
+}
+
+impl Conversation {
+    /// Starts a new conversation, or continues `options.resume` if given.
+    pub async fn new(options: Options) -> Result<Self> {
+        let Options {
+            client: ollama_client,
+            system_prompt,
+            model,
+            config,
+            tools: tools_enabled,
+            working_directory: cwd,
+            data_dir,
+            resume,
+        } = options;
+        let models = ollama_client.list_models().await.unwrap_or_else(|_| {
+            vec![Model {
+                name: "llama2".to_string(),
+                modified_at: chrono::Utc::now(),
+                size: 0,
+            }]
+        });
+        let mut messages = VecDeque::new();
+        let mcp = if tools_enabled {
+            let (mcp, errors) = McpServers::start(&config.mcp_servers, &cwd).await;
+            for error in errors {
+                messages.push_back(Message::Info {
+                    content: error,
+                    timestamp: chrono::Utc::now(),
+                });
+            }
+            mcp
+        } else {
+            McpServers::default()
+        };
+        let system_prompt = system_prompt + &mcp.prompt_section();
+        let code_index = (tools_enabled && config.index.enabled).then(|| {
+            let shared = Arc::new(Mutex::new(Index::load(&data_dir, &cwd, &config.index.model)));
+            retrieval::update_in_background(&shared, &ollama_client);
+            if let Err(e) = index::watch(&shared, &ollama_client) {
+                logging::warn("index", "changed files are only indexed by /index", json!({ "error": format!("{:#}", e) }));
+            }
+            shared
+        });
+        let chat_history = vec![ChatTurn { role: "system".to_string(), content: system_prompt.clone() }];
+        let mut conversation = Conversation {
+            models,
+            selected_model_index: 0,
+            messages,
+            ollama_client,
+            is_loading: false,
+            error_message: None,
+            streaming_message: None,
+            stream: None,
+            stream_buffer: String::new(),
+            working_directory: cwd.display().to_string(),
+            data_dir: data_dir.clone(),
+            system_prompt: system_prompt.clone(),
+            memory: MemoryStore::load(&data_dir, &cwd.display().to_string()),
+            recalled: HashSet::new(),
+            index: code_index,
+            chat_history,
+            running_command: None,
+            checkpoints: Checkpoints::new(&data_dir),
+            session_allowed: HashSet::new(),
+            run: AgentRun::default(),
+            plan: None,
+            mcp,
+            audit: AuditLog::new(&data_dir),
+            command_audit: None,
+            reply_started: None,
+            tools_enabled,
+            session: Session::new(&cwd.display().to_string()),
+            events: None,
+            draft: None,
+            templates: commands::load_templates(&cwd),
+            options: config.options.clone(),
+            usage: TokenUsage::default(),
+            compacting: false,
+            config,
+        };
+        if let Some(session) = resume {
+            conversation.resume_session(session);
+        }
+        // The model asked for wins over the model the session used
+        if let Some(name) = model {
+            conversation.select_model(name);
+        }
+        Ok(conversation)
+    }
+
+    /// Switches to the model called `name`, adding it to the list if the
+    /// client did not list it.
+    fn select_model(&mut self, name: String) {
+        self.selected_model_index = match self.models.iter().position(|m| m.name == name) {
+            Some(index) => index,
+            None => {
+                self.models.push(Model { name, modified_at: chrono::Utc::now(), size: 0 });
+                self.models.len() - 1
+            }
+        };
+    }
+
+    /// Replaces the conversation with a saved session.
+    fn resume_session(&mut self, session: Session) {
+        self.chat_history.truncate(1);
+        self.chat_history.extend(session.turns.iter().cloned());
+        self.messages = session.messages.iter().cloned().collect();
+        if !session.model.is_empty() {
+            self.select_model(session.model.clone());
+        }
+        self.run = AgentRun::default();
+        self.plan = None;
+        self.recalled.clear();
+        let info = format!("Resumed session {} ({} messages).", session.id, session.turns.len());
+        self.session = session;
+        self.push_info(info);
+    }
+
+    /// Starts sending an `Event` for everything that happens from now on.
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<Event> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.events = Some(tx);
+        rx
+    }
+
+    fn emit(&self, event: Event) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    // After each user/assistant message, push to chat_history
+    fn add_user_message(&mut self, content: &str) {
+        self.chat_history.push(ChatTurn { role: "user".to_string(), content: content.to_string() });
+    }
+    fn add_assistant_message(&mut self, content: &str) {
+        self.chat_history.push(ChatTurn { role: "assistant".to_string(), content: content.to_string() });
+    }
+
+    // Build the messages array for the API call
+    fn build_messages(&self, user_message: &str) -> Vec<ChatTurn> {
+        let mut messages = self.chat_history.clone();
+        if !user_message.is_empty() {
+            messages.push(ChatTurn { role: "user".to_string(), content: user_message.to_string() });
+        }
+        messages
+    }
+
+    /// Cancels the running command or stops the reply being generated, and
+    /// pauses the agent run.
+    pub fn stop(&mut self) {
+        if let Some(running) = self.running_command.as_mut() {
+            running.cancel();
+        } else if self.is_loading {
+            logging::info("app", "reply stopped with Esc", serde_json::Value::Null);
+            self.stream = None;
+            self.finish_streaming();
+        } else {
+            return;
+        }
+        self.run.stopped = true;
+        self.pause_plan("stopped with Esc");
+    }
+
+    /// Whether a reply is streaming or a command is running.
+    pub fn is_busy(&self) -> bool {
+        self.is_loading || self.running_command.is_some()
+    }
+
+    /// Moves the reply being streamed or the running command along. Call it
+    /// in a loop while `is_busy`; each call waits at most 10ms for a reply.
+    pub async fn poll(&mut self) -> Result<()> {
+        if self.is_loading {
+            self.process_streaming().await?;
+        }
+        if self.running_command.is_some() {
+            self.process_command().await?;
+        }
+        Ok(())
+    }
+
+    /// Runs `text` if it is a slash command, otherwise sends it to the model.
+    pub async fn submit(&mut self, text: &str) -> Result<()> {
+        match commands::parse(text) {
+            Some((name, arguments)) => self.run_slash_command(name, arguments).await,
+            None => self.send_with_mentions(text.to_string(), text, text).await,
+        }
+    }
+
+    /// Sends `prompt` with the files mentioned in `typed`, the memories
+    /// relevant to it and related code from the index attached, showing
+    /// `shown`. Only what the user typed is searched for mentions, so a
+    /// template cannot attach files on its own.
+    async fn send_with_mentions(&mut self, shown: String, prompt: &str, typed: &str) -> Result<()> {
+        let working_directory = PathBuf::from(&self.working_directory);
+        let (attachments, mut notes) = mentions::attach(typed, &working_directory, &self.config, &self.session_allowed);
+        let mut sent = format!("{}{}", prompt, attachments);
+        if let Some((memories, note)) = self.recall(prompt) {
+            sent.push_str(&memories);
+            notes.push(note);
+        }
+        if let Some((code, note)) = self.retrieve(prompt).await {
+            sent.push_str(&code);
+            notes.push(note);
+        }
+        self.start_message_sending(shown, sent).await?;
+        for note in notes {
+            self.push_info(note);
+        }
+        Ok(())
+    }
+
+    /// Sends a message from the user. `shown` is what the chat shows and
+    /// `sent` what the model gets; they differ for prompt templates.
+    async fn start_message_sending(&mut self, shown: String, sent: String) -> Result<()> {
+        self.add_user_message(&sent);
+        self.checkpoints.begin_turn(&shown);
+        self.run = AgentRun::default();
+        self.messages.push_back(Message::User {
+            content: shown,
+            timestamp: chrono::Utc::now(),
+        });
+
+        if self.messages.len() > 50 {
+            self.messages.pop_front();
+        }
+
+        self.is_loading = true;
+        self.error_message = None;
+        self.streaming_message = Some(String::new());
+
+        self.reply_started = Some(Instant::now());
+        let selected_model = &self.models[self.selected_model_index];
+        let messages = self.build_messages("");
+        match self.ollama_client.chat_stream(selected_model.name.clone(), messages, self.options.clone()).await {
+            Ok(stream) => {
+                self.stream = Some(stream);
+            }
+            Err(e) => {
+                self.emit(Event::Error { message: format!("{:#}", e) });
+                self.error_message = Some(format!("Error: {}", e));
+                self.is_loading = false;
+                self.streaming_message = None;
+            }
+        }
+        Ok(())
+    }
+
+    async fn start_message_sending_with_content(&mut self, content: String) -> Result<()> {
+        let user_message = content;
+        self.add_user_message(&user_message);
+        if self.messages.len() > 50 {
+            self.messages.pop_front();
+        }
+
+        self.is_loading = true;
+        self.error_message = None;
+        self.streaming_message = Some(String::new());
+
+        self.reply_started = Some(Instant::now());
+        let selected_model = &self.models[self.selected_model_index];
+        let messages = self.build_messages("");
+        match self.ollama_client.chat_stream(selected_model.name.clone(), messages, self.options.clone()).await {
+            Ok(stream) => {
+                self.stream = Some(stream);
+            }
+            Err(e) => {
+                self.emit(Event::Error { message: format!("{:#}", e) });
+                self.error_message = Some(format!("Error: {}", e));
+                self.is_loading = false;
+                self.streaming_message = None;
+            }
+        }
+        Ok(())
+    }
+
+    async fn process_streaming(&mut self) -> Result<()> {
+        if let Some(ref mut stream) = self.stream {
+            // Try to get the next chunk with a very short timeout
+            match tokio::time::timeout(std::time::Duration::from_millis(10), stream.next()).await {
+                Ok(Some(Ok(chunk))) => {
+                    // Chunks do not always end on a line boundary
+                    self.stream_buffer.push_str(&chunk);
+                    while let Some(end) = self.stream_buffer.find('\n') {
+                        let line: String = self.stream_buffer.drain(..=end).collect();
+                        let trimmed = line.trim();
+                        if trimmed.is_empty() {
+                            continue;
+                        }
+                        logging::trace("stream", "line", json!({ "line": trimmed }));
+                        let chunk = match ChatChunk::parse(trimmed) {
+                            Ok(chunk) => chunk,
+                            Err(e) => {
+                                let line: String = trimmed.chars().take(200).collect();
+                                logging::warn("stream", "ignored a line that is not JSON", json!({ "error": e.to_string(), "line": line }));
+                                continue;
+                            }
+                        };
+                        if let Some(error) = chunk.error.clone() {
+                            logging::error("stream", "Ollama returned an error", json!({ "error": error }));
+                            self.emit(Event::Error { message: error });
+                        }
+                        if !chunk.content.is_empty() {
+                            self.emit(Event::AssistantDelta { text: chunk.content.clone() });
+                        }
+                        if let Some(ref mut streaming) = self.streaming_message {
+                            if streaming.is_empty() && !chunk.content.is_empty() {
+                                let elapsed_ms = self.reply_started.map_or(0, |started| started.elapsed().as_millis() as u64);
+                                logging::debug("stream", "first content", json!({ "elapsed_ms": elapsed_ms }));
+                            }
+                            streaming.push_str(&chunk.content);
+                        }
+                        if chunk.done {
+                            self.run.tokens += chunk.prompt_tokens + chunk.reply_tokens;
+                            self.usage.last_prompt = chunk.prompt_tokens;
+                            self.usage.prompt += chunk.prompt_tokens;
+                            self.usage.reply += chunk.reply_tokens;
+                            self.usage.replies += 1;
+                            self.emit(Event::Usage {
+                                prompt_tokens: chunk.prompt_tokens,
+                                reply_tokens: chunk.reply_tokens,
+                            });
+                            logging::info(
+                                "stream",
+                                "reply finished",
+                                json!({
+                                    "elapsed_ms": self.reply_started.map_or(0, |started| started.elapsed().as_millis() as u64),
+                                    "prompt_tokens": chunk.prompt_tokens,
+                                    "reply_tokens": chunk.reply_tokens,
+                                    "chars": self.streaming_message.as_ref().map_or(0, String::len),
+                                    "done_reason": chunk.done_reason,
+                                }),
+                            );
+                            self.finish_streaming();
+                            break;
+                        }
+                    }
+                }
+                Ok(Some(Err(e))) => {
+                    logging::error("stream", "stream failed", json!({ "error": format!("{:#}", e) }));
+                    self.emit(Event::Error { message: format!("{:#}", e) });
+                    self.error_message = Some("Stream error".to_string());
+                    self.finish_streaming();
+                }
+                Ok(None) => {
+                    // Stream finished
+                    logging::warn("stream", "stream closed before the reply was done", serde_json::Value::Null);
+                    self.finish_streaming();
+                }
+                Err(_) => {
+                    // Timeout - this is expected, just continue
+                }
+            }
+        }
+        // The reply is complete; run or refuse any tool call it made if the config says so
+        if !self.is_loading {
+            self.apply_permissions().await?;
+            self.advance_plan().await?;
+        }
+        Ok(())
+    }
+
+    fn finish_streaming(&mut self) {
+        // When done, push the full message to history
+        if let Some(content) = self.streaming_message.take() {
+            if content.trim().is_empty() {
+                logging::warn("stream", "dropped an empty reply", json!({ "chars": content.len() }));
+            } else {
+                self.emit(Event::AssistantMessage { content: content.clone() });
+                self.add_assistant_message(&content);
+                self.messages.push_back(Message::Assistant {
+                    content: content.clone(),
+                    timestamp: chrono::Utc::now(),
+                });
+                // Parse for tool calls in the assistant message
+                if self.tools_enabled && !self.compacting {
+                    self.parse_tool_calls(&content);
+                }
+            }
+        }
+        self.is_loading = false;
+        self.stream = None;
+        self.stream_buffer.clear();
+        if self.compacting {
+            self.compacting = false;
+            self.finish_compact();
+        }
+        self.save_session();
+    }
+
+    /// Saves the conversation so far; a failure is only logged.
+    fn save_session(&mut self) {
+        if let Err(e) = self.write_session() {
+            logging::warn("session", "cannot save the session", json!({ "error": format!("{:#}", e) }));
+        }
+    }
+
+    fn write_session(&mut self) -> Result<()> {
+        self.session.model = self.models[self.selected_model_index].name.clone();
+        self.session.turns = self.chat_history[1..].to_vec();
+        self.session.messages = self.messages.iter().cloned().collect();
+        self.session.save(&self.data_dir)
+    }
+
+    pub async fn execute_tool_call(&mut self, tool_call: ToolCall) -> Result<ToolOutput> {
+        match tool_call {
+            ToolCall::Mcp { name, arguments } => Ok(self.mcp.call_tool(&name, &arguments).await.into()),
+            ToolCall::McpReadResource { server, uri } => Ok(self.mcp.read_resource(&server, &uri).await.into()),
+            ToolCall::Remember { text, scope } => Ok(match self.memory.add(&text, scope, Author::Model) {
+                Ok(memory) => format!("[TOOL RESULT: remember]\nSaved {} memory {}.", scope.label(), memory.id),
+                Err(e) => format!("[TOOL ERROR: remember]\nError: {:#}", e),
+            }
+            .into()),
+            ToolCall::SemanticSearch { query, max_results } => {
+                Ok(self.semantic_search(&query, max_results.unwrap_or(5)).await.into())
+            }
+            tool_call => tools::execute(tool_call, Path::new(&self.working_directory), Some(&mut self.checkpoints)).await,
+        }
+    }
+
+    /// Sends the current step of an executing plan to the model.
+    async fn start_plan_step(&mut self) -> Result<()> {
+        let Some(plan) = self.plan.as_mut() else {
+            return Ok(());
+        };
+        let Some(index) = plan.current() else {
+            return Ok(());
+        };
+        plan.steps[index].state = StepState::Active;
+        let prompt = plan.step_prompt(index);
+        let info = format!("Plan step {}/{}: {}", index + 1, plan.steps.len(), plan.steps[index].text);
+        self.run = AgentRun::default();
+        self.push_info(info);
+        self.start_message_sending_with_content(prompt).await
+    }
+
+    fn pause_plan(&mut self, reason: &str) {
+        if let Some(plan) = self.plan.as_mut().filter(|plan| plan.phase == Phase::Executing) {
+            plan.phase = Phase::Paused;
+            self.push_info(format!("Plan paused: {}. /plan continue resumes the current step.", reason));
+        }
+    }
+
+    /// Moves plan mode along once the model has finished replying: a reply
+    /// containing a plan is offered for review, and the end of a step's work
+    /// ticks it off and starts the next one.
+    async fn advance_plan(&mut self) -> Result<()> {
+        let waiting = self.is_loading
+            || self.running_command.is_some()
+            || matches!(self.messages.back(), Some(Message::PendingToolCall { .. }));
+        let Some(plan) = self.plan.as_mut().filter(|_| !waiting) else {
+            return Ok(());
+        };
+        match plan.phase {
+            Phase::Drafting | Phase::Proposed => {
+                let reply = self.chat_history.last().filter(|turn| turn.role == "assistant");
+                let Some(steps) = reply.and_then(|turn| plan::parse(&turn.content)) else {
+                    if plan.phase == Phase::Drafting {
+                        self.push_info("No numbered plan found in the reply. Reply to ask for one, or /plan cancel.".to_string());
+                    }
+                    return Ok(());
+                };
+                let count = steps.len();
+                plan.steps = steps;
+                plan.phase = Phase::Proposed;
+                self.push_info(format!(
+                    "Plan with {} steps ready. /plan approve to start, /plan edit N, /plan drop N, /plan add TEXT, or reply to ask for changes.",
+                    count
+                ));
+                Ok(())
+            }
+            Phase::Executing => {
+                if let Some(index) = plan.current() {
+                    plan.steps[index].state = StepState::Done;
+                }
+                if plan.current().is_some() {
+                    return self.start_plan_step().await;
+                }
+                let summary = plan
+                    .steps
+                    .iter()
+                    .enumerate()
+                    .map(|(i, step)| format!("[x] {}. {} ({} tool calls, {} failed)", i + 1, step.text, step.calls, step.failed))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.plan = None;
+                self.push_info(format!("Plan complete:\n{}", summary));
+                Ok(())
+            }
+            Phase::Paused => Ok(()),
+        }
+    }
+
+    /// Shows a notice in the chat without sending it to the model.
+    pub fn push_info(&mut self, content: String) {
+        self.emit(Event::Info { message: content.clone() });
+        self.messages.push_back(Message::Info {
+            content,
+            timestamp: chrono::Utc::now(),
+        });
+    }
+
+    /// Collects output from a running command and, once it exits, records it
+    /// in the chat and sends the result to the model.
+    async fn process_command(&mut self) -> Result<()> {
+        let Some(running) = self.running_command.as_mut() else {
+            return Ok(());
+        };
+        let Some(reason) = running.poll() else {
+            return Ok(());
+        };
+        let result = running.format_result(&reason);
+        self.running_command = None;
+        if let Some(entry) = self.command_audit.take() {
+            self.finish_call(entry, &result, &[]);
+        }
+        self.end_step(&result);
+        self.messages.push_back(Message::ToolCallResult {
+            result: result.clone(),
+            timestamp: chrono::Utc::now(),
+        });
+        self.start_message_sending_with_content(result).await
+    }
+}
@@ -0,0 +1,357 @@
+//! The permission flow: tool calls the model makes wait for the user, run
+//! under the permission config, or are refused, and what ran is audited.
+
+use super::{Conversation, Message, StepStatus};
+use crate::audit::{self, Decision};
+use crate::config::Action;
+use crate::events::Event;
+use crate::logging;
+use crate::plan::{Phase, StepState};
+use crate::shell::{self, ShellOptions};
+use crate::tools::{self, Risk, ToolCall};
+use anyhow::Result;
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+impl Conversation {
+    pub(super) fn parse_tool_calls(&mut self, message: &str) {
+        // Only allow one pending tool call at a time
+        match tools::parse_tool_call(message) {
+            Some(Ok(invocation)) => match ToolCall::from_invocation(&invocation) {
+                Ok(tool_call) => {
+                    logging::debug("tools", "parsed tool call", json!({ "tool": tool_call.name(), "target": tool_call.target() }));
+                    let working_directory = Path::new(&self.working_directory);
+                    let risk = tool_call.risk(working_directory);
+                    let preview = tools::preview(&tool_call, working_directory);
+                    self.emit(Event::ToolCallProposed {
+                        tool: tool_call.name().to_string(),
+                        target: tool_call.target(),
+                        arguments: tool_call.arguments(),
+                        risk,
+                    });
+                    self.messages.push_back(Message::PendingToolCall {
+                        tool_call,
+                        original_message: invocation.raw,
+                        preview,
+                        risk,
+                        timestamp: chrono::Utc::now(),
+                    });
+                }
+                Err(e) => {
+                    logging::warn("tools", "invalid tool call", json!({ "error": e.to_string(), "call": invocation.raw }));
+                    self.emit(Event::Info { message: format!("Invalid tool call: {}", e) });
+                    self.error_message = Some(format!("Invalid tool call: {}", e));
+                }
+            },
+            Some(Err(e)) => {
+                logging::warn("tools", "cannot parse tool call", json!({ "error": e.to_string() }));
+                self.emit(Event::Info { message: format!("Could not parse tool call: {}", e) });
+                self.error_message = Some(format!("Could not parse tool call: {}", e));
+            }
+            None => {}
+        }
+    }
+
+    /// Runs an accepted tool call and sends the result to the model. Commands
+    /// run in the background; `process_command` sends their result.
+    async fn accept_tool_call(&mut self, tool_call: ToolCall, auto: Option<String>) -> Result<()> {
+        let decision = if auto.is_some() { Decision::Auto } else { Decision::Accepted };
+        let entry = self.decision_entry(&tool_call, decision, auto.clone());
+        logging::info("tools", "running tool", json!({ "tool": tool_call.name(), "target": tool_call.target(), "auto": auto }));
+        self.begin_step(&tool_call, auto);
+        if let ToolCall::RunCommand { command, timeout_secs } = tool_call {
+            let timeout = shell::timeout(timeout_secs);
+            let options = ShellOptions::from_env();
+            match shell::spawn(&command, Path::new(&self.working_directory), timeout, &options) {
+                Ok(running) => {
+                    self.running_command = Some(running);
+                    self.command_audit = Some(entry);
+                }
+                Err(e) => {
+                    let result = format!("[TOOL ERROR: run_command]\nCommand: {}\nError: {}", command, e);
+                    self.finish_call(entry, &result, &[]);
+                    self.end_step(&result);
+                    self.start_message_sending_with_content(result).await?;
+                }
+            }
+            return Ok(());
+        }
+        let output = self.execute_tool_call(tool_call).await?;
+        self.finish_call(entry, &output.text, &output.changed);
+        self.end_step(&output.text);
+        // Do NOT push a user message with the result; instead, send it as a hidden user message to the AI
+        self.start_message_sending_with_content(output.text).await
+    }
+
+    /// Applies the permission config to a tool call the model just made:
+    /// allowed calls run straight away, denied ones are refused and the model
+    /// is told why, and everything else waits for the user. Destructive calls
+    /// and commits always wait, whatever allowed them.
+    pub(super) async fn apply_permissions(&mut self) -> Result<()> {
+        let Some(Message::PendingToolCall { tool_call, risk, .. }) = self.messages.back().cloned() else {
+            return Ok(());
+        };
+        let decision = self.config.permissions.decide(&tool_call, Path::new(&self.working_directory), &self.session_allowed);
+        logging::debug(
+            "permissions",
+            "decided",
+            json!({ "tool": tool_call.name(), "action": format!("{:?}", decision.action), "reason": decision.reason }),
+        );
+        match decision.action {
+            Action::Ask => Ok(()),
+            Action::Allow if tool_call.always_asks(risk) => Ok(()),
+            Action::Allow if !self.config.agent.enabled => Ok(()),
+            // Nothing changes while a plan is being drafted without the user seeing it
+            Action::Allow if self.plan.as_ref().is_some_and(|p| p.phase == Phase::Drafting) && risk > Risk::Read => Ok(()),
+            Action::Allow => {
+                if let Some(reason) = self.agent_pause_reason() {
+                    // Keep the call pending, just above the notice
+                    let pending = self.messages.pop_back();
+                    self.messages.push_back(Message::Info {
+                        content: format!("Agent paused: {}. Accept the call to continue.", reason),
+                        timestamp: chrono::Utc::now(),
+                    });
+                    self.messages.extend(pending);
+                    return Ok(());
+                }
+                self.messages.pop_back();
+                self.accept_tool_call(tool_call, Some(decision.reason)).await
+            }
+            Action::Deny => self.block_pending(decision.reason).await,
+        }
+    }
+
+    /// The tool call waiting for approval, if any.
+    pub fn pending_tool_call(&self) -> Option<(&ToolCall, Risk)> {
+        match self.messages.back() {
+            Some(Message::PendingToolCall { tool_call, risk, .. }) => Some((tool_call, *risk)),
+            _ => None,
+        }
+    }
+
+    /// Runs the pending tool call. With `always`, later calls to the same tool
+    /// run without asking until Quill exits, unless the tool always asks.
+    pub async fn accept_pending(&mut self, always: bool) -> Result<()> {
+        let Some(Message::PendingToolCall { tool_call, risk, .. }) = self.messages.pop_back() else {
+            return Ok(());
+        };
+        if always && !tool_call.always_asks(risk) {
+            self.session_allowed.insert(tool_call.name().to_string());
+        }
+        self.accept_tool_call(tool_call, None).await
+    }
+
+    /// Refuses the pending tool call and waits for the user to say what to do instead.
+    pub fn deny_pending(&mut self) {
+        let Some(Message::PendingToolCall { tool_call, original_message, .. }) = self.messages.pop_back() else {
+            return;
+        };
+        let entry = self.decision_entry(&tool_call, Decision::Denied, None);
+        self.write_audit(&entry);
+        self.messages.push_back(Message::ToolCallDenied {
+            tool_call,
+            original_message,
+            timestamp: chrono::Utc::now(),
+        });
+        self.pause_plan("a tool call was denied");
+    }
+
+    /// Refuses the pending tool call under the permission settings and tells
+    /// the model why, so it carries on without it.
+    pub async fn block_pending(&mut self, reason: String) -> Result<()> {
+        let Some(Message::PendingToolCall { tool_call, original_message, .. }) = self.messages.pop_back() else {
+            return Ok(());
+        };
+        let result = format!(
+            "[TOOL ERROR: {}]\nError: this call is blocked by the user's permission settings ({}); do not retry it",
+            tool_call.name(),
+            reason
+        );
+        let entry = self.decision_entry(&tool_call, Decision::Blocked, Some(reason));
+        self.write_audit(&entry);
+        self.messages.push_back(Message::ToolCallDenied {
+            tool_call,
+            original_message,
+            timestamp: chrono::Utc::now(),
+        });
+        self.start_message_sending_with_content(result).await
+    }
+
+    /// Reports who decided whether a call runs and starts its audit entry.
+    fn decision_entry(&self, tool_call: &ToolCall, decision: Decision, reason: Option<String>) -> audit::Entry {
+        self.emit(Event::ToolDecision {
+            tool: tool_call.name().to_string(),
+            decision,
+            reason: reason.clone(),
+        });
+        self.audit.entry(tool_call, decision, reason)
+    }
+
+    /// Why allowed calls should stop running on their own, if they should.
+    pub fn agent_pause_reason(&self) -> Option<String> {
+        let agent = &self.config.agent;
+        if self.run.stopped {
+            Some("stopped with Esc".to_string())
+        } else if self.run.steps >= agent.max_steps {
+            Some(format!("reached the limit of {} steps", agent.max_steps))
+        } else {
+            agent
+                .max_tokens
+                .filter(|max| self.run.tokens >= *max)
+                .map(|max| format!("used {} of the {} token budget", self.run.tokens, max))
+        }
+    }
+
+    fn begin_step(&mut self, tool_call: &ToolCall, auto: Option<String>) {
+        self.run.steps += 1;
+        self.messages.push_back(Message::AgentStep {
+            number: self.run.steps,
+            tool: tool_call.name().to_string(),
+            target: tool_call.target(),
+            auto,
+            status: StepStatus::Running,
+            timestamp: chrono::Utc::now(),
+        });
+    }
+
+    /// Marks the running step as done or failed from the tool result.
+    pub(super) fn end_step(&mut self, result: &str) {
+        let step = self.messages.iter_mut().rev().find_map(|message| match message {
+            Message::AgentStep { status, .. } if *status == StepStatus::Running => Some(status),
+            _ => None,
+        });
+        let failed = result.starts_with("[TOOL ERROR");
+        if let Some(status) = step {
+            *status = if failed { StepStatus::Failed } else { StepStatus::Done };
+        }
+        let active = self.plan.iter_mut().flat_map(|plan| plan.steps.iter_mut()).find(|s| s.state == StepState::Active);
+        if let Some(step) = active {
+            step.calls += 1;
+            step.failed += usize::from(failed);
+        }
+    }
+
+    /// Logs and audits the outcome of a call that ran.
+    pub(super) fn finish_call(&mut self, mut entry: audit::Entry, result: &str, changed: &[PathBuf]) {
+        entry.finish(result, changed, Path::new(&self.working_directory));
+        let level = if entry.error { logging::Level::Warn } else { logging::Level::Debug };
+        logging::log(
+            level,
+            "tools",
+            "tool finished",
+            json!({
+                "tool": entry.tool,
+                "duration_ms": entry.duration_ms,
+                "result_bytes": entry.result_bytes,
+                "error": entry.error,
+                "files_changed": entry.files_changed,
+            }),
+        );
+        self.emit(Event::ToolResult {
+            tool: entry.tool.clone(),
+            error: entry.error,
+            duration_ms: entry.duration_ms,
+            files_changed: entry.files_changed.clone(),
+            output: result.to_string(),
+        });
+        self.write_audit(&entry);
+    }
+
+    fn write_audit(&mut self, entry: &audit::Entry) {
+        if let Err(e) = self.audit.write(entry) {
+            self.push_info(format!("Could not write the audit log: {:#}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, IndexSettings, Permissions};
+    use crate::conversation::Options;
+    use crate::ollama::OllamaClient;
+
+    /// A conversation in `working_directory` that keeps its checkpoints and
+    /// audit log next to it, in `data_dir(working_directory)`.
+    async fn conversation(working_directory: &Path, permissions: Permissions) -> Conversation {
+        let config = Config {
+            permissions,
+            index: IndexSettings { enabled: false, ..IndexSettings::default() },
+            ..Config::default()
+        };
+        Conversation::new(Options {
+            client: OllamaClient::new("http://127.0.0.1:9"),
+            system_prompt: String::new(),
+            model: None,
+            config,
+            tools: true,
+            working_directory: working_directory.to_path_buf(),
+            data_dir: data_dir(working_directory),
+            resume: None,
+        })
+        .await
+        .unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("quill-test-{}-{}", name, uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn data_dir(working_directory: &Path) -> PathBuf {
+        working_directory.with_extension("data")
+    }
+
+    fn remove(working_directory: &Path) {
+        std::fs::remove_dir_all(working_directory).unwrap();
+        let _ = std::fs::remove_dir_all(data_dir(working_directory));
+    }
+
+    #[tokio::test]
+    async fn destructive_calls_wait_for_approval_when_allowed() {
+        let dir = temp_dir("destructive");
+        std::fs::write(dir.join("notes.txt"), "keep me").unwrap();
+        let mut permissions = Permissions::default();
+        permissions.default = Action::Allow;
+        let mut conversation = conversation(&dir, permissions).await;
+
+        conversation.parse_tool_calls("[tool_call: delete_path(path=\"notes.txt\")]");
+        conversation.apply_permissions().await.unwrap();
+
+        assert!(matches!(conversation.pending_tool_call(), Some((ToolCall::DeletePath { .. }, Risk::Destructive))));
+        assert!(dir.join("notes.txt").exists());
+        remove(&dir);
+    }
+
+    #[tokio::test]
+    async fn always_does_not_allow_destructive_tools_for_the_session() {
+        let dir = temp_dir("always");
+        std::fs::write(dir.join("notes.txt"), "delete me").unwrap();
+        let mut conversation = conversation(&dir, Permissions::default()).await;
+
+        conversation.parse_tool_calls("[tool_call: delete_path(path=\"notes.txt\")]");
+        conversation.accept_pending(true).await.unwrap();
+
+        assert!(!dir.join("notes.txt").exists());
+        assert!(!conversation.session_allowed.contains("delete_path"));
+        assert!(data_dir(&dir).join("audit.jsonl").exists());
+        remove(&dir);
+    }
+
+    #[tokio::test]
+    async fn commits_wait_for_approval_when_allowed() {
+        let dir = temp_dir("commit");
+        let mut permissions = Permissions::default();
+        permissions.default = Action::Allow;
+        let mut conversation = conversation(&dir, permissions).await;
+
+        conversation.parse_tool_calls("[tool_call: git_commit(message=\"Update\", all=true)]");
+        conversation.apply_permissions().await.unwrap();
+        assert!(matches!(conversation.pending_tool_call(), Some((ToolCall::GitCommit { .. }, _))));
+        conversation.accept_pending(true).await.unwrap();
+
+        assert!(!conversation.session_allowed.contains("git_commit"));
+        remove(&dir);
+    }
+}
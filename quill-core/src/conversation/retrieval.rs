@@ -0,0 +1,135 @@
+//! What is added to a message besides the user's text: related code from the
+//! index and relevant memories, and the `semantic_search` tool.
+
+use super::Conversation;
+use crate::config::Action;
+use crate::index::{self, Hit, SharedIndex};
+use crate::logging;
+use crate::memory;
+use crate::ollama::OllamaClient;
+use crate::tools::ToolCall;
+use anyhow::Result;
+use serde_json::json;
+use std::path::Path;
+
+impl Conversation {
+    /// Returns the chunks of the index as it is most similar to `query`,
+    /// leaving out files the permissions deny reading. Takes `&mut` because a
+    /// `Conversation` is not `Sync`, so `&self` would not be `Send`.
+    async fn search_index(&mut self, query: &str, limit: usize) -> Result<Vec<Hit>> {
+        let Some(shared) = &self.index else {
+            anyhow::bail!("the code index is turned off (index.enabled in the config)");
+        };
+        let hits = index::search(shared, &self.ollama_client, query, limit).await?;
+        let working_directory = Path::new(&self.working_directory);
+        Ok(hits
+            .into_iter()
+            .filter(|hit| {
+                let read = ToolCall::ReadFile { path: hit.path.clone(), start_line: None, end_line: None };
+                self.config.permissions.decide(&read, working_directory, &self.session_allowed).action != Action::Deny
+            })
+            .collect())
+    }
+
+    pub(super) async fn semantic_search(&mut self, query: &str, limit: usize) -> String {
+        match self.search_index(query, limit).await {
+            Ok(hits) if hits.is_empty() => format!("[TOOL RESULT: semantic_search]\nNo code found for \"{}\".", query),
+            Ok(hits) => {
+                let mut result = String::from("[TOOL RESULT: semantic_search]");
+                for hit in hits {
+                    result.push_str(&format!(
+                        "\n\n--- {} (lines {}-{}, score {:.2}) ---\n{}",
+                        hit.path,
+                        hit.start_line,
+                        hit.end_line,
+                        hit.score,
+                        hit.text.trim_end()
+                    ));
+                }
+                result
+            }
+            Err(e) => format!("[TOOL ERROR: semantic_search]\nError: {:#}", e),
+        }
+    }
+
+    /// The chunks of code most related to `prompt`, as text to append to it
+    /// and a note for the user. Nothing is added while the index is being
+    /// built for the first time or after its last update failed.
+    pub(super) async fn retrieve(&mut self, prompt: &str) -> Option<(String, String)> {
+        let limit = self.config.index.retrieve;
+        if limit == 0 || self.index.as_ref()?.lock().unwrap().error.is_some() {
+            return None;
+        }
+        let hits = match self.search_index(prompt, limit).await {
+            Ok(hits) => hits,
+            Err(e) => {
+                logging::debug("index", "no code retrieved", json!({ "error": format!("{:#}", e) }));
+                return None;
+            }
+        };
+        let hits: Vec<Hit> = hits.into_iter().filter(|hit| hit.score >= index::MIN_RETRIEVED_SCORE).collect();
+        if hits.is_empty() {
+            return None;
+        }
+        let mut text = String::from("\n\n[RELEVANT CODE: parts of the codebase that may be related, found by semantic search]");
+        let mut places = Vec::new();
+        for hit in &hits {
+            text.push_str(&format!("\n--- {} (lines {}-{}) ---\n{}", hit.path, hit.start_line, hit.end_line, hit.text.trim_end()));
+            places.push(format!("{}:{}-{}", hit.path, hit.start_line, hit.end_line));
+        }
+        Some((text, format!("Added code from the index: {}.", places.join(", "))))
+    }
+
+    /// `/index` reports on the index and looks for changed files in the
+    /// background, also after a failed update.
+    pub(super) fn index_command(&mut self) -> String {
+        let Some(shared) = &self.index else {
+            return "The code index is turned off. Set index.enabled in the config to build one.".to_string();
+        };
+        let mut index = shared.lock().unwrap();
+        let mut status = index.status();
+        if !index.updating {
+            index.error = None;
+            drop(index);
+            update_in_background(shared, &self.ollama_client);
+            status.push_str(" Looking for changed files in the background; /index again shows the progress.");
+        }
+        status
+    }
+
+    /// The memories relevant to `prompt` that were not added to an earlier
+    /// message yet, as text to append to it and a note for the user.
+    pub(super) fn recall(&mut self, prompt: &str) -> Option<(String, String)> {
+        let memories: Vec<_> = self
+            .memory
+            .relevant(prompt, memory::MAX_RECALLED + self.recalled.len())
+            .into_iter()
+            .filter(|memory| !self.recalled.contains(&memory.id))
+            .take(memory::MAX_RECALLED)
+            .collect();
+        if memories.is_empty() {
+            return None;
+        }
+        let mut text = "\n\n[MEMORIES: facts saved in earlier sessions that may be relevant]".to_string();
+        for memory in &memories {
+            text.push_str(&format!("\n- {}", memory.text));
+        }
+        let note = match memories.len() {
+            1 => "Added 1 memory to the message (/memory lists them).".to_string(),
+            count => format!("Added {} memories to the message (/memory lists them).", count),
+        };
+        let ids: Vec<String> = memories.iter().map(|memory| memory.id.clone()).collect();
+        self.recalled.extend(ids);
+        Some((text, note))
+    }
+}
+
+/// Starts an update of the index that the conversation does not wait for.
+pub(super) fn update_in_background(index: &SharedIndex, client: &OllamaClient) {
+    let (index, client) = (index.clone(), client.clone());
+    tokio::spawn(async move {
+        if let Err(e) = index::update(&index, &client).await {
+            logging::warn("index", "cannot update the code index", json!({ "error": format!("{:#}", e) }));
+        }
+    });
+}
@@ -0,0 +1,498 @@
+//! Slash commands such as `/model`, `/undo` and `/plan`, and prompt
+//! templates, run from the input box instead of being sent to the model.
+
+use super::{AgentRun, ChatTurn, Conversation, Message};
+use crate::audit;
+use crate::checkpoint::Checkpoint;
+use crate::commands;
+use crate::memory::{Author, Scope};
+use crate::plan::{Phase, Plan, PlanStep, StepState};
+use crate::session::Session;
+use anyhow::Result;
+use serde_json::Value;
+use std::path::Path;
+
+/// Prompt for `/compact`.
+const COMPACT_PROMPT: &str = "Summarize our conversation so far so it can replace the full history. \
+Keep the user's goals, the decisions made, the files and code touched (with their paths), \
+the current state of the work and anything left to do. Be concise and do not call any tools.";
+
+impl Conversation {
+    /// Runs a slash command instead of sending it.
+    pub(super) async fn run_slash_command(&mut self, command: &str, arguments: &str) -> Result<()> {
+        let arg = arguments.split_whitespace().next();
+        let content = match command {
+            "/help" => self.help(),
+            "/model" => self.model_command(arg),
+            "/clear" => {
+                self.clear();
+                "Cleared the conversation; the model no longer sees the earlier messages.".to_string()
+            }
+            "/new" => self.new_session(),
+            "/save" => self.save_command(arguments),
+            "/load" => self.load_command(arg),
+            "/export" => self.export(arg),
+            "/system" => self.system_command(arguments),
+            "/options" => self.options_command(arguments),
+            "/tokens" => self.token_report(),
+            "/compact" => return self.compact(arguments).await,
+            "/remember" => self.remember_command(arguments),
+            "/forget" => self.forget(arg),
+            "/memory" => self.memory_list(),
+            "/index" => self.index_command(),
+            "/quit" => "/quit only closes the chat interface.".to_string(),
+            "/plan" => return self.plan_command(arguments).await,
+            "/undo" => match arg.map_or(Ok(1), str::parse::<usize>) {
+                Ok(count) => {
+                    let result = self.checkpoints.undo(count);
+                    self.restored_message(result, "Nothing to undo.")
+                }
+                Err(_) => "Usage: /undo [N]".to_string(),
+            },
+            "/checkpoints" => match arg.map(str::parse::<usize>) {
+                None => self.checkpoint_list(),
+                Some(Ok(turn)) if turn >= 1 => {
+                    let result = self.checkpoints.restore_turn(turn);
+                    self.restored_message(result, "No tool edits were made since that turn.")
+                }
+                Some(_) => "Usage: /checkpoints [TURN]".to_string(),
+            },
+            "/agent" => {
+                match arg {
+                    Some("on") => self.config.agent.enabled = true,
+                    Some("off") => self.config.agent.enabled = false,
+                    Some(_) => {
+                        self.push_info("Usage: /agent [on|off]".to_string());
+                        return Ok(());
+                    }
+                    None => {}
+                }
+                let agent = &self.config.agent;
+                let budget = match agent.max_tokens {
+                    Some(max) => format!("{} steps or {} tokens per message", agent.max_steps, max),
+                    None => format!("{} steps per message", agent.max_steps),
+                };
+                if agent.enabled {
+                    format!("Agent mode is on: allowed tools run without asking, up to {}. Esc stops.", budget)
+                } else {
+                    "Agent mode is off: every tool call waits for approval.".to_string()
+                }
+            }
+            "/audit" => match arg.map_or(Ok(20), str::parse::<usize>) {
+                Ok(count) => self.audit_list(count),
+                Err(_) => "Usage: /audit [N]".to_string(),
+            },
+            _ => {
+                // Pick up template files added since the conversation started
+                self.templates = commands::load_templates(Path::new(&self.working_directory));
+                match self.templates.iter().find(|template| template.name == command) {
+                    Some(template) => {
+                        let prompt = template.expand(arguments);
+                        let shown = format!("{} {}", command, arguments).trim_end().to_string();
+                        return self.send_with_mentions(shown, &prompt, arguments).await;
+                    }
+                    None => format!("Unknown command {}. /help lists the commands.", command),
+                }
+            }
+        };
+        // Commands that report through the chat themselves return nothing
+        if !content.is_empty() {
+            self.push_info(content);
+        }
+        Ok(())
+    }
+
+    /// `/remember TEXT` saves a project memory; `/remember -g TEXT` a global one.
+    fn remember_command(&mut self, arguments: &str) -> String {
+        let (scope, text) = match arguments.strip_prefix("-g") {
+            Some(text) if text.is_empty() || text.starts_with(char::is_whitespace) => (Scope::Global, text.trim()),
+            _ => (Scope::Project, arguments),
+        };
+        if text.is_empty() {
+            return "Usage: /remember [-g] TEXT (-g saves it for every project)".to_string();
+        }
+        match self.memory.add(text, scope, Author::User) {
+            Ok(memory) => format!("Saved {} memory {}.", scope.label(), memory.id),
+            Err(e) => format!("Could not save the memory: {:#}", e),
+        }
+    }
+
+    fn forget(&mut self, id: Option<&str>) -> String {
+        let Some(id) = id else {
+            return "Usage: /forget ID (/memory lists the IDs)".to_string();
+        };
+        match self.memory.find(id).and_then(|index| self.memory.remove(index)) {
+            Ok(memory) => format!("Forgot {}: {}", memory.id, memory.text),
+            Err(e) => format!("Could not forget {}: {:#}", id, e),
+        }
+    }
+
+    fn memory_list(&self) -> String {
+        if self.memory.memories.is_empty() {
+            return "No memories yet. /remember TEXT saves one, and the model can save them with the remember tool.".to_string();
+        }
+        let mut lines = vec!["Memories (/forget ID deletes one):".to_string()];
+        for memory in &self.memory.memories {
+            lines.push(format!("  {}  {:<7}  {}", memory.id, memory.scope.label(), memory.text));
+        }
+        lines.join("\n")
+    }
+
+    fn help(&mut self) -> String {
+        self.templates = commands::load_templates(Path::new(&self.working_directory));
+        let commands = commands::matching("/", &self.templates);
+        let width = commands.iter().map(|c| c.name.len() + c.args.len() + 1).max().unwrap_or(0);
+        let mut lines = vec!["Commands:".to_string()];
+        for command in commands {
+            let usage = format!("{} {}", command.name, command.args);
+            lines.push(format!("  {:<width$}  {}", usage.trim_end(), command.description, width = width));
+        }
+        lines.push("Add your own as Markdown prompt templates in ~/.config/quill/commands or .quill/commands.".to_string());
+        lines.join("\n")
+    }
+
+    /// `/model` lists the models; `/model NAME` switches to the one with
+    /// that name, or the only one whose name starts with it.
+    fn model_command(&mut self, name: Option<&str>) -> String {
+        let Some(name) = name else {
+            let mut lines = vec!["Models (/model NAME switches):".to_string()];
+            for (i, model) in self.models.iter().enumerate() {
+                let marker = if i == self.selected_model_index { "*" } else { " " };
+                lines.push(format!("{} {}", marker, model.name));
+            }
+            return lines.join("\n");
+        };
+        let exact = self.models.iter().position(|m| m.name == name);
+        let prefixed: Vec<usize> = (0..self.models.len()).filter(|&i| self.models[i].name.starts_with(name)).collect();
+        match (exact, prefixed.as_slice()) {
+            (Some(index), _) | (None, &[index]) => {
+                self.selected_model_index = index;
+                format!("Switched to {}.", self.models[index].name)
+            }
+            (None, []) => format!("No model {}. /model lists them.", name),
+            (None, _) => format!("{} models start with {}; give more of the name.", prefixed.len(), name),
+        }
+    }
+
+    /// Forgets the conversation, keeping the system prompt and the session.
+    fn clear(&mut self) {
+        self.chat_history.truncate(1);
+        self.messages.clear();
+        self.recalled.clear();
+        self.run = AgentRun::default();
+        self.plan = None;
+        self.save_session();
+    }
+
+    fn new_session(&mut self) -> String {
+        self.save_session();
+        let previous = std::mem::replace(&mut self.session, Session::new(&self.working_directory));
+        self.clear();
+        if previous.turns.is_empty() {
+            return format!("Started session {}.", self.session.id);
+        }
+        format!("Started session {}. /load {} goes back to the previous one.", self.session.id, previous.id)
+    }
+
+    fn save_command(&mut self, name: &str) -> String {
+        if !name.is_empty() {
+            self.session.name = Some(name.to_string());
+        }
+        match self.write_session() {
+            Ok(()) => format!(
+                "Saved session {}. Continue it later with /load {} or quill --resume={}.",
+                self.session.id,
+                self.session.name.as_deref().unwrap_or(&self.session.id),
+                self.session.name.as_deref().unwrap_or(&self.session.id)
+            ),
+            Err(e) => format!("Could not save the session: {:#}", e),
+        }
+    }
+
+    /// `/load` lists the sessions saved in this directory; `/load ID`
+    /// continues one.
+    fn load_command(&mut self, id: Option<&str>) -> String {
+        let Some(id) = id else {
+            let sessions = match Session::list(&self.data_dir) {
+                Ok(sessions) => sessions,
+                Err(e) => return format!("Could not list the sessions: {:#}", e),
+            };
+            let mut lines = vec!["Saved sessions in this directory (/load ID continues one):".to_string()];
+            let here = sessions.iter().filter(|s| s.working_directory == self.working_directory && s.id != self.session.id);
+            for session in here.take(15) {
+                lines.push(format!(
+                    "  {}  {}  {:>3} msgs  {}",
+                    session.id,
+                    session.updated.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"),
+                    session.turns.len(),
+                    session.title()
+                ));
+            }
+            if lines.len() == 1 {
+                return "No other saved sessions in this directory.".to_string();
+            }
+            return lines.join("\n");
+        };
+        match Session::find(&self.data_dir, id, &self.working_directory) {
+            Ok(session) => {
+                self.save_session();
+                self.resume_session(session);
+                String::new()
+            }
+            Err(e) => format!("{:#}", e),
+        }
+    }
+
+    /// Writes the conversation as Markdown to `file`, or to
+    /// `quill-SESSION.md` in the working directory.
+    fn export(&self, file: Option<&str>) -> String {
+        let default_name = format!("quill-{}.md", self.session.id);
+        let path = Path::new(&self.working_directory).join(file.unwrap_or(&default_name));
+        let mut text = format!(
+            "# {}\n\nSession {} with {}, exported {}.\n",
+            self.session.title(),
+            self.session.id,
+            self.models[self.selected_model_index].name,
+            chrono::Local::now().format("%Y-%m-%d %H:%M")
+        );
+        for turn in &self.chat_history[1..] {
+            let tool_result = turn.content.starts_with("[TOOL RESULT") || turn.content.starts_with("[TOOL ERROR");
+            let content = if tool_result { format!("```\n{}\n```", turn.content.trim_end()) } else { turn.content.trim_end().to_string() };
+            let heading = match turn.role.as_str() {
+                "assistant" => "Assistant",
+                _ if tool_result => "Tool result",
+                _ => "User",
+            };
+            text.push_str(&format!("\n## {}\n\n{}\n", heading, content));
+        }
+        match std::fs::write(&path, text) {
+            Ok(()) => format!("Exported {} turns to {}.", self.chat_history.len() - 1, path.display()),
+            Err(e) => format!("Could not write {}: {}", path.display(), e),
+        }
+    }
+
+    /// `/system` shows the system prompt; `/system TEXT` replaces it.
+    fn system_command(&mut self, text: &str) -> String {
+        if text.is_empty() {
+            return format!("System prompt:\n{}", self.system_prompt);
+        }
+        self.system_prompt = text.to_string() + &self.mcp.prompt_section();
+        self.chat_history[0].content = self.system_prompt.clone();
+        "System prompt replaced; it applies from the next message.".to_string()
+    }
+
+    /// `/options` shows the model options, `/options KEY VALUE` sets one
+    /// (VALUE is read as JSON if it can be) and `/options KEY` clears it.
+    fn options_command(&mut self, arguments: &str) -> String {
+        let (key, value) = arguments.split_once(char::is_whitespace).unwrap_or((arguments, ""));
+        let value = value.trim();
+        if key.is_empty() {
+            if self.options.is_empty() {
+                return "No model options set. /options KEY VALUE sets one, e.g. /options temperature 0.2".to_string();
+            }
+            let mut lines = vec!["Model options:".to_string()];
+            lines.extend(self.options.iter().map(|(key, value)| format!("  {} = {}", key, value)));
+            return lines.join("\n");
+        }
+        if value.is_empty() {
+            return match self.options.remove(key) {
+                Some(_) => format!("Cleared {}.", key),
+                None => format!("{} is not set.", key),
+            };
+        }
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+        let info = format!("{} = {}", key, value);
+        self.options.insert(key.to_string(), value);
+        info
+    }
+
+    fn token_report(&self) -> String {
+        let chars: usize = self.chat_history.iter().map(|turn| turn.content.len()).sum();
+        let mut lines = vec![
+            format!("Last request: {} prompt tokens.", self.usage.last_prompt),
+            format!(
+                "This session: {} prompt and {} reply tokens over {} replies.",
+                self.usage.prompt, self.usage.reply, self.usage.replies
+            ),
+            format!("The conversation is now about {} tokens ({} characters).", chars / 4, chars),
+        ];
+        if let Some(context) = self.options.get("num_ctx") {
+            lines.push(format!("Context window: {} tokens (num_ctx).", context));
+        }
+        lines.push("/compact replaces the conversation with a summary when it gets long.".to_string());
+        lines.join("\n")
+    }
+
+    /// Asks the model to summarize the conversation; `finish_compact`
+    /// replaces the history with the summary once it arrives.
+    async fn compact(&mut self, focus: &str) -> Result<()> {
+        if self.chat_history.len() < 3 {
+            self.push_info("Nothing to compact yet.".to_string());
+            return Ok(());
+        }
+        let mut prompt = COMPACT_PROMPT.to_string();
+        if !focus.is_empty() {
+            prompt.push_str(&format!("\nFocus on: {}", focus));
+        }
+        self.compacting = true;
+        self.push_info("Compacting the conversation...".to_string());
+        self.start_message_sending_with_content(prompt).await
+    }
+
+    pub(super) fn finish_compact(&mut self) {
+        let summary = match self.chat_history.last() {
+            Some(turn) if turn.role == "assistant" => turn.content.clone(),
+            _ => {
+                // The summary request failed or was stopped; drop it
+                if self.chat_history.last().is_some_and(|turn| turn.role == "user") {
+                    self.chat_history.pop();
+                }
+                self.push_info("Could not compact the conversation; it is unchanged.".to_string());
+                return;
+            }
+        };
+        let turns = self.chat_history.len() - 3;
+        self.chat_history.truncate(1);
+        // Memories added to the replaced turns can be added again
+        self.recalled.clear();
+        self.chat_history.push(ChatTurn {
+            role: "user".to_string(),
+            content: format!("[SUMMARY OF THE CONVERSATION SO FAR]\n{}", summary),
+        });
+        self.push_info(format!("Replaced {} turns with the summary above (about {} tokens).", turns, summary.len() / 4));
+    }
+
+    /// `/plan TASK` asks the model for a plan; while a plan exists the
+    /// arguments edit, approve, resume or cancel it instead.
+    async fn plan_command(&mut self, args: &str) -> Result<()> {
+        const USAGE: &str = "Usage: /plan approve | edit N [TEXT] | drop N | add TEXT | continue | cancel";
+        let Some(plan) = self.plan.as_mut() else {
+            if args.is_empty() {
+                self.push_info("Usage: /plan TASK".to_string());
+                return Ok(());
+            }
+            let plan = Plan::new(args);
+            let prompt = plan.planning_prompt();
+            self.plan = Some(plan);
+            self.checkpoints.begin_turn(args);
+            self.run = AgentRun::default();
+            self.messages.push_back(Message::User {
+                content: format!("/plan {}", args),
+                timestamp: chrono::Utc::now(),
+            });
+            return self.start_message_sending_with_content(prompt).await;
+        };
+        let (action, rest) = args.split_once(' ').unwrap_or((args, ""));
+        let number = rest.split_whitespace().next().and_then(|n| n.parse::<usize>().ok());
+        let index = number.filter(|n| (1..=plan.steps.len()).contains(n)).map(|n| n - 1);
+        let editable = plan.phase != Phase::Drafting;
+        let info = match action {
+            "approve" if plan.phase == Phase::Proposed && !plan.steps.is_empty() => {
+                plan.phase = Phase::Executing;
+                return self.start_plan_step().await;
+            }
+            "continue" if plan.phase == Phase::Paused => {
+                plan.phase = Phase::Executing;
+                return self.start_plan_step().await;
+            }
+            "edit" if editable => match index {
+                Some(i) => {
+                    let text = rest.split_once(' ').map_or("", |(_, text)| text.trim());
+                    if text.is_empty() {
+                        // Put the step in the input box so it can be edited in place
+                        self.draft = Some(format!("/plan edit {} {}", i + 1, plan.steps[i].text));
+                        return Ok(());
+                    }
+                    plan.steps[i].text = text.to_string();
+                    format!("Step {} updated.", i + 1)
+                }
+                None => format!("No step {}.", rest),
+            },
+            "drop" if editable => match index {
+                Some(i) if plan.steps[i].state == StepState::Todo => {
+                    plan.steps.remove(i);
+                    format!("Step {} removed.", i + 1)
+                }
+                Some(i) => format!("Step {} has already been started.", i + 1),
+                None => format!("No step {}.", rest),
+            },
+            "add" if editable && !rest.trim().is_empty() => {
+                plan.steps.push(PlanStep::new(rest));
+                format!("Added step {}.", plan.steps.len())
+            }
+            "cancel" => {
+                self.plan = None;
+                "Plan cancelled.".to_string()
+            }
+            _ => USAGE.to_string(),
+        };
+        self.push_info(info);
+        Ok(())
+    }
+
+    fn audit_list(&self, count: usize) -> String {
+        match self.audit.recent(count) {
+            Ok(entries) if entries.is_empty() => format!("No tool calls logged yet ({}).", self.audit.path().display()),
+            Ok(entries) => {
+                let mut lines = vec![format!("Last {} tool calls, from {}:", entries.len(), self.audit.path().display())];
+                lines.extend(entries.iter().map(audit::Entry::summary));
+                lines.join("\n")
+            }
+            Err(e) => format!("Could not read the audit log: {:#}", e),
+        }
+    }
+
+    /// Describes reverted checkpoints and tells the model about them, since
+    /// the file contents it last saw may no longer be on disk.
+    fn restored_message(&mut self, result: Result<Vec<Checkpoint>>, nothing: &str) -> String {
+        let (restored, error) = match result {
+            Ok(restored) => (restored, None),
+            Err(e) => (Vec::new(), Some(format!("{:#}", e))),
+        };
+        if restored.is_empty() && error.is_none() {
+            return nothing.to_string();
+        }
+        let mut lines: Vec<String> = restored
+            .iter()
+            .map(|c| format!("  #{} {}: {}", c.id, c.tool, c.target))
+            .collect();
+        if !restored.is_empty() {
+            self.add_user_message(&format!(
+                "[NOTE] The user reverted these tool calls; re-read any file before editing it again:\n{}",
+                lines.join("\n")
+            ));
+            lines.insert(0, format!("Reverted {} tool edit(s):", restored.len()));
+        }
+        if let Some(error) = error {
+            lines.push(format!("Error: {}", error));
+        }
+        lines.join("\n")
+    }
+
+    fn checkpoint_list(&self) -> String {
+        if self.checkpoints.checkpoints.is_empty() {
+            return "No tool edits in this session yet.".to_string();
+        }
+        let mut lines = vec!["Tool edits by turn (/undo [N] reverts the last N, /checkpoints TURN restores the files to before that turn):".to_string()];
+        let mut last_turn = None;
+        for checkpoint in &self.checkpoints.checkpoints {
+            if last_turn != Some(checkpoint.turn) {
+                let prompt = checkpoint
+                    .turn
+                    .checked_sub(1)
+                    .and_then(|i| self.checkpoints.turns.get(i))
+                    .map_or("", String::as_str);
+                lines.push(format!("turn {}: {}", checkpoint.turn, prompt));
+                last_turn = Some(checkpoint.turn);
+            }
+            lines.push(format!(
+                "  #{} {} {}: {}",
+                checkpoint.id,
+                checkpoint.timestamp.format("%H:%M"),
+                checkpoint.tool,
+                checkpoint.target
+            ));
+        }
+        lines.join("\n")
+    }
+}
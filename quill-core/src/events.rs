@@ -0,0 +1,56 @@
+//! Events describing what a conversation is doing, for frontends that do not
+//! read `Conversation::messages` directly, such as `quill run --json` and
+//! `quill serve`.
+
+use crate::audit::Decision;
+use crate::tools::Risk;
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// Text added to the reply being streamed.
+    AssistantDelta { text: String },
+    /// A complete reply from the model.
+    AssistantMessage { content: String },
+    /// The model asked to call a tool.
+    ToolCallProposed {
+        tool: String,
+        target: String,
+        arguments: Value,
+        risk: Risk,
+    },
+    /// Whether the call runs, and who decided.
+    ToolDecision {
+        tool: String,
+        decision: Decision,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// The outcome of a call that ran; `output` is what the model is sent.
+    ToolResult {
+        tool: String,
+        error: bool,
+        duration_ms: Option<u64>,
+        files_changed: Vec<String>,
+        output: String,
+    },
+    /// Tokens reported for one reply.
+    Usage { prompt_tokens: u64, reply_tokens: u64 },
+    /// A notice that the chat interface would show, such as the agent pausing.
+    Info { message: String },
+    Error { message: String },
+    /// Nothing is running: the conversation waits for the pending tool call
+    /// to be decided, or for the next message. Sent by `quill serve`.
+    Idle { pending_tool_call: bool },
+    /// The last event of `quill run --json`.
+    Final {
+        /// `done`, `stopped` (a step or token limit was reached) or `error`.
+        status: String,
+        /// The model's last reply.
+        answer: String,
+        steps: usize,
+        tokens: u64,
+    },
+}
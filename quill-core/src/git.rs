@@ -0,0 +1,427 @@
+//! Git tools implemented on libgit2, so results are structured and compact
+//! instead of whatever the installed `git` prints.
+
+use crate::tools;
+use chrono::{DateTime, Utc};
+use git2::{
+    BlameOptions, Diff, DiffFormat, DiffOptions, DiffStatsFormat, Index, Repository, RepositoryState,
+    Sort, Status, StatusOptions,
+};
+use std::path::{Path, PathBuf};
+
+/// Output longer than this is truncated.
+const MAX_OUTPUT_BYTES: usize = 40 * 1024;
+/// Default and maximum number of commits shown by `git_log`.
+const DEFAULT_LOG_COUNT: usize = 10;
+const MAX_LOG_COUNT: usize = 100;
+/// Maximum number of lines shown by one `git_blame` call.
+const MAX_BLAME_LINES: usize = 200;
+
+type GitResult<T> = Result<T, String>;
+
+fn open(working_directory: &Path) -> GitResult<Repository> {
+    Repository::discover(working_directory)
+        .map_err(|_| format!("{} is not inside a git repository", working_directory.display()))
+}
+
+fn err(e: git2::Error) -> String {
+    e.message().to_string()
+}
+
+fn workdir(repo: &Repository) -> GitResult<PathBuf> {
+    repo.workdir()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| "the repository is bare".to_string())
+}
+
+/// Converts a path relative to the working directory into one relative to
+/// the repository root, which is what libgit2 expects.
+fn repo_path(repo: &Repository, working_directory: &Path, path: &str) -> GitResult<PathBuf> {
+    let root = workdir(repo)?;
+    let absolute = working_directory.join(path);
+    let canonical_root = root.canonicalize().unwrap_or(root);
+    let canonical = absolute
+        .canonicalize()
+        .or_else(|_| {
+            // The file may have been deleted; canonicalize its directory instead.
+            let parent = absolute.parent().unwrap_or(&absolute).canonicalize()?;
+            Ok::<_, std::io::Error>(parent.join(absolute.file_name().unwrap_or_default()))
+        })
+        .unwrap_or(absolute);
+    canonical
+        .strip_prefix(&canonical_root)
+        .map(Path::to_path_buf)
+        .map_err(|_| format!("{} is outside the repository", path))
+}
+
+fn truncate(mut out: String) -> String {
+    if out.len() > MAX_OUTPUT_BYTES {
+        let mut cut = MAX_OUTPUT_BYTES;
+        while !out.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        out.truncate(cut);
+        out.push_str("\n[output truncated; narrow it down with `path`]");
+    }
+    out
+}
+
+fn format_time(time: git2::Time) -> String {
+    DateTime::<Utc>::from_timestamp(time.seconds(), 0)
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+fn short_id(repo: &Repository, oid: git2::Oid) -> String {
+    repo.find_object(oid, None)
+        .and_then(|o| o.short_id())
+        .ok()
+        .and_then(|buf| buf.as_str().map(str::to_string))
+        .unwrap_or_else(|| oid.to_string()[..7].to_string())
+}
+
+/// Branch, upstream tracking and changed files, one per line in `XY path`
+/// form where X is the index and Y the working tree state.
+pub fn status(working_directory: &Path) -> GitResult<String> {
+    let repo = open(working_directory)?;
+    let mut out = String::new();
+    match repo.head() {
+        Ok(head) if repo.head_detached().unwrap_or(false) => {
+            let id = head.target().map(|oid| short_id(&repo, oid)).unwrap_or_default();
+            out.push_str(&format!("HEAD detached at {}\n", id));
+        }
+        Ok(head) => {
+            let branch = head.shorthand().unwrap_or("HEAD").to_string();
+            out.push_str(&format!("On branch {}", branch));
+            let upstream = repo
+                .find_branch(&branch, git2::BranchType::Local)
+                .and_then(|b| b.upstream());
+            if let (Ok(upstream), Some(local)) = (upstream, head.target()) {
+                let name = upstream.name().ok().flatten().unwrap_or("upstream").to_string();
+                if let Some(remote) = upstream.get().target() {
+                    let (ahead, behind) = repo.graph_ahead_behind(local, remote).map_err(err)?;
+                    out.push_str(&format!(" (tracking {}: ahead {}, behind {})", name, ahead, behind));
+                }
+            }
+            out.push('\n');
+        }
+        Err(_) => out.push_str("No commits yet\n"),
+    }
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .renames_head_to_index(true);
+    let statuses = repo.statuses(Some(&mut opts)).map_err(err)?;
+    if statuses.is_empty() {
+        out.push_str("Working tree clean\n");
+        return Ok(out);
+    }
+    for entry in statuses.iter() {
+        let s = entry.status();
+        let index = if s.contains(Status::CONFLICTED) {
+            'U'
+        } else if s.contains(Status::INDEX_NEW) {
+            'A'
+        } else if s.contains(Status::INDEX_MODIFIED) || s.contains(Status::INDEX_TYPECHANGE) {
+            'M'
+        } else if s.contains(Status::INDEX_DELETED) {
+            'D'
+        } else if s.contains(Status::INDEX_RENAMED) {
+            'R'
+        } else if s.contains(Status::WT_NEW) {
+            '?'
+        } else {
+            ' '
+        };
+        let worktree = if s.contains(Status::CONFLICTED) {
+            'U'
+        } else if s.contains(Status::WT_NEW) {
+            '?'
+        } else if s.contains(Status::WT_MODIFIED) || s.contains(Status::WT_TYPECHANGE) {
+            'M'
+        } else if s.contains(Status::WT_DELETED) {
+            'D'
+        } else if s.contains(Status::WT_RENAMED) {
+            'R'
+        } else {
+            ' '
+        };
+        let path = entry.path().unwrap_or("?");
+        match entry.head_to_index().and_then(|d| d.old_file().path().map(Path::to_path_buf)) {
+            Some(old) if s.contains(Status::INDEX_RENAMED) => {
+                out.push_str(&format!("{}{} {} -> {}\n", index, worktree, old.display(), path))
+            }
+            _ => out.push_str(&format!("{}{} {}\n", index, worktree, path)),
+        }
+    }
+    Ok(truncate(out))
+}
+
+fn render_diff(diff: &Diff) -> GitResult<String> {
+    let stats = diff.stats().map_err(err)?;
+    if stats.files_changed() == 0 {
+        return Ok("No changes.".to_string());
+    }
+    let summary = stats.to_buf(DiffStatsFormat::FULL, 80).map_err(err)?;
+    let mut out = summary.as_str().unwrap_or_default().to_string();
+    out.push('\n');
+    diff.print(DiffFormat::Patch, |_, _, line| {
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            out.push(line.origin());
+        }
+        out.push_str(&String::from_utf8_lossy(line.content()));
+        true
+    })
+    .map_err(err)?;
+    Ok(truncate(out))
+}
+
+/// Unstaged changes by default, staged changes with `staged`, or everything
+/// since `against` (any revision, e.g. `HEAD~3` or `main`).
+pub fn diff(working_directory: &Path, staged: bool, against: Option<&str>, path: Option<&str>) -> GitResult<String> {
+    let repo = open(working_directory)?;
+    let mut opts = DiffOptions::new();
+    if let Some(path) = path {
+        opts.pathspec(repo_path(&repo, working_directory, path)?);
+    }
+    let diff = match against {
+        Some(rev) => {
+            let tree = repo
+                .revparse_single(rev)
+                .and_then(|o| o.peel_to_tree())
+                .map_err(|e| format!("unknown revision `{}`: {}", rev, e.message()))?;
+            if staged {
+                repo.diff_tree_to_index(Some(&tree), None, Some(&mut opts))
+            } else {
+                repo.diff_tree_to_workdir_with_index(Some(&tree), Some(&mut opts))
+            }
+        }
+        None if staged => {
+            let head = repo.head().and_then(|h| h.peel_to_tree()).ok();
+            repo.diff_tree_to_index(head.as_ref(), None, Some(&mut opts))
+        }
+        None => repo.diff_index_to_workdir(None, Some(&mut opts)),
+    }
+    .map_err(err)?;
+    render_diff(&diff)
+}
+
+/// The most recent commits reachable from HEAD, optionally only those that
+/// touch `path`.
+pub fn log(working_directory: &Path, max_count: Option<usize>, path: Option<&str>) -> GitResult<String> {
+    let repo = open(working_directory)?;
+    let filter = path.map(|p| repo_path(&repo, working_directory, p)).transpose()?;
+    let max_count = max_count.unwrap_or(DEFAULT_LOG_COUNT).clamp(1, MAX_LOG_COUNT);
+    let mut walk = repo.revwalk().map_err(err)?;
+    walk.push_head().map_err(|_| "the repository has no commits yet".to_string())?;
+    walk.set_sorting(Sort::TIME).map_err(err)?;
+    let mut out = String::new();
+    let mut shown = 0;
+    for oid in walk {
+        let commit = repo.find_commit(oid.map_err(err)?).map_err(err)?;
+        if let Some(filter) = &filter {
+            let tree = commit.tree().map_err(err)?;
+            let parent = commit.parent(0).and_then(|p| p.tree()).ok();
+            let mut opts = DiffOptions::new();
+            opts.pathspec(filter);
+            let diff = repo
+                .diff_tree_to_tree(parent.as_ref(), Some(&tree), Some(&mut opts))
+                .map_err(err)?;
+            if diff.deltas().len() == 0 {
+                continue;
+            }
+        }
+        let author = commit.author();
+        out.push_str(&format!(
+            "{} {} {} <{}>\n    {}\n",
+            short_id(&repo, commit.id()),
+            format_time(commit.time()),
+            author.name().unwrap_or("?"),
+            author.email().unwrap_or("?"),
+            commit.summary().unwrap_or("")
+        ));
+        shown += 1;
+        if shown == max_count {
+            break;
+        }
+    }
+    if shown == 0 {
+        return Ok("No commits found.".to_string());
+    }
+    Ok(truncate(out))
+}
+
+/// Who last changed each line of `path`, including uncommitted edits.
+pub fn blame(working_directory: &Path, path: &str, start_line: Option<usize>, end_line: Option<usize>) -> GitResult<String> {
+    let full = tools::resolve_in_workspace(working_directory, path)?;
+    let repo = open(working_directory)?;
+    let rel = repo_path(&repo, working_directory, path)?;
+    let content = std::fs::read_to_string(full).map_err(|e| e.to_string())?;
+    let committed = repo
+        .blame_file(&rel, Some(&mut BlameOptions::new()))
+        .map_err(|e| format!("cannot blame {}: {}", path, e.message()))?;
+    let blame = committed.blame_buffer(content.as_bytes()).map_err(err)?;
+    let lines: Vec<&str> = content.lines().collect();
+    let start = start_line.unwrap_or(1).max(1);
+    let end = end_line
+        .unwrap_or(lines.len())
+        .min(lines.len())
+        .min(start + MAX_BLAME_LINES - 1);
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate().take(end).skip(start - 1) {
+        let (id, origin) = match blame.get_line(i + 1) {
+            Some(hunk) if !hunk.final_commit_id().is_zero() => (
+                short_id(&repo, hunk.final_commit_id()),
+                format!(
+                    "{} {}",
+                    hunk.final_signature().name().unwrap_or("?"),
+                    format_time(hunk.final_signature().when())
+                ),
+            ),
+            _ => ("0000000".to_string(), "Not committed yet".to_string()),
+        };
+        out.push_str(&format!("{} ({}) {:>5}\t{}\n", id, origin, i + 1, line));
+    }
+    if end_line.is_some_and(|e| e > end) && end < lines.len() {
+        out.push_str(&format!("[stopped after {} lines; pass start_line={} to continue]\n", MAX_BLAME_LINES, end + 1));
+    }
+    Ok(truncate(out))
+}
+
+/// Builds the index a commit would use without writing it: `paths` are staged
+/// (or removed if deleted), and `all` stages every change to tracked files.
+fn commit_index(repo: &Repository, working_directory: &Path, paths: Option<&[String]>, all: bool) -> GitResult<Index> {
+    let mut index = repo.index().map_err(err)?;
+    if all {
+        index.update_all(["*"], None).map_err(err)?;
+    }
+    for path in paths.unwrap_or_default() {
+        let rel = repo_path(repo, working_directory, path)?;
+        if working_directory.join(path).exists() {
+            index.add_path(&rel).map_err(|e| format!("cannot stage {}: {}", path, e.message()))?;
+        } else {
+            index.remove_path(&rel).map_err(|e| format!("cannot stage {}: {}", path, e.message()))?;
+        }
+    }
+    Ok(index)
+}
+
+fn staged_diff<'r>(repo: &'r Repository, index: &mut Index) -> GitResult<Diff<'r>> {
+    let head = repo.head().and_then(|h| h.peel_to_tree()).ok();
+    repo.diff_tree_to_index(head.as_ref(), Some(index), None).map_err(err)
+}
+
+/// Refuses to commit while a merge, rebase or similar is in progress: a plain
+/// commit would drop the other parent and leave the operation half done.
+fn ensure_clean_state(repo: &Repository) -> GitResult<()> {
+    let operation = match repo.state() {
+        RepositoryState::Clean => return Ok(()),
+        RepositoryState::Merge => "a merge",
+        RepositoryState::Revert | RepositoryState::RevertSequence => "a revert",
+        RepositoryState::CherryPick | RepositoryState::CherryPickSequence => "a cherry-pick",
+        RepositoryState::Bisect => "a bisect",
+        RepositoryState::Rebase | RepositoryState::RebaseInteractive | RepositoryState::RebaseMerge => "a rebase",
+        RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => "git am",
+    };
+    Err(format!("{} is in progress; finish or abort it with git before committing", operation))
+}
+
+/// What `commit` would record, for the approval prompt.
+pub fn commit_preview(working_directory: &Path, message: &str, paths: Option<&[String]>, all: bool) -> GitResult<String> {
+    let repo = open(working_directory)?;
+    ensure_clean_state(&repo)?;
+    let mut index = commit_index(&repo, working_directory, paths, all)?;
+    let diff = staged_diff(&repo, &mut index)?;
+    if diff.deltas().len() == 0 {
+        return Err("nothing to commit".to_string());
+    }
+    let signature = repo.signature().map_err(|e| format!("no git identity configured: {}", e.message()))?;
+    Ok(format!(
+        "commit as {} <{}>\n{}\n\n{}",
+        signature.name().unwrap_or("?"),
+        signature.email().unwrap_or("?"),
+        message.trim(),
+        render_diff(&diff)?
+    ))
+}
+
+/// Stages `paths` (and every tracked change with `all`) and commits on HEAD.
+pub fn commit(working_directory: &Path, message: &str, paths: Option<&[String]>, all: bool) -> GitResult<String> {
+    if message.trim().is_empty() {
+        return Err("commit message must not be empty".to_string());
+    }
+    let repo = open(working_directory)?;
+    ensure_clean_state(&repo)?;
+    let mut index = commit_index(&repo, working_directory, paths, all)?;
+    let diff = staged_diff(&repo, &mut index)?;
+    if diff.deltas().len() == 0 {
+        return Err("nothing to commit".to_string());
+    }
+    let stats = diff
+        .stats()
+        .and_then(|s| s.to_buf(DiffStatsFormat::SHORT, 80))
+        .map(|b| b.as_str().unwrap_or_default().trim().to_string())
+        .map_err(err)?;
+    index.write().map_err(err)?;
+    let tree = repo
+        .find_tree(index.write_tree().map_err(err)?)
+        .map_err(err)?;
+    let signature = repo.signature().map_err(|e| format!("no git identity configured: {}", e.message()))?;
+    let parent = repo.head().and_then(|h| h.peel_to_commit()).ok();
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    let oid = repo
+        .commit(Some("HEAD"), &signature, &signature, message.trim(), &tree, &parents)
+        .map_err(err)?;
+    Ok(format!("Committed {}: {}\n{}", short_id(&repo, oid), message.lines().next().unwrap_or(""), stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn repository(name: &str) -> (PathBuf, Repository) {
+        let dir = std::env::temp_dir().join(format!("quill-test-{}-{}", name, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let repo = Repository::init(&dir).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        (dir, repo)
+    }
+
+    #[test]
+    fn commit_refuses_during_a_merge() {
+        let (dir, repo) = repository("git-merge");
+        fs::write(dir.join("a.txt"), "one\n").unwrap();
+        commit(&dir, "first", Some(&["a.txt".to_string()]), false).unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        fs::write(dir.join(".git").join("MERGE_HEAD"), format!("{}\n", head)).unwrap();
+        fs::write(dir.join("a.txt"), "two\n").unwrap();
+        let preview = commit_preview(&dir, "merge", None, true);
+        let result = commit(&dir, "merge", None, true);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(preview.unwrap_err(), "a merge is in progress; finish or abort it with git before committing");
+        assert_eq!(result.unwrap_err(), "a merge is in progress; finish or abort it with git before committing");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn blame_stays_in_the_working_directory() {
+        let (dir, _repo) = repository("git-blame");
+        fs::write(dir.join("a.txt"), "one\n").unwrap();
+        commit(&dir, "first", Some(&["a.txt".to_string()]), false).unwrap();
+        let outside = std::env::temp_dir().join(format!("quill-test-git-outside-{}", uuid::Uuid::new_v4()));
+        fs::write(&outside, "secret\n").unwrap();
+        std::os::unix::fs::symlink(&outside, dir.join("link.txt")).unwrap();
+
+        let blamed = blame(&dir, "a.txt", None, None);
+        let escaped = blame(&dir, "link.txt", None, None);
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_file(&outside).unwrap();
+
+        assert!(blamed.unwrap().contains("one"));
+        let error = escaped.unwrap_err();
+        assert!(error.contains("outside the working directory"), "{}", error);
+    }
+}
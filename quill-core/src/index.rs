@@ -0,0 +1,504 @@
+//! Semantic search over the codebase. Text files are cut into overlapping
+//! chunks of lines, embedded with an Ollama embedding model and kept on disk
+//! per working directory, so only files that changed since the last update
+//! are embedded again. `semantic_search` and the code added to each message
+//! look up the chunks closest to a query.
+//!
+//! The index is shared between the conversation and the task that builds it
+//! in the background; the lock is only held between network requests. A
+//! watcher updates it when files change, so searches never wait for a walk of
+//! the tree.
+
+use crate::logging;
+use crate::ollama::OllamaClient;
+use crate::search;
+use crate::utils::path_key;
+use anyhow::{bail, Context, Result};
+use notify::{RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Lines per chunk, and how many of them the next chunk repeats.
+const CHUNK_LINES: usize = 60;
+const CHUNK_OVERLAP: usize = 10;
+/// Embedding models have a context limit; longer chunks are cut for embedding.
+const MAX_CHUNK_CHARS: usize = 4000;
+/// Larger files are usually generated or data, and are not indexed.
+const MAX_FILE_BYTES: u64 = 256 * 1024;
+/// Chunks embedded per request.
+const BATCH_SIZE: usize = 32;
+/// The index is saved after this many batches, so an interrupted build
+/// does not start over.
+const SAVE_EVERY: usize = 20;
+/// How long the watcher waits for more changes before updating, so a burst
+/// of writes gives one update.
+const WATCH_DELAY: Duration = Duration::from_secs(2);
+
+/// Chunks less similar to a message than this are not added to it; they are
+/// rarely about the same thing.
+pub const MIN_RETRIEVED_SCORE: f32 = 0.5;
+
+/// An index shared with the task that updates it.
+pub type SharedIndex = Arc<Mutex<Index>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Chunk {
+    start_line: usize,
+    end_line: usize,
+    #[serde(skip)]
+    vector: Vec<f32>,
+}
+
+/// What was indexed of one file, and the state of the file at the time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileEntry {
+    modified: u128,
+    size: u64,
+    chunks: Vec<Chunk>,
+}
+
+/// The part of the index stored as JSON; the vectors go in a separate file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    model: String,
+    dimensions: usize,
+    files: BTreeMap<String, FileEntry>,
+}
+
+#[derive(Debug)]
+pub struct Index {
+    /// Where the index files of every working directory are kept.
+    dir: PathBuf,
+    root: PathBuf,
+    pub model: String,
+    dimensions: usize,
+    files: BTreeMap<String, FileEntry>,
+    /// Set while an update runs, so a second one does not start.
+    pub updating: bool,
+    /// Files embedded and to embed in the running update.
+    pub progress: Option<(usize, usize)>,
+    /// Why the last update failed. Automatic updates stop until the next
+    /// explicit one.
+    pub error: Option<String>,
+}
+
+/// A chunk found by `search`.
+#[derive(Debug, Clone)]
+pub struct Hit {
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Cosine similarity to the query, up to 1.
+    pub score: f32,
+    /// The chunk's lines as they are now, numbered like `read_file` output.
+    pub text: String,
+}
+
+/// What an update did.
+#[derive(Debug, Default)]
+pub struct Update {
+    pub embedded: usize,
+    pub removed: usize,
+}
+
+/// When a file was last modified, and its size.
+fn stamp(path: &Path) -> Option<(u128, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+    Some((modified, metadata.len()))
+}
+
+/// Line ranges of the chunks of a file with `lines` lines, 1-based and inclusive.
+fn chunk_ranges(lines: usize) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = 1;
+    while start <= lines {
+        let end = (start + CHUNK_LINES - 1).min(lines);
+        ranges.push((start, end));
+        if end == lines {
+            break;
+        }
+        start = end + 1 - CHUNK_OVERLAP;
+    }
+    ranges
+}
+
+/// The text to embed for lines `start..=end` of `path`: the path helps the
+/// model place the code.
+fn chunk_text(path: &str, lines: &[&str], start: usize, end: usize) -> String {
+    let mut text = format!("{}\n{}", path, lines[start - 1..end].join("\n"));
+    if text.len() > MAX_CHUNK_CHARS {
+        let mut cut = MAX_CHUNK_CHARS;
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.truncate(cut);
+    }
+    text
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 {
+        0.0
+    } else {
+        dot / norms
+    }
+}
+
+impl Index {
+    fn files_path(&self) -> (PathBuf, PathBuf) {
+        let key = path_key(&self.root.display().to_string());
+        (self.dir.join(format!("{}.json", key)), self.dir.join(format!("{}.bin", key)))
+    }
+
+    /// Reads the index of `root` built with `model`. An index that cannot be
+    /// read, or was built with another model, is started over.
+    pub fn load(data_dir: &Path, root: &Path, model: &str) -> Self {
+        let mut index = Index {
+            dir: data_dir.join("index"),
+            root: root.to_path_buf(),
+            model: model.to_string(),
+            dimensions: 0,
+            files: BTreeMap::new(),
+            updating: false,
+            progress: None,
+            error: None,
+        };
+        match index.read() {
+            Ok(Some(manifest)) if manifest.model == model => {
+                index.dimensions = manifest.dimensions;
+                index.files = manifest.files;
+            }
+            Ok(_) => {}
+            Err(e) => logging::warn("index", "cannot read the index; rebuilding it", json!({ "error": format!("{:#}", e) })),
+        }
+        index
+    }
+
+    fn read(&self) -> Result<Option<Manifest>> {
+        let (manifest_path, vectors_path) = self.files_path();
+        let text = match fs::read_to_string(&manifest_path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("cannot read {}", manifest_path.display())),
+        };
+        let mut manifest: Manifest = serde_json::from_str(&text)?;
+        let bytes = fs::read(&vectors_path).with_context(|| format!("cannot read {}", vectors_path.display()))?;
+        let chunks: usize = manifest.files.values().map(|file| file.chunks.len()).sum();
+        if bytes.len() != chunks * manifest.dimensions * 4 {
+            bail!("{} does not match {}", vectors_path.display(), manifest_path.display());
+        }
+        let mut floats = bytes.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        for chunk in manifest.files.values_mut().flat_map(|file| file.chunks.iter_mut()) {
+            chunk.vector = floats.by_ref().take(manifest.dimensions).collect();
+        }
+        Ok(Some(manifest))
+    }
+
+    fn save(&self) -> Result<()> {
+        let (manifest_path, vectors_path) = self.files_path();
+        fs::create_dir_all(&self.dir).with_context(|| format!("cannot create {}", self.dir.display()))?;
+        let mut bytes = Vec::new();
+        for chunk in self.files.values().flat_map(|file| &file.chunks) {
+            for value in &chunk.vector {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        let manifest = json!({ "model": self.model, "dimensions": self.dimensions, "files": self.files });
+        // Vectors first: a manifest that does not match them is discarded on load
+        let temp = vectors_path.with_extension("bin.tmp");
+        fs::write(&temp, bytes).with_context(|| format!("cannot write {}", temp.display()))?;
+        fs::rename(&temp, &vectors_path).with_context(|| format!("cannot write {}", vectors_path.display()))?;
+        let temp = manifest_path.with_extension("json.tmp");
+        fs::write(&temp, manifest.to_string()).with_context(|| format!("cannot write {}", temp.display()))?;
+        fs::rename(&temp, &manifest_path).with_context(|| format!("cannot write {}", manifest_path.display()))?;
+        Ok(())
+    }
+
+    /// Number of files and chunks in the index.
+    pub fn size(&self) -> (usize, usize) {
+        (self.files.len(), self.files.values().map(|file| file.chunks.len()).sum())
+    }
+
+    /// A line about the state of the index, for `/index`.
+    pub fn status(&self) -> String {
+        let (files, chunks) = self.size();
+        let mut status = format!("The index has {} chunks from {} files, embedded with {}.", chunks, files, self.model);
+        if let Some((done, total)) = self.progress {
+            status.push_str(&format!(" Updating: {} of {} files.", done, total));
+        }
+        if let Some(error) = &self.error {
+            status.push_str(&format!(" The last update failed: {}", error));
+        }
+        status
+    }
+}
+
+/// Embeds the files that changed since the last update and drops those that
+/// are gone. Returns `None` if another update is already running.
+pub async fn update(index: &SharedIndex, client: &OllamaClient) -> Result<Option<Update>> {
+    let (root, model, known) = {
+        let mut index = index.lock().unwrap();
+        if index.updating {
+            return Ok(None);
+        }
+        index.updating = true;
+        let known: HashMap<String, (u128, u64)> =
+            index.files.iter().map(|(path, file)| (path.clone(), (file.modified, file.size))).collect();
+        (index.root.clone(), index.model.clone(), known)
+    };
+    let result = embed_changes(index, client, &root, &model, known).await;
+    let mut index = index.lock().unwrap();
+    index.updating = false;
+    index.progress = None;
+    index.error = result.as_ref().err().map(|e| format!("{:#}", e));
+    if let Err(e) = index.save() {
+        logging::warn("index", "cannot save the index", json!({ "error": format!("{:#}", e) }));
+    }
+    result.map(Some)
+}
+
+async fn embed_changes(
+    index: &SharedIndex,
+    client: &OllamaClient,
+    root: &Path,
+    model: &str,
+    mut known: HashMap<String, (u128, u64)>,
+) -> Result<Update> {
+    let mut changed = Vec::new();
+    for entry in search::walker(root).flatten() {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let Ok(relative) = entry.path().strip_prefix(root) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let Some((modified, size)) = stamp(entry.path()) else {
+            continue;
+        };
+        if known.remove(&relative) != Some((modified, size)) {
+            changed.push((relative, modified, size));
+        }
+    }
+    // What is left was not found on disk
+    let removed = known.len();
+    {
+        let mut index = index.lock().unwrap();
+        for path in known.keys() {
+            index.files.remove(path);
+        }
+        index.progress = Some((0, changed.len()));
+    }
+    if changed.is_empty() {
+        return Ok(Update { embedded: 0, removed });
+    }
+    logging::info("index", "updating", json!({ "root": root.display().to_string(), "files": changed.len(), "removed": removed }));
+
+    // Chunks of several files go in one request; a file is added to the index
+    // once all of its chunks are embedded
+    let mut pending: Vec<(String, FileEntry)> = Vec::new();
+    let mut texts: Vec<String> = Vec::new();
+    let mut batches = 0;
+    let mut done = 0;
+    let total = changed.len();
+    for (i, (path, modified, size)) in changed.into_iter().enumerate() {
+        let content = if size <= MAX_FILE_BYTES { fs::read(root.join(&path)).ok() } else { None };
+        // Binary, non-UTF-8 and large files are recorded without chunks so
+        // they are not read again until they change
+        let text = content.filter(|bytes| !bytes.contains(&0)).and_then(|bytes| String::from_utf8(bytes).ok());
+        let mut entry = FileEntry { modified, size, chunks: Vec::new() };
+        if let Some(text) = text {
+            let lines: Vec<&str> = text.lines().collect();
+            for (start, end) in chunk_ranges(lines.len()) {
+                texts.push(chunk_text(&path, &lines, start, end));
+                entry.chunks.push(Chunk { start_line: start, end_line: end, vector: Vec::new() });
+            }
+        }
+        pending.push((path, entry));
+        if texts.len() < BATCH_SIZE && i + 1 < total {
+            continue;
+        }
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(BATCH_SIZE) {
+            vectors.extend(client.embed(model, batch).await?);
+        }
+        let mut vectors = vectors.into_iter();
+        let mut index = index.lock().unwrap();
+        for (path, mut entry) in pending.drain(..) {
+            for chunk in &mut entry.chunks {
+                chunk.vector = vectors.next().context("the embedding model returned fewer embeddings than chunks")?;
+                check_dimensions(&mut index, chunk.vector.len())?;
+            }
+            index.files.insert(path, entry);
+            done += 1;
+        }
+        texts.clear();
+        index.progress = Some((done, total));
+        batches += 1;
+        if batches % SAVE_EVERY == 0 {
+            index.save()?;
+        }
+    }
+    logging::info("index", "updated", json!({ "root": root.display().to_string(), "embedded": done, "removed": removed }));
+    Ok(Update { embedded: done, removed })
+}
+
+/// Records the length of the vectors in the index. Every vector must have the
+/// same length, or similarities between them mean nothing.
+fn check_dimensions(index: &mut Index, dimensions: usize) -> Result<()> {
+    if dimensions == 0 {
+        bail!("the embedding model returned an empty embedding");
+    }
+    if index.dimensions != 0 && index.dimensions != dimensions {
+        bail!("the embedding model returned {} dimensions but the index has {}", dimensions, index.dimensions);
+    }
+    index.dimensions = dimensions;
+    Ok(())
+}
+
+/// Updates the index in the background whenever files under its root change,
+/// for as long as the index is in use. After a failed update nothing happens
+/// until an explicit one clears the error.
+pub fn watch(index: &SharedIndex, client: &OllamaClient) -> Result<()> {
+    let root = index.lock().unwrap().root.clone();
+    let (sender, mut changes) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        let in_git = |path: &PathBuf| path.components().any(|c| c == Component::Normal(".git".as_ref()));
+        if !event.kind.is_access() && !event.paths.iter().all(in_git) {
+            let _ = sender.send(());
+        }
+    })
+    .context("cannot watch for file changes")?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .with_context(|| format!("cannot watch {}", root.display()))?;
+    let (index, client) = (Arc::downgrade(index), client.clone());
+    tokio::spawn(async move {
+        // Events stop when the watcher is dropped with the task
+        let _watcher = watcher;
+        while changes.recv().await.is_some() {
+            tokio::time::sleep(WATCH_DELAY).await;
+            while changes.try_recv().is_ok() {}
+            let Some(index) = index.upgrade() else {
+                break;
+            };
+            if index.lock().unwrap().error.is_some() {
+                continue;
+            }
+            loop {
+                match update(&index, &client).await {
+                    // A running update may have walked past the changed files
+                    Ok(None) => tokio::time::sleep(WATCH_DELAY).await,
+                    Ok(Some(_)) => break,
+                    Err(e) => {
+                        logging::warn("index", "cannot update the code index", json!({ "error": format!("{:#}", e) }));
+                        break;
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The `limit` chunks closest to `query`, best first.
+pub async fn search(index: &SharedIndex, client: &OllamaClient, query: &str, limit: usize) -> Result<Vec<Hit>> {
+    let (root, model) = {
+        let index = index.lock().unwrap();
+        if index.files.is_empty() {
+            match index.progress {
+                Some((done, total)) => bail!("the index is still being built ({} of {} files)", done, total),
+                None => bail!("the index is empty"),
+            }
+        }
+        (index.root.clone(), index.model.clone())
+    };
+    let query_vector = client.embed(&model, &[query.to_string()]).await?.pop().context("the embedding model returned no embedding for the query")?;
+    let mut scored: Vec<(f32, String, usize, usize)> = {
+        let index = index.lock().unwrap();
+        if query_vector.len() != index.dimensions {
+            bail!("the query embedding has {} dimensions but the index has {}", query_vector.len(), index.dimensions);
+        }
+        index
+            .files
+            .iter()
+            .flat_map(|(path, file)| file.chunks.iter().map(move |chunk| (path, chunk)))
+            .map(|(path, chunk)| (cosine(&query_vector, &chunk.vector), path.clone(), chunk.start_line, chunk.end_line))
+            .collect()
+    };
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    let mut hits = Vec::new();
+    for (score, path, start_line, end_line) in scored {
+        if hits.len() == limit {
+            break;
+        }
+        // Skip chunks that overlap a better one from the same file
+        let overlaps = hits.iter().any(|hit: &Hit| hit.path == path && start_line <= hit.end_line && hit.start_line <= end_line);
+        if overlaps {
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(root.join(&path)) else {
+            continue;
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        let end_line = end_line.min(lines.len());
+        if start_line > end_line {
+            continue;
+        }
+        let width = end_line.to_string().len();
+        let text = (start_line..=end_line)
+            .map(|n| format!("{:>width$}\t{}", n, lines[n - 1], width = width))
+            .collect::<Vec<_>>()
+            .join("\n");
+        hits.push(Hit { path, start_line, end_line, score, text });
+    }
+    Ok(hits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_index() -> Index {
+        Index {
+            dir: PathBuf::from("/data/index"),
+            root: PathBuf::from("/workspace"),
+            model: "nomic-embed-text".to_string(),
+            dimensions: 0,
+            files: BTreeMap::new(),
+            updating: false,
+            progress: None,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn chunks_overlap_and_cover_the_file() {
+        assert_eq!(chunk_ranges(0), vec![]);
+        assert_eq!(chunk_ranges(10), vec![(1, 10)]);
+        assert_eq!(chunk_ranges(120), vec![(1, 60), (51, 110), (101, 120)]);
+    }
+
+    #[test]
+    fn embeddings_must_keep_their_dimensions() {
+        let mut index = empty_index();
+        assert!(check_dimensions(&mut index, 0).is_err());
+        check_dimensions(&mut index, 768).unwrap();
+        check_dimensions(&mut index, 768).unwrap();
+        let err = check_dimensions(&mut index, 384).unwrap_err();
+        assert_eq!(err.to_string(), "the embedding model returned 384 dimensions but the index has 768");
+        assert_eq!(index.dimensions, 768);
+    }
+}
@@ -0,0 +1,151 @@
+//! Project instructions: `QUILL.md` or `AGENTS.md` files that tell the model
+//! about a codebase, such as its conventions, how to build and test it and
+//! what not to touch. They are read from the user's config directory (for
+//! every project) and from the working directory and each of its parents,
+//! and added to the system prompt.
+
+use crate::logging;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Names of instruction files, read in this order when a directory has both.
+pub const FILE_NAMES: [&str; 2] = ["QUILL.md", "AGENTS.md"];
+/// Longer files are cut so one file cannot fill the context window.
+const MAX_FILE_BYTES: usize = 32 * 1024;
+
+/// One instruction file.
+#[derive(Debug, Clone)]
+pub struct Instructions {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/// The instruction files that apply in `working_directory`, most general
+/// first: the user's, then those of the outermost parent down to the working
+/// directory itself.
+pub fn discover(working_directory: &Path) -> Vec<Instructions> {
+    let config_dir = dirs::config_dir().map(|dir| dir.join("quill"));
+    discover_from(config_dir.as_deref(), working_directory)
+}
+
+/// Like `discover`, with the user's instruction files in `config_dir`.
+pub fn discover_from(config_dir: Option<&Path>, working_directory: &Path) -> Vec<Instructions> {
+    let mut dirs: Vec<PathBuf> = config_dir.map(Path::to_path_buf).into_iter().collect();
+    let mut ancestors: Vec<PathBuf> = working_directory.ancestors().map(Path::to_path_buf).collect();
+    ancestors.reverse();
+    dirs.extend(ancestors);
+
+    let mut found: Vec<Instructions> = Vec::new();
+    for dir in dirs {
+        for name in FILE_NAMES {
+            let path = dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            // The config directory can also be a parent of the working directory
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+            if found.iter().any(|file| file.path.canonicalize().unwrap_or_else(|_| file.path.clone()) == canonical) {
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(text) if !text.trim().is_empty() => found.push(Instructions { text: truncate(text), path }),
+                Ok(_) => {}
+                Err(e) => {
+                    logging::warn("instructions", "cannot read an instruction file", json!({ "path": path.display().to_string(), "error": e.to_string() }));
+                }
+            }
+        }
+    }
+    found
+}
+
+fn truncate(mut text: String) -> String {
+    if text.len() <= MAX_FILE_BYTES {
+        return text;
+    }
+    let mut end = MAX_FILE_BYTES;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    text.push_str("\n[truncated]");
+    text
+}
+
+/// The part of the system prompt that holds the instruction files, or an
+/// empty string when there are none.
+pub fn prompt_section(files: &[Instructions]) -> String {
+    if files.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from(
+        "\n\nInstructions for this project, from the user and the repository. Follow them; \
+when they disagree, the later ones (closer to the working directory) win.",
+    );
+    for file in files {
+        section.push_str(&format!("\n\n--- {} ---\n{}", file.path.display(), file.text.trim_end()));
+    }
+    section
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("quill-test-{}-{}", name, uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// The paths found, relative to `dir`; files outside it are ignored.
+    fn found(config_dir: &Path, working_directory: &Path, dir: &Path) -> Vec<String> {
+        discover_from(Some(config_dir), working_directory)
+            .iter()
+            .filter_map(|file| file.path.strip_prefix(dir).ok())
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn discover_reads_the_config_dir_then_the_outermost_parent_first() {
+        let dir = temp_dir("instructions");
+        let project = dir.join("repo").join("crate");
+        fs::create_dir_all(&project).unwrap();
+        fs::create_dir_all(dir.join("config")).unwrap();
+        fs::write(dir.join("config").join("QUILL.md"), "user").unwrap();
+        fs::write(dir.join("AGENTS.md"), "outermost").unwrap();
+        fs::write(dir.join("repo").join("AGENTS.md"), "repo agents").unwrap();
+        fs::write(dir.join("repo").join("QUILL.md"), "repo quill").unwrap();
+        fs::write(project.join("QUILL.md"), "crate").unwrap();
+        fs::write(project.join("AGENTS.md"), " \n").unwrap();
+
+        let paths = found(&dir.join("config"), &project, &dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(paths, ["config/QUILL.md", "AGENTS.md", "repo/QUILL.md", "repo/AGENTS.md", "repo/crate/QUILL.md"]);
+    }
+
+    #[test]
+    fn discover_reads_a_config_dir_that_is_also_a_parent_once() {
+        let dir = temp_dir("instructions-parent");
+        let project = dir.join("project");
+        fs::create_dir_all(&project).unwrap();
+        fs::write(dir.join("QUILL.md"), "user").unwrap();
+        fs::write(project.join("QUILL.md"), "project").unwrap();
+
+        let paths = found(&dir, &project, &dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(paths, ["QUILL.md", "project/QUILL.md"]);
+    }
+
+    #[test]
+    fn truncate_cuts_at_a_char_boundary() {
+        assert_eq!(truncate("short".to_string()), "short");
+        let text = format!("{}é", "a".repeat(MAX_FILE_BYTES - 1));
+        let truncated = truncate(text);
+        assert_eq!(truncated, format!("{}\n[truncated]", "a".repeat(MAX_FILE_BYTES - 1)));
+    }
+}
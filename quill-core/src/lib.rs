@@ -0,0 +1,32 @@
+//! Quill's engine: a conversation with a local model that can read, search
+//! and change a codebase through tools, under the user's permission rules.
+//!
+//! `Conversation` is the entry point. The terminal interface, `quill -p` and
+//! `quill run --json` are frontends built on it, and other tools can embed it
+//! the same way: start one with `Options`, `submit` messages, `poll` while it
+//! is busy, decide pending tool calls, and follow along with `subscribe`.
+
+pub mod audit;
+pub mod checkpoint;
+pub mod commands;
+pub mod config;
+pub mod conversation;
+pub mod events;
+pub mod git;
+pub mod index;
+pub mod instructions;
+pub mod logging;
+pub mod mcp;
+pub mod memory;
+pub mod mentions;
+pub mod ollama;
+pub mod patch;
+pub mod plan;
+pub mod search;
+pub mod session;
+pub mod shell;
+pub mod tools;
+pub mod utils;
+
+pub use conversation::{Conversation, Options};
+pub use events::Event;
@@ -0,0 +1,205 @@
+//! Leveled, structured logging for diagnosing Quill itself.
+//!
+//! `QUILL_LOG` sets the level (`error`, `warn`, `info`, `debug`, `trace` or
+//! `off`). When it is set, records at that level and above are appended as
+//! JSON lines to `QUILL_LOG_FILE`, or `quill.log` in the data directory. The
+//! most recent records are also kept in memory for the log panel (F2), at
+//! `info` and above when `QUILL_LOG` is not set.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+/// Records kept for the log panel.
+const KEEP_RECENT: usize = 500;
+/// A log file larger than this is moved to `quill.log.old` at startup.
+const MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn parse(value: &str) -> Option<Option<Level>> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "off" | "none" | "0" => Some(None),
+            "error" => Some(Some(Level::Error)),
+            "warn" | "warning" => Some(Some(Level::Warn)),
+            "info" => Some(Some(Level::Info)),
+            "debug" => Some(Some(Level::Debug)),
+            "trace" | "all" => Some(Some(Level::Trace)),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub level: Level,
+    /// The part of Quill the record comes from, e.g. `ollama` or `tools`.
+    pub target: &'static str,
+    pub message: String,
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+impl Record {
+    /// The record as one line of text, fields as `key=value`.
+    pub fn line(&self) -> String {
+        let mut line = format!(
+            "{} {:<5} {} {}",
+            self.timestamp.with_timezone(&chrono::Local).format("%H:%M:%S%.3f"),
+            self.level.label(),
+            self.target,
+            self.message
+        );
+        for (key, value) in &self.fields {
+            match value {
+                Value::String(s) => line.push_str(&format!(" {}={:?}", key, s)),
+                value => line.push_str(&format!(" {}={}", key, value)),
+            }
+        }
+        line
+    }
+}
+
+struct Logger {
+    level: Option<Level>,
+    file: Option<File>,
+    recent: VecDeque<Record>,
+}
+
+static LOGGER: OnceLock<Mutex<Logger>> = OnceLock::new();
+
+/// Reads `QUILL_LOG` and opens the log file. Records logged before this are
+/// dropped.
+pub fn init() {
+    let mut warning = None;
+    let configured = std::env::var("QUILL_LOG").ok().filter(|v| !v.trim().is_empty());
+    let level = match configured.as_deref().map(|v| (v, Level::parse(v))) {
+        None => Some(Level::Info),
+        Some((_, Some(level))) => level,
+        Some((value, None)) => {
+            warning = Some(format!("QUILL_LOG={} is not a log level; using info", value));
+            Some(Level::Info)
+        }
+    };
+    let file = match configured.as_ref().and(level) {
+        Some(_) => match open_file() {
+            Ok(file) => Some(file),
+            Err(e) => {
+                warning = Some(format!("Cannot open the log file: {}", e));
+                None
+            }
+        },
+        None => None,
+    };
+    let _ = LOGGER.set(Mutex::new(Logger {
+        level,
+        file,
+        recent: VecDeque::new(),
+    }));
+    if let Some(warning) = warning {
+        warn("logging", &warning, Value::Null);
+    }
+}
+
+pub fn file_path() -> PathBuf {
+    match std::env::var_os("QUILL_LOG_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => crate::utils::data_dir().join("quill.log"),
+    }
+}
+
+fn open_file() -> std::io::Result<File> {
+    let path = file_path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if fs::metadata(&path).is_ok_and(|meta| meta.len() > MAX_FILE_BYTES) {
+        fs::rename(&path, path.with_extension("log.old"))?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Logs a record. `fields` should be a JSON object; anything else is stored
+/// under `value`.
+pub fn log(level: Level, target: &'static str, message: &str, fields: Value) {
+    let Some(mut logger) = LOGGER.get().and_then(|logger| logger.lock().ok()) else {
+        return;
+    };
+    if logger.level.is_none_or(|max| level > max) {
+        return;
+    }
+    let fields = match fields {
+        Value::Object(map) => map,
+        Value::Null => Map::new(),
+        value => Map::from_iter([("value".to_string(), value)]),
+    };
+    let record = Record {
+        timestamp: chrono::Utc::now(),
+        level,
+        target,
+        message: message.to_string(),
+        fields,
+    };
+    if let Some(file) = logger.file.as_mut() {
+        if let Ok(line) = serde_json::to_string(&record) {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+    if logger.recent.len() >= KEEP_RECENT {
+        logger.recent.pop_front();
+    }
+    logger.recent.push_back(record);
+}
+
+pub fn error(target: &'static str, message: &str, fields: Value) {
+    log(Level::Error, target, message, fields);
+}
+
+pub fn warn(target: &'static str, message: &str, fields: Value) {
+    log(Level::Warn, target, message, fields);
+}
+
+pub fn info(target: &'static str, message: &str, fields: Value) {
+    log(Level::Info, target, message, fields);
+}
+
+pub fn debug(target: &'static str, message: &str, fields: Value) {
+    log(Level::Debug, target, message, fields);
+}
+
+pub fn trace(target: &'static str, message: &str, fields: Value) {
+    log(Level::Trace, target, message, fields);
+}
+
+/// The last `count` records, oldest first.
+pub fn recent(count: usize) -> Vec<Record> {
+    let Some(logger) = LOGGER.get().and_then(|logger| logger.lock().ok()) else {
+        return Vec::new();
+    };
+    let skip = logger.recent.len().saturating_sub(count);
+    logger.recent.iter().skip(skip).cloned().collect()
+}
@@ -0,0 +1,426 @@
+//! Client for Model Context Protocol servers run over stdio.
+//!
+//! Each server listed under `mcp_servers` in the config is started when Quill
+//! starts. Its tools are offered to the model as `server.tool(...)` and its
+//! resources can be read with `mcp_read_resource`; both go through the same
+//! approval flow as the built-in tools.
+
+use crate::logging;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::oneshot;
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+/// How long a server gets to start up and list its tools.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(20);
+/// How long a single tool call or resource read may take.
+const CALL_TIMEOUT: Duration = Duration::from_secs(120);
+/// Results sent back to the model are cut to this size.
+const MAX_RESULT_BYTES: usize = 40 * 1024;
+/// Stderr kept from each server, shown if it fails to start.
+const MAX_STDERR_BYTES: usize = 2048;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Added to Quill's own environment.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpTool {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub input_schema: Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpResource {
+    pub uri: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Requests waiting for a response, or `None` once the server has exited.
+type Pending = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<Result<Value, String>>>>>>;
+
+/// A running server and the JSON-RPC connection to it.
+pub struct McpServer {
+    pub name: String,
+    pub tools: Vec<McpTool>,
+    pub resources: Vec<McpResource>,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+    pending: Pending,
+    next_id: AtomicU64,
+    stderr: Arc<Mutex<String>>,
+    // Killed when the server is dropped.
+    _child: Child,
+}
+
+impl McpServer {
+    async fn start(name: &str, config: &ServerConfig, working_directory: &Path) -> Result<Self> {
+        let mut child = Command::new(&config.command)
+            .args(&config.args)
+            .envs(&config.env)
+            .current_dir(working_directory)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("cannot start `{}`", config.command))?;
+        let stdin = Arc::new(tokio::sync::Mutex::new(child.stdin.take().ok_or_else(|| anyhow!("no stdin"))?));
+        let stdout = child.stdout.take().ok_or_else(|| anyhow!("no stdout"))?;
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        let stderr = Arc::new(Mutex::new(String::new()));
+        tokio::spawn(read_messages(BufReader::new(stdout), pending.clone(), stdin.clone()));
+        if let Some(err) = child.stderr.take() {
+            let stderr = stderr.clone();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(err).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let mut buf = stderr.lock().unwrap_or_else(|e| e.into_inner());
+                    buf.push_str(&line);
+                    buf.push('\n');
+                    if buf.len() > MAX_STDERR_BYTES {
+                        let cut = buf.len() - MAX_STDERR_BYTES;
+                        let cut = (cut..buf.len()).find(|i| buf.is_char_boundary(*i)).unwrap_or(0);
+                        buf.drain(..cut);
+                    }
+                }
+            });
+        }
+        let mut server = McpServer {
+            name: name.to_string(),
+            tools: Vec::new(),
+            resources: Vec::new(),
+            stdin,
+            pending,
+            next_id: AtomicU64::new(1),
+            stderr,
+            _child: child,
+        };
+        let init = server
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": { "name": "quill", "version": env!("CARGO_PKG_VERSION") },
+                }),
+                STARTUP_TIMEOUT,
+            )
+            .await
+            .map_err(|e| server.with_stderr(e))?;
+        server.notify("notifications/initialized", json!({})).await?;
+        let capabilities = init.get("capabilities").cloned().unwrap_or_default();
+        if capabilities.get("tools").is_some() {
+            server.tools = server.list("tools/list", "tools").await?;
+        }
+        if capabilities.get("resources").is_some() {
+            server.resources = server.list("resources/list", "resources").await?;
+        }
+        Ok(server)
+    }
+
+    fn with_stderr(&self, error: anyhow::Error) -> anyhow::Error {
+        let stderr = self.stderr.lock().map(|s| s.trim().to_string()).unwrap_or_default();
+        if stderr.is_empty() {
+            error
+        } else {
+            anyhow!("{:#}\nserver stderr:\n{}", error, stderr)
+        }
+    }
+
+    /// Fetches every page of a `*/list` method.
+    async fn list<T: for<'de> Deserialize<'de>>(&self, method: &str, key: &str) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let mut page = self.request(method, params, STARTUP_TIMEOUT).await?;
+            let list = page.get_mut(key).map(Value::take).unwrap_or_default();
+            items.extend(serde_json::from_value::<Vec<T>>(list).with_context(|| format!("invalid {} response", method))?);
+            cursor = page.get("nextCursor").and_then(Value::as_str).map(str::to_string);
+            if cursor.is_none() {
+                return Ok(items);
+            }
+        }
+    }
+
+    async fn send(&self, message: Value) -> Result<()> {
+        let mut line = serde_json::to_string(&message)?;
+        line.push('\n');
+        let mut stdin = self.stdin.lock().await;
+        stdin.write_all(line.as_bytes()).await?;
+        stdin.flush().await?;
+        Ok(())
+    }
+
+    async fn notify(&self, method: &str, params: Value) -> Result<()> {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params })).await
+    }
+
+    async fn request(&self, method: &str, params: Value, timeout: Duration) -> Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        match self.pending.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            Some(pending) => pending.insert(id, tx),
+            None => bail!("{}: server exited", method),
+        };
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .await
+            .with_context(|| format!("{}: server is not running", method))?;
+        let response = tokio::time::timeout(timeout, rx).await;
+        if let Some(pending) = self.pending.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+            pending.remove(&id);
+        }
+        match response {
+            Ok(Ok(result)) => result.map_err(|e| anyhow!("{}: {}", method, e)),
+            Ok(Err(_)) => bail!("{}: server exited", method),
+            Err(_) => bail!("{}: no response after {}s", method, timeout.as_secs()),
+        }
+    }
+}
+
+/// Routes responses to the requests waiting for them and answers requests
+/// from the server, which Quill does not support beyond `ping`.
+async fn read_messages(
+    stdout: BufReader<tokio::process::ChildStdout>,
+    pending: Pending,
+    stdin: Arc<tokio::sync::Mutex<ChildStdin>>,
+) {
+    let mut lines = stdout.lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        let id = message.get("id").cloned();
+        if let Some(method) = message.get("method").and_then(Value::as_str) {
+            let Some(id) = id else {
+                continue; // notification
+            };
+            let reply = if method == "ping" {
+                json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+            } else {
+                json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32601, "message": "method not supported by quill" } })
+            };
+            let mut stdin = stdin.lock().await;
+            let _ = stdin.write_all(format!("{}\n", reply).as_bytes()).await;
+            let _ = stdin.flush().await;
+            continue;
+        }
+        let Some(id) = id.as_ref().and_then(Value::as_u64) else {
+            continue;
+        };
+        let result = match message.get("error") {
+            Some(error) => Err(error.get("message").and_then(Value::as_str).unwrap_or("unknown error").to_string()),
+            None => Ok(message.get("result").cloned().unwrap_or_default()),
+        };
+        if let Some(tx) = pending.lock().unwrap_or_else(|e| e.into_inner()).as_mut().and_then(|p| p.remove(&id)) {
+            let _ = tx.send(result);
+        }
+    }
+    // Dropping the senders fails every request still waiting.
+    pending.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+/// All connected servers.
+#[derive(Default)]
+pub struct McpServers {
+    pub servers: Vec<McpServer>,
+}
+
+impl McpServers {
+    /// Starts every configured server in parallel. Servers that fail to start
+    /// are left out and their errors returned.
+    pub async fn start(configs: &HashMap<String, ServerConfig>, working_directory: &Path) -> (Self, Vec<String>) {
+        let mut names: Vec<&String> = configs.keys().collect();
+        names.sort();
+        let started = futures::future::join_all(
+            names.iter().map(|name| McpServer::start(name, &configs[*name], working_directory)),
+        )
+        .await;
+        let mut servers = Vec::new();
+        let mut errors = Vec::new();
+        for (name, result) in names.into_iter().zip(started) {
+            match result {
+                Ok(server) => {
+                    logging::info(
+                        "mcp",
+                        "server started",
+                        json!({ "server": name, "tools": server.tools.len(), "resources": server.resources.len() }),
+                    );
+                    servers.push(server);
+                }
+                Err(e) => {
+                    logging::warn("mcp", "server failed to start", json!({ "server": name, "error": format!("{:#}", e) }));
+                    errors.push(format!("MCP server `{}` failed to start: {:#}", name, e));
+                }
+            }
+        }
+        (McpServers { servers }, errors)
+    }
+
+    fn server(&self, name: &str) -> Result<&McpServer, String> {
+        self.servers
+            .iter()
+            .find(|s| s.name == name)
+            .ok_or_else(|| format!("no MCP server named `{}` is connected", name))
+    }
+
+    /// Calls `server.tool` and formats the result for the model.
+    pub async fn call_tool(&self, name: &str, arguments: &Map<String, Value>) -> String {
+        let result = async {
+            let (server, tool) = name.split_once('.').ok_or("expected server.tool")?;
+            let server = self.server(server)?;
+            if !server.tools.iter().any(|t| t.name == tool) {
+                return Err(format!("server `{}` has no tool `{}`", server.name, tool));
+            }
+            let result = server
+                .request("tools/call", json!({ "name": tool, "arguments": arguments }), CALL_TIMEOUT)
+                .await
+                .map_err(|e| format!("{:#}", e))?;
+            let text = content_text(result.get("content"));
+            if result.get("isError").and_then(Value::as_bool) == Some(true) {
+                Err(text)
+            } else {
+                Ok(text)
+            }
+        }
+        .await;
+        match result {
+            Ok(text) => format!("[TOOL RESULT: {}]\n---\n{}", name, truncate(&text)),
+            Err(e) => format!("[TOOL ERROR: {}]\nError: {}", name, truncate(&e)),
+        }
+    }
+
+    pub async fn read_resource(&self, server: &str, uri: &str) -> String {
+        let result = async {
+            let server = self.server(server)?;
+            let result = server
+                .request("resources/read", json!({ "uri": uri }), CALL_TIMEOUT)
+                .await
+                .map_err(|e| format!("{:#}", e))?;
+            Ok::<_, String>(content_text(result.get("contents")))
+        }
+        .await;
+        match result {
+            Ok(text) => format!("[TOOL RESULT: mcp_read_resource]\nResource: {}\n---\n{}", uri, truncate(&text)),
+            Err(e) => format!("[TOOL ERROR: mcp_read_resource]\nResource: {}\nError: {}", uri, e),
+        }
+    }
+
+    /// Describes the servers' tools and resources for the system prompt.
+    pub fn prompt_section(&self) -> String {
+        if self.servers.is_empty() {
+            return String::new();
+        }
+        let mut out = String::from(
+            "\n\nExternal tools from MCP servers (call them by their full name with named arguments, e.g. [tool_call: server.tool(arg=\"value\")]):\n",
+        );
+        let mut resources = Vec::new();
+        for server in &self.servers {
+            for tool in &server.tools {
+                out.push_str(&format!(
+                    "  - {}.{}({}): {}\n",
+                    server.name,
+                    tool.name,
+                    signature(&tool.input_schema),
+                    tool.description.as_deref().unwrap_or("").trim()
+                ));
+            }
+            for resource in &server.resources {
+                let about = resource.description.as_deref().or(resource.name.as_deref()).unwrap_or("");
+                resources.push(format!("  - {}: {} {}\n", server.name, resource.uri, about));
+            }
+        }
+        if !resources.is_empty() {
+            out.push_str("  - mcp_read_resource(server: str, uri: str): Reads one of these resources:\n");
+            for resource in resources {
+                out.push_str(&format!("  {}", resource));
+            }
+        }
+        out
+    }
+}
+
+/// `name: type` pairs from a tool's JSON schema, with `= none` on optional ones.
+fn signature(schema: &Value) -> String {
+    let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+        return String::new();
+    };
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .map(|r| r.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    properties
+        .iter()
+        .map(|(name, property)| {
+            let kind = property.get("type").and_then(Value::as_str).unwrap_or("any");
+            if required.contains(&name.as_str()) {
+                format!("{}: {}", name, kind)
+            } else {
+                format!("{}: {} = none", name, kind)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Joins the text parts of a `content` or `contents` array; other parts are
+/// described by their type.
+fn content_text(content: Option<&Value>) -> String {
+    let Some(items) = content.and_then(Value::as_array) else {
+        return String::new();
+    };
+    items
+        .iter()
+        .map(|item| {
+            if let Some(text) = item.get("text").and_then(Value::as_str) {
+                return text.to_string();
+            }
+            if let Some(resource) = item.get("resource") {
+                return content_text(Some(&Value::Array(vec![resource.clone()])));
+            }
+            let kind = item.get("type").and_then(Value::as_str).unwrap_or("binary");
+            let mime = item.get("mimeType").and_then(Value::as_str).unwrap_or("unknown type");
+            format!("[{} content not shown: {}]", kind, mime)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn truncate(text: &str) -> String {
+    if text.len() <= MAX_RESULT_BYTES {
+        return text.to_string();
+    }
+    let mut end = MAX_RESULT_BYTES;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}\n[truncated: {} more bytes]", &text[..end], text.len() - end)
+}
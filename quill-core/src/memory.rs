@@ -0,0 +1,378 @@
+//! Long-term memory: short facts worth knowing in later sessions, such as
+//! "tests need a running Postgres" or "the user prefers tabs". The model saves
+//! them with the `remember` tool and the user with `/remember`.
+//!
+//! Project memories belong to one working directory; global ones apply
+//! everywhere. Both are JSON files under the data directory. Before each
+//! message, the memories that share the most words with it are added to it.
+
+use crate::logging;
+use crate::utils::path_key;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Memories added to one message at most.
+pub const MAX_RECALLED: usize = 5;
+/// Longer memories are refused; they should be facts, not documents.
+const MAX_MEMORY_CHARS: usize = 500;
+/// Words too common to say anything about relevance.
+const STOP_WORDS: &[&str] = &[
+    "the", "and", "for", "are", "but", "not", "you", "all", "any", "can", "had", "her", "was", "one", "our", "out",
+    "has", "have", "this", "that", "with", "from", "they", "will", "would", "there", "their", "what", "when",
+    "which", "into", "than", "then", "them", "these", "those", "some", "use", "using", "should", "could", "about",
+    "does", "how", "why", "its", "also", "just", "only", "please", "make", "like",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Only for the working directory it was saved in.
+    Project,
+    /// For every project.
+    Global,
+}
+
+impl Scope {
+    pub fn label(self) -> &'static str {
+        match self {
+            Scope::Project => "project",
+            Scope::Global => "global",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Scope> {
+        match name {
+            "project" => Some(Scope::Project),
+            "global" => Some(Scope::Global),
+            _ => None,
+        }
+    }
+}
+
+/// Who saved a memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Author {
+    User,
+    Model,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Memory {
+    pub id: String,
+    pub text: String,
+    pub scope: Scope,
+    pub author: Author,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub updated: chrono::DateTime<chrono::Utc>,
+}
+
+/// What a memory file holds.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct MemoryFile {
+    /// For project memories, the directory they belong to.
+    #[serde(default)]
+    working_directory: Option<String>,
+    memories: Vec<Memory>,
+}
+
+/// The project and global memories for one working directory.
+#[derive(Debug)]
+pub struct MemoryStore {
+    working_directory: String,
+    project_file: PathBuf,
+    global_file: PathBuf,
+    /// Project memories first, then global ones, each oldest first.
+    pub memories: Vec<Memory>,
+}
+
+fn read(path: &Path) -> Vec<Memory> {
+    match fs::read_to_string(path) {
+        Ok(text) => match serde_json::from_str::<MemoryFile>(&text) {
+            Ok(file) => file.memories,
+            Err(e) => {
+                logging::warn("memory", "cannot parse a memory file", json!({ "path": path.display().to_string(), "error": e.to_string() }));
+                Vec::new()
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            logging::warn("memory", "cannot read a memory file", json!({ "path": path.display().to_string(), "error": e.to_string() }));
+            Vec::new()
+        }
+    }
+}
+
+fn write(path: &Path, file: &MemoryFile) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
+    }
+    // Write to a temporary file first so a crash never loses the memories
+    let temp = path.with_extension("json.tmp");
+    fs::write(&temp, serde_json::to_string_pretty(file)?).with_context(|| format!("cannot write {}", temp.display()))?;
+    fs::rename(&temp, path).with_context(|| format!("cannot write {}", path.display()))?;
+    Ok(())
+}
+
+/// The words of `text` that say something about what it is about.
+fn keywords(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .map(str::to_lowercase)
+        .filter(|word| word.chars().count() >= 3 && !STOP_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+impl MemoryStore {
+    /// Reads the memories for `working_directory` from `data_dir`.
+    /// Unreadable files are logged and treated as empty.
+    pub fn load(data_dir: &Path, working_directory: &str) -> Self {
+        let dir = data_dir.join("memory");
+        let project_file = dir.join("projects").join(format!("{}.json", path_key(working_directory)));
+        let global_file = dir.join("global.json");
+        let mut memories = read(&project_file);
+        memories.extend(read(&global_file));
+        MemoryStore { working_directory: working_directory.to_string(), project_file, global_file, memories }
+    }
+
+    /// Writes the file of the memories in `scope`; the other one is left alone.
+    fn save(&self, scope: Scope) -> Result<()> {
+        let path = match scope {
+            Scope::Project => &self.project_file,
+            Scope::Global => &self.global_file,
+        };
+        let file = MemoryFile {
+            working_directory: (scope == Scope::Project).then(|| self.working_directory.clone()),
+            memories: self.memories.iter().filter(|memory| memory.scope == scope).cloned().collect(),
+        };
+        write(path, &file)
+    }
+
+    /// Where a new memory in `scope` goes, keeping project memories before
+    /// global ones.
+    fn insert_position(&self, scope: Scope) -> usize {
+        match scope {
+            Scope::Project => self.memories.iter().position(|m| m.scope == Scope::Global).unwrap_or(self.memories.len()),
+            Scope::Global => self.memories.len(),
+        }
+    }
+
+    /// The memory whose ID starts with `id`, if exactly one does.
+    pub fn find(&self, id: &str) -> Result<usize> {
+        let matches: Vec<usize> = (0..self.memories.len()).filter(|&i| self.memories[i].id.starts_with(id)).collect();
+        match matches.as_slice() {
+            [index] => Ok(*index),
+            [] => bail!("no memory {}", id),
+            _ => bail!("{} memories start with {}; give more of the ID", matches.len(), id),
+        }
+    }
+
+    /// Saves a new memory. Saving the same text again returns the existing one.
+    pub fn add(&mut self, text: &str, scope: Scope, author: Author) -> Result<&Memory> {
+        let text = text.trim();
+        if text.is_empty() {
+            bail!("nothing to remember");
+        }
+        if text.chars().count() > MAX_MEMORY_CHARS {
+            bail!("a memory can be at most {} characters; save the key fact only", MAX_MEMORY_CHARS);
+        }
+        if let Some(index) = self.memories.iter().position(|memory| memory.scope == scope && memory.text == text) {
+            return Ok(&self.memories[index]);
+        }
+        let now = chrono::Utc::now();
+        let memory = Memory {
+            id: uuid::Uuid::new_v4().simple().to_string()[..8].to_string(),
+            text: text.to_string(),
+            scope,
+            author,
+            created: now,
+            updated: now,
+        };
+        let index = self.insert_position(scope);
+        self.memories.insert(index, memory);
+        if let Err(e) = self.save(scope) {
+            self.memories.remove(index);
+            return Err(e);
+        }
+        Ok(&self.memories[index])
+    }
+
+    /// Replaces the text of the memory at `index`.
+    pub fn edit(&mut self, index: usize, text: &str) -> Result<()> {
+        let text = text.trim();
+        if text.is_empty() {
+            bail!("a memory cannot be empty; delete it instead");
+        }
+        let memory = &mut self.memories[index];
+        memory.text = text.to_string();
+        memory.updated = chrono::Utc::now();
+        let scope = memory.scope;
+        self.save(scope)
+    }
+
+    /// Moves the memory at `index` between the project and global files.
+    /// Returns its new index. If either file cannot be written, the memory
+    /// stays where it was.
+    pub fn toggle_scope(&mut self, index: usize) -> Result<usize> {
+        let previous = self.memories.clone();
+        let mut memory = self.memories.remove(index);
+        memory.scope = match memory.scope {
+            Scope::Project => Scope::Global,
+            Scope::Global => Scope::Project,
+        };
+        memory.updated = chrono::Utc::now();
+        let new_index = self.insert_position(memory.scope);
+        self.memories.insert(new_index, memory);
+        if let Err(e) = self.save(Scope::Project).and_then(|()| self.save(Scope::Global)) {
+            // The project file may already have been written without it
+            self.memories = previous;
+            let _ = self.save(Scope::Project);
+            return Err(e);
+        }
+        Ok(new_index)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<Memory> {
+        let memory = self.memories.remove(index);
+        self.save(memory.scope)?;
+        Ok(memory)
+    }
+
+    /// The memories most relevant to `text`, best first: those sharing the
+    /// most keywords with it, where words few memories use count for more.
+    /// Project memories win ties.
+    pub fn relevant(&self, text: &str, limit: usize) -> Vec<&Memory> {
+        let query = keywords(text);
+        if query.is_empty() {
+            return Vec::new();
+        }
+        let memory_words: Vec<HashSet<String>> = self.memories.iter().map(|memory| keywords(&memory.text)).collect();
+        let mut scored: Vec<(f64, usize)> = memory_words
+            .iter()
+            .enumerate()
+            .filter_map(|(i, words)| {
+                let score: f64 = words
+                    .intersection(&query)
+                    .map(|word| {
+                        let used_by = memory_words.iter().filter(|other| other.contains(word)).count();
+                        1.0 / used_by as f64
+                    })
+                    .sum();
+                (score > 0.0).then_some((score, i))
+            })
+            .collect();
+        scored.sort_by(|a, b| {
+            b.0.total_cmp(&a.0)
+                .then_with(|| (self.memories[a.1].scope == Scope::Global).cmp(&(self.memories[b.1].scope == Scope::Global)))
+        });
+        scored.into_iter().take(limit).map(|(_, i)| &self.memories[i]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROJECT: &str = "/work/project";
+
+    fn store(name: &str) -> (PathBuf, MemoryStore) {
+        let dir = std::env::temp_dir().join(format!("quill-test-{}-{}", name, uuid::Uuid::new_v4().simple()));
+        let store = MemoryStore::load(&dir, PROJECT);
+        (dir, store)
+    }
+
+    fn memory(id: &str, text: &str, scope: Scope) -> Memory {
+        let now = chrono::Utc::now();
+        Memory { id: id.to_string(), text: text.to_string(), scope, author: Author::User, created: now, updated: now }
+    }
+
+    #[test]
+    fn add_saves_once_and_limits_the_length() {
+        let (dir, mut store) = store("memory-add");
+
+        let id = store.add("  tests need Postgres ", Scope::Project, Author::User).unwrap().id.clone();
+        assert_eq!(store.add("tests need Postgres", Scope::Project, Author::Model).unwrap().id, id);
+        store.add("tests need Postgres", Scope::Global, Author::User).unwrap();
+        assert!(store.add(" ", Scope::Project, Author::User).is_err());
+        let err = store.add(&"x".repeat(MAX_MEMORY_CHARS + 1), Scope::Project, Author::User).unwrap_err();
+        assert!(err.to_string().contains("at most"), "{}", err);
+
+        let reloaded = MemoryStore::load(&dir, PROJECT);
+        let saved: Vec<(&str, Scope)> = reloaded.memories.iter().map(|m| (m.text.as_str(), m.scope)).collect();
+        assert_eq!(saved, [("tests need Postgres", Scope::Project), ("tests need Postgres", Scope::Global)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn add_forgets_the_memory_when_saving_fails() {
+        let (dir, mut store) = store("memory-add-fails");
+        fs::create_dir_all(dir.join("memory")).unwrap();
+        fs::write(dir.join("memory").join("projects"), "not a directory").unwrap();
+
+        assert!(store.add("tests need Postgres", Scope::Project, Author::User).is_err());
+
+        assert!(store.memories.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn find_needs_an_unambiguous_prefix() {
+        let (_dir, mut store) = store("memory-find");
+        store.memories = vec![memory("abc123", "one", Scope::Project), memory("abd456", "two", Scope::Global)];
+
+        assert_eq!(store.find("abd").unwrap(), 1);
+        let err = store.find("ab").unwrap_err();
+        assert!(err.to_string().contains("2 memories start with ab"), "{}", err);
+        assert!(store.find("x").is_err());
+    }
+
+    #[test]
+    fn toggle_scope_moves_the_memory_between_files() {
+        let (dir, mut store) = store("memory-toggle");
+        store.add("uses tabs", Scope::Global, Author::User).unwrap();
+        store.add("tests need Postgres", Scope::Project, Author::User).unwrap();
+
+        assert_eq!(store.toggle_scope(1).unwrap(), 1);
+
+        let reloaded = MemoryStore::load(&dir, PROJECT);
+        let saved: Vec<(&str, Scope)> = reloaded.memories.iter().map(|m| (m.text.as_str(), m.scope)).collect();
+        assert_eq!(saved, [("tests need Postgres", Scope::Project), ("uses tabs", Scope::Project)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn toggle_scope_rolls_back_when_saving_fails() {
+        let (dir, mut store) = store("memory-toggle-fails");
+        store.add("tests need Postgres", Scope::Project, Author::User).unwrap();
+        // Renaming the new global file over a directory fails
+        fs::create_dir_all(dir.join("memory").join("global.json")).unwrap();
+
+        assert!(store.toggle_scope(0).is_err());
+
+        assert_eq!(store.memories[0].scope, Scope::Project);
+        let reloaded = MemoryStore::load(&dir, PROJECT);
+        assert_eq!(reloaded.memories.len(), 1);
+        assert_eq!(reloaded.memories[0].scope, Scope::Project);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn relevant_prefers_rare_words_then_project_memories() {
+        let (_dir, mut store) = store("memory-relevant");
+        store.memories = vec![
+            memory("1", "the database runs in docker", Scope::Project),
+            memory("2", "docker images are built by make", Scope::Global),
+            memory("3", "postgres database migrations live in db/", Scope::Global),
+            memory("4", "postgres database migrations are checked in CI", Scope::Project),
+        ];
+
+        let ids = |text: &str, limit: usize| store.relevant(text, limit).iter().map(|m| m.id.clone()).collect::<Vec<_>>();
+
+        assert_eq!(ids("how do the postgres migrations work?", 5), ["4", "3"]);
+        assert_eq!(ids("start docker", 1), ["1"]);
+        assert!(ids("and the", 5).is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+//! `@path` mentions: `explain @src/app.rs` or `@src/app.rs:120-180` attaches
+//! the file, or those lines, to the message so the model does not have to ask
+//! for it with `read_file`. Typing the mention is the approval; deny rules in
+//! the permissions still apply, and files outside the workspace are not
+//! attached.
+
+use crate::config::{Action, Config};
+use crate::search;
+use crate::tools::{self, ToolCall};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Files attached to one message at most.
+const MAX_ATTACHMENTS: usize = 10;
+/// Files offered for completion at most, so huge trees stay responsive.
+const MAX_FILES: usize = 20_000;
+/// Entries in the completion popup.
+const MAX_COMPLETIONS: usize = 10;
+
+/// A file mentioned in a message, with the lines asked for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mention {
+    pub path: String,
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+}
+
+impl Mention {
+    /// `src/app.rs` or `src/app.rs (lines 120-180)`.
+    pub fn label(&self) -> String {
+        match (self.start_line, self.end_line) {
+            (Some(start), Some(end)) if start == end => format!("{} (line {})", self.path, start),
+            (Some(start), Some(end)) => format!("{} (lines {}-{})", self.path, start, end),
+            (Some(start), None) => format!("{} (from line {})", self.path, start),
+            _ => self.path.clone(),
+        }
+    }
+}
+
+/// Reads `:120-180`, `:120-` or `:120` off the end of a mention.
+fn split_range(mention: &str) -> (&str, Option<usize>, Option<usize>) {
+    let Some((path, range)) = mention.rsplit_once(':') else {
+        return (mention, None, None);
+    };
+    let lines = match range.split_once('-') {
+        Some((start, "")) => start.parse().ok().map(|start| (start, None)),
+        Some((start, end)) => start.parse().ok().zip(end.parse().ok()).map(|(start, end)| (start, Some(end))),
+        None => range.parse().ok().map(|line| (line, Some(line))),
+    };
+    match lines {
+        Some((start, end)) if !path.is_empty() => (path, Some(start), end),
+        _ => (mention, None, None),
+    }
+}
+
+/// The `@` mentions in `text`, in order and without repeats. A mention starts
+/// a word; `user@host` is not one.
+pub fn parse(text: &str) -> Vec<Mention> {
+    let mut mentions: Vec<Mention> = Vec::new();
+    for word in text.split_whitespace() {
+        let Some(mention) = word.strip_prefix('@') else {
+            continue;
+        };
+        // Punctuation after a mention ends the sentence, not the path
+        let mention = mention.trim_end_matches([',', '.', ':', ';', '!', '?', ')', '"', '\'', '`']);
+        let (path, start_line, end_line) = split_range(mention);
+        if path.is_empty() {
+            continue;
+        }
+        let mention = Mention { path: path.to_string(), start_line, end_line };
+        if !mentions.contains(&mention) {
+            mentions.push(mention);
+        }
+    }
+    mentions
+}
+
+/// Reads the files mentioned in `text`. Returns them as text to append to the
+/// message, and a note for each mention to show the user what was attached.
+pub fn attach(text: &str, working_directory: &Path, config: &Config, session_allowed: &HashSet<String>) -> (String, Vec<String>) {
+    let mut attachments = String::new();
+    let mut notes = Vec::new();
+    for (i, mention) in parse(text).into_iter().enumerate() {
+        if i == MAX_ATTACHMENTS {
+            notes.push(format!("Attached only the first {} mentioned files.", MAX_ATTACHMENTS));
+            break;
+        }
+        let path = match tools::resolve_in_workspace(working_directory, &mention.path) {
+            Ok(path) => path,
+            Err(_) => {
+                notes.push(format!("Did not attach @{}: it is outside the working directory.", mention.path));
+                continue;
+            }
+        };
+        if !path.is_file() {
+            let reason = if path.is_dir() { "it is a directory" } else { "no such file" };
+            notes.push(format!("Did not attach @{}: {}.", mention.path, reason));
+            continue;
+        }
+        let read = ToolCall::ReadFile {
+            path: mention.path.clone(),
+            start_line: mention.start_line,
+            end_line: mention.end_line,
+        };
+        let decision = config.permissions.decide(&read, working_directory, session_allowed);
+        if decision.action == Action::Deny {
+            notes.push(format!("Did not attach @{}: denied by {}.", mention.path, decision.reason));
+            continue;
+        }
+        match tools::read_file(&path, mention.start_line, mention.end_line) {
+            Ok(content) => {
+                attachments.push_str(&format!("\n\n[ATTACHED FILE: {}]\n{}", mention.label(), content.trim_end()));
+                notes.push(format!("Attached {}.", mention.label()));
+            }
+            Err(e) => notes.push(format!("Did not attach @{}: {}.", mention.path, e)),
+        }
+    }
+    (attachments, notes)
+}
+
+/// Files under `working_directory` that can be mentioned, relative to it.
+/// Ignored and hidden files are left out, as in `glob`.
+pub fn files(working_directory: &Path) -> Vec<String> {
+    search::walker(working_directory)
+        .flatten()
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter_map(|entry| {
+            let relative = entry.path().strip_prefix(working_directory).ok()?;
+            Some(relative.to_string_lossy().replace('\\', "/"))
+        })
+        .take(MAX_FILES)
+        .collect()
+}
+
+/// Scores `path` for `query` typed after `@`: the query's characters must
+/// appear in order. Runs of adjacent characters, matches at the start of a
+/// path segment or word, and matches in the file name score higher.
+fn score(query: &str, path: &str) -> Option<i64> {
+    let lower = path.to_lowercase();
+    let file_name_start = lower.rfind('/').map_or(0, |i| i + 1);
+    let mut chars = lower.char_indices();
+    let mut previous: Option<usize> = None;
+    let mut score = 0;
+    for wanted in query.to_lowercase().chars() {
+        let (index, _) = chars.by_ref().find(|&(_, c)| c == wanted)?;
+        score += 1;
+        if previous.is_some_and(|previous| previous + 1 == index) {
+            score += 5;
+        }
+        let at_boundary = index == 0 || lower[..index].ends_with(['/', '_', '-', '.']);
+        if at_boundary {
+            score += 8;
+        }
+        if index >= file_name_start {
+            score += 2;
+        }
+        previous = Some(index);
+    }
+    if lower[file_name_start..].contains(&query.to_lowercase()) {
+        score += 20;
+    }
+    Some(score * 10 - path.len() as i64)
+}
+
+/// The best matches for `query` among `files`, best first.
+pub fn complete(query: &str, files: &[String]) -> Vec<String> {
+    let mut scored: Vec<(i64, &String)> = files
+        .iter()
+        .filter_map(|path| score(query, path).map(|score| (score, path)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+    scored.into_iter().take(MAX_COMPLETIONS).map(|(_, path)| path.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mention(path: &str, start_line: Option<usize>, end_line: Option<usize>) -> Mention {
+        Mention { path: path.to_string(), start_line, end_line }
+    }
+
+    #[test]
+    fn split_range_reads_line_ranges() {
+        assert_eq!(split_range("src/app.rs:10-20"), ("src/app.rs", Some(10), Some(20)));
+        assert_eq!(split_range("src/app.rs:10-"), ("src/app.rs", Some(10), None));
+        assert_eq!(split_range("src/app.rs:10"), ("src/app.rs", Some(10), Some(10)));
+        assert_eq!(split_range("src/app.rs"), ("src/app.rs", None, None));
+        assert_eq!(split_range("src/app.rs:"), ("src/app.rs:", None, None));
+        assert_eq!(split_range("c:/notes:x"), ("c:/notes:x", None, None));
+        assert_eq!(split_range(":10"), (":10", None, None));
+    }
+
+    #[test]
+    fn parse_finds_mentions_with_ranges() {
+        assert_eq!(
+            parse("compare @src/app.rs:10-20 with @src/ui.rs, and @src/app.rs:10-20 again"),
+            vec![mention("src/app.rs", Some(10), Some(20)), mention("src/ui.rs", None, None)]
+        );
+    }
+
+    #[test]
+    fn parse_drops_trailing_colons_and_punctuation() {
+        assert_eq!(parse("look at @src/app.rs: it fails"), vec![mention("src/app.rs", None, None)]);
+        assert_eq!(parse("see @src/app.rs:12: here"), vec![mention("src/app.rs", Some(12), Some(12))]);
+        assert_eq!(parse("(in @README.md)."), vec![mention("README.md", None, None)]);
+    }
+
+    #[test]
+    fn parse_ignores_email_like_text() {
+        assert!(parse("mail me at user@example.com or @ alone").is_empty());
+        assert!(parse("@: @.").is_empty());
+    }
+
+    #[test]
+    fn attach_skips_files_outside_the_workspace() {
+        let dir = std::env::temp_dir().join(format!("quill-test-mentions-{}", uuid::Uuid::new_v4()));
+        let workspace = dir.join("workspace");
+        std::fs::create_dir_all(&workspace).unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        std::fs::write(workspace.join("notes.txt"), "notes").unwrap();
+        let (attachments, notes) = attach(
+            "@notes.txt @../secret.txt",
+            &workspace,
+            &Config::default(),
+            &HashSet::new(),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(attachments.contains("[ATTACHED FILE: notes.txt]"), "{}", attachments);
+        assert!(!attachments.contains("secret"), "{}", attachments);
+        assert_eq!(notes[1], "Did not attach @../secret.txt: it is outside the working directory.");
+    }
+}
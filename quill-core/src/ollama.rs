@@ -0,0 +1,196 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use futures::stream::Stream;
+use futures::stream::StreamExt;
+use std::pin::Pin;
+use crate::conversation::ChatTurn;
+use crate::logging;
+use serde_json::{json, Map, Value};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Model {
+    pub name: String,
+    pub modified_at: DateTime<Utc>,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    options: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ModelsResponse {
+    models: Vec<Model>,
+}
+
+/// One line of a streamed chat response.
+#[derive(Debug, Clone, Default)]
+pub struct ChatChunk {
+    /// Text added to the reply; may be empty.
+    pub content: String,
+    pub done: bool,
+    pub done_reason: Option<String>,
+    pub prompt_tokens: u64,
+    pub reply_tokens: u64,
+    /// Set when Ollama reports an error instead of a reply.
+    pub error: Option<String>,
+}
+
+impl ChatChunk {
+    pub fn parse(line: &str) -> serde_json::Result<Self> {
+        let json: serde_json::Value = serde_json::from_str(line)?;
+        let count = |key: &str| json.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+        Ok(ChatChunk {
+            content: json
+                .get("message")
+                .and_then(|m| m.get("content"))
+                .or_else(|| json.get("response"))
+                .and_then(|c| c.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            done: json.get("done").and_then(|d| d.as_bool()) == Some(true),
+            done_reason: json.get("done_reason").and_then(|r| r.as_str()).map(str::to_string),
+            prompt_tokens: count("prompt_eval_count"),
+            reply_tokens: count("eval_count"),
+            error: json.get("error").map(|e| e.as_str().map_or_else(|| e.to_string(), str::to_string)),
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct OllamaClient {
+    client: Client,
+    base_url: String,
+}
+
+impl OllamaClient {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    pub async fn list_models(&self) -> Result<Vec<Model>> {
+        let url = format!("{}/api/tags", self.base_url);
+        let started = Instant::now();
+        let response = match self.client.get(&url).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                logging::warn("ollama", "cannot list models", json!({ "url": url, "error": e.to_string() }));
+                return Err(e.into());
+            }
+        };
+        
+        if response.status().is_success() {
+            let models_response: ModelsResponse = response.json().await?;
+            logging::debug(
+                "ollama",
+                "listed models",
+                json!({ "count": models_response.models.len(), "elapsed_ms": started.elapsed().as_millis() as u64 }),
+            );
+            Ok(models_response.models)
+        } else {
+            logging::warn("ollama", "cannot list models", json!({ "url": url, "status": response.status().as_u16() }));
+            Err(anyhow::anyhow!("Failed to fetch models: {}", response.status()))
+        }
+    }
+
+    /// Embeds each of `inputs` with an embedding model such as `nomic-embed-text`.
+    pub async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let url = format!("{}/api/embed", self.base_url);
+        let started = Instant::now();
+        let response = self.client.post(&url).json(&EmbedRequest { model, input: inputs }).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            // Ollama explains the problem, e.g. that the model is not pulled
+            let body = response.text().await.unwrap_or_default();
+            let message = serde_json::from_str::<Value>(&body)
+                .ok()
+                .and_then(|json| json.get("error").and_then(|e| e.as_str()).map(str::to_string))
+                .unwrap_or(body);
+            logging::warn("ollama", "embed request failed", json!({ "model": model, "status": status.as_u16(), "error": message }));
+            return Err(anyhow::anyhow!("cannot embed with {}: {}", model, message));
+        }
+        let embeddings = response.json::<EmbedResponse>().await?.embeddings;
+        if embeddings.len() != inputs.len() {
+            return Err(anyhow::anyhow!("{} returned {} embeddings for {} inputs", model, embeddings.len(), inputs.len()));
+        }
+        logging::debug(
+            "ollama",
+            "embedded",
+            json!({ "model": model, "inputs": inputs.len(), "elapsed_ms": started.elapsed().as_millis() as u64 }),
+        );
+        Ok(embeddings)
+    }
+
+    /// Starts a chat request; `options` are model options such as `temperature`.
+    pub async fn chat_stream(&self, model_name: String, messages: Vec<ChatTurn>, options: Map<String, Value>) -> Result<Pin<Box<dyn Stream<Item = Result<String>> + Send>>> {
+        let url = format!("{}/api/chat", self.base_url);
+        let request_messages: Vec<ChatMessage> = messages
+            .iter()
+            .map(|turn| ChatMessage { role: turn.role.clone(), content: turn.content.clone() })
+            .collect();
+        logging::info(
+            "ollama",
+            "chat request",
+            json!({
+                "model": model_name,
+                "messages": request_messages.len(),
+                "chars": request_messages.iter().map(|m| m.content.len()).sum::<usize>(),
+            }),
+        );
+        let request = ChatRequest {
+            model: model_name,
+            messages: request_messages,
+            stream: true,
+            options,
+        };
+        let started = Instant::now();
+        let response = match self.client.post(&url).json(&request).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                logging::error("ollama", "chat request failed", json!({ "url": url, "error": e.to_string() }));
+                return Err(e.into());
+            }
+        };
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        if !response.status().is_success() {
+            logging::error("ollama", "chat request failed", json!({ "status": response.status().as_u16(), "elapsed_ms": elapsed_ms }));
+            return Err(anyhow::anyhow!("Failed to get streaming response: {}", response.status()));
+        }
+        logging::debug("ollama", "chat response started", json!({ "status": response.status().as_u16(), "elapsed_ms": elapsed_ms }));
+        let stream = response.bytes_stream();
+        let mapped = stream.map(|chunk| {
+            let chunk = chunk?;
+            let s = String::from_utf8_lossy(&chunk).to_string();
+            Ok(s)
+        });
+        Ok(Box::pin(mapped))
+    }
+} 
\ No newline at end of file
@@ -0,0 +1,388 @@
+//! Parsing and applying unified diffs produced by the model.
+//!
+//! Hunks are located by content rather than trusting the line numbers in the
+//! `@@` header: the search starts at the expected line and moves outwards, and
+//! if the exact context is not found, up to `MAX_FUZZ` leading/trailing context
+//! lines are dropped, the same way `patch(1)` does.
+
+const MAX_FUZZ: usize = 2;
+
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    /// `None` when the old side is `/dev/null` (file creation).
+    pub old_path: Option<String>,
+    /// `None` when the new side is `/dev/null` (file deletion).
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FilePatch {
+    /// The path the patch applies to.
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    pub header: String,
+    pub old_start: usize,
+    pub lines: Vec<HunkLine>,
+}
+
+#[derive(Debug, Clone)]
+pub enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|l| match l {
+                HunkLine::Context(s) | HunkLine::Remove(s) => Some(s.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|l| match l {
+                HunkLine::Context(s) | HunkLine::Add(s) => Some(s.as_str()),
+                HunkLine::Remove(_) => None,
+            })
+            .collect()
+    }
+
+    /// Number of context lines before the first change and after the last one.
+    fn context_margins(&self) -> (usize, usize) {
+        let is_context = |l: &&HunkLine| matches!(l, HunkLine::Context(_));
+        let leading = self.lines.iter().take_while(is_context).count();
+        let trailing = self.lines.iter().rev().take_while(is_context).count();
+        (leading, trailing)
+    }
+}
+
+/// Parses a unified diff touching one or more files.
+pub fn parse(patch: &str) -> Result<Vec<FilePatch>, String> {
+    let mut files: Vec<FilePatch> = Vec::new();
+    let mut lines = patch.lines().peekable();
+    while let Some(line) = lines.next() {
+        if let Some(old) = line.strip_prefix("--- ") {
+            let new = match lines.next().and_then(|l| l.strip_prefix("+++ ")) {
+                Some(new) => new,
+                None => return Err(format!("expected `+++` line after `{}`", line)),
+            };
+            files.push(FilePatch {
+                old_path: parse_path(old, "a/"),
+                new_path: parse_path(new, "b/"),
+                hunks: Vec::new(),
+            });
+        } else if line.starts_with("@@") {
+            let file = files
+                .last_mut()
+                .ok_or_else(|| format!("hunk `{}` appears before any `---`/`+++` file header", line))?;
+            let old_start = parse_hunk_header(line)?;
+            let mut hunk = Hunk {
+                header: line.to_string(),
+                old_start,
+                lines: Vec::new(),
+            };
+            while let Some(next) = lines.peek() {
+                if next.starts_with("@@") || (next.starts_with("--- ") && is_file_header(&mut lines.clone())) {
+                    break;
+                }
+                let next = lines.next().unwrap_or_default();
+                if let Some(s) = next.strip_prefix('+') {
+                    hunk.lines.push(HunkLine::Add(s.to_string()));
+                } else if let Some(s) = next.strip_prefix('-') {
+                    hunk.lines.push(HunkLine::Remove(s.to_string()));
+                } else if let Some(s) = next.strip_prefix(' ') {
+                    hunk.lines.push(HunkLine::Context(s.to_string()));
+                } else if next.starts_with('\\') {
+                    // "\ No newline at end of file"
+                } else if next.is_empty() {
+                    // Models often strip the leading space from blank context lines.
+                    hunk.lines.push(HunkLine::Context(String::new()));
+                } else {
+                    break;
+                }
+            }
+            // Trailing blank lines usually separate files rather than being context.
+            while matches!(hunk.lines.last(), Some(HunkLine::Context(s)) if s.is_empty()) {
+                hunk.lines.pop();
+            }
+            file.hunks.push(hunk);
+        }
+    }
+    if files.is_empty() {
+        return Err("no `---`/`+++` file headers found in patch".to_string());
+    }
+    if let Some(file) = files.iter().find(|f| f.hunks.is_empty()) {
+        return Err(format!("no hunks found for {}", file.path()));
+    }
+    Ok(files)
+}
+
+fn is_file_header<'a>(lines: &mut impl Iterator<Item = &'a str>) -> bool {
+    lines.next();
+    lines.next().is_some_and(|l| l.starts_with("+++ "))
+}
+
+fn parse_path(raw: &str, prefix: &str) -> Option<String> {
+    // Drop a trailing timestamp ("file.rs\t2024-01-01 ...").
+    let path = raw.split('\t').next().unwrap_or(raw).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    Some(path.strip_prefix(prefix).unwrap_or(path).to_string())
+}
+
+fn parse_hunk_header(line: &str) -> Result<usize, String> {
+    let old = line
+        .trim_start_matches('@')
+        .split_whitespace()
+        .find_map(|part| part.strip_prefix('-'))
+        .ok_or_else(|| format!("malformed hunk header `{}`", line))?;
+    let start = old.split(',').next().unwrap_or(old);
+    start
+        .parse()
+        .map_err(|_| format!("malformed hunk header `{}`", line))
+}
+
+#[derive(Debug, Clone)]
+pub struct HunkReport {
+    pub header: String,
+    pub result: Result<Placement, String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    /// 1-based line in the original file where the hunk was applied.
+    pub line: usize,
+    /// Difference between where the header said the hunk was and where it matched.
+    pub offset: isize,
+    /// Number of context lines that had to be ignored on each side.
+    pub fuzz: usize,
+}
+
+impl std::fmt::Display for HunkReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.result {
+            Ok(p) => {
+                write!(f, "{} applied at line {}", self.header, p.line)?;
+                if p.offset != 0 {
+                    write!(f, " (offset {:+})", p.offset)?;
+                }
+                if p.fuzz != 0 {
+                    write!(f, " (fuzz {})", p.fuzz)?;
+                }
+                Ok(())
+            }
+            Err(e) => write!(f, "{} REJECTED: {}", self.header, e),
+        }
+    }
+}
+
+/// Applies `hunks` to `content`, returning the new content and one report per
+/// hunk. Rejected hunks are skipped; the others are still applied.
+pub fn apply(content: &str, hunks: &[Hunk]) -> (String, Vec<HunkReport>) {
+    let trailing_newline = content.is_empty() || content.ends_with('\n');
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut reports = Vec::new();
+    // Shift between original and current line numbers from hunks applied so far.
+    let mut delta: isize = 0;
+    for hunk in hunks {
+        // `-N,0` means "after line N" rather than "at line N"
+        let start_index = if hunk.old_lines().is_empty() { hunk.old_start } else { hunk.old_start.saturating_sub(1) };
+        let expected = (start_index as isize + delta).max(0) as usize;
+        let result = locate(&lines, hunk, expected).map(|(at, fuzz)| {
+            let (leading, trailing) = if fuzz == 0 { (0, 0) } else { hunk.context_margins() };
+            let drop_front = leading.min(fuzz);
+            let drop_back = trailing.min(fuzz);
+            let old = hunk.old_lines();
+            let new = hunk.new_lines();
+            let old_len = old.len() - drop_front - drop_back;
+            let replacement: Vec<String> = new[drop_front..new.len() - drop_back]
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            let inserted = replacement.len() as isize;
+            lines.splice(at..at + old_len, replacement);
+            let start = at as isize - drop_front as isize;
+            let placement = Placement {
+                line: (start - delta).max(0) as usize + 1,
+                offset: start - expected as isize,
+                fuzz,
+            };
+            delta += inserted - old_len as isize;
+            placement
+        });
+        reports.push(HunkReport {
+            header: hunk.header.clone(),
+            result,
+        });
+    }
+    let mut out = lines.join("\n");
+    if trailing_newline && !lines.is_empty() {
+        out.push('\n');
+    }
+    (out, reports)
+}
+
+/// Finds where a hunk's old lines occur, returning the index of the first
+/// matched line and the fuzz that was needed.
+fn locate(lines: &[String], hunk: &Hunk, expected: usize) -> Result<(usize, usize), String> {
+    let old = hunk.old_lines();
+    let (leading, trailing) = hunk.context_margins();
+    for fuzz in 0..=MAX_FUZZ {
+        let drop_front = leading.min(fuzz);
+        let drop_back = trailing.min(fuzz);
+        if fuzz > 0 && drop_front == 0 && drop_back == 0 {
+            break;
+        }
+        let needle = &old[drop_front..old.len() - drop_back];
+        let expected = expected + drop_front;
+        if needle.is_empty() {
+            return Ok((expected.min(lines.len()), fuzz));
+        }
+        for exact in [true, false] {
+            if let Some(at) = search(lines, needle, expected, exact) {
+                return Ok((at, fuzz));
+            }
+        }
+    }
+    Err("context not found in file".to_string())
+}
+
+/// Searches outwards from `expected` for `needle`. Non-exact matching ignores
+/// trailing whitespace.
+fn search(lines: &[String], needle: &[&str], expected: usize, exact: bool) -> Option<usize> {
+    if needle.len() > lines.len() {
+        return None;
+    }
+    let last = lines.len() - needle.len();
+    let matches_at = |at: usize| {
+        lines[at..at + needle.len()].iter().zip(needle).all(|(a, b)| {
+            if exact {
+                a == b
+            } else {
+                a.trim_end() == b.trim_end()
+            }
+        })
+    };
+    let expected = expected.min(last);
+    for distance in 0..=last {
+        if let Some(at) = expected.checked_sub(distance) {
+            if matches_at(at) {
+                return Some(at);
+            }
+        }
+        let at = expected + distance;
+        if distance > 0 && at <= last && matches_at(at) {
+            return Some(at);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply_patch(content: &str, patch: &str) -> (String, Vec<HunkReport>) {
+        let files = parse(patch).unwrap();
+        assert_eq!(files.len(), 1);
+        apply(content, &files[0].hunks)
+    }
+
+    const CONTENT: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\n";
+
+    #[test]
+    fn parses_files_and_hunks() {
+        let patch = "--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,2 +1,2 @@\n-old\n+new\n keep\n\
+--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1 @@\n+hello\n";
+        let files = parse(patch).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path(), "src/lib.rs");
+        assert_eq!(files[0].hunks[0].old_start, 1);
+        assert_eq!(files[0].hunks[0].old_lines(), ["old", "keep"]);
+        assert_eq!(files[0].hunks[0].new_lines(), ["new", "keep"]);
+        assert_eq!(files[1].old_path, None);
+        assert_eq!(files[1].path(), "new.txt");
+    }
+
+    #[test]
+    fn rejects_patches_without_headers_or_hunks() {
+        assert!(parse("@@ -1 +1 @@\n-a\n+b\n").is_err());
+        assert!(parse("--- a/x\n+++ b/x\n").is_err());
+        assert!(parse("--- a/x\nno new header\n").is_err());
+    }
+
+    #[test]
+    fn finds_hunks_away_from_their_header_line() {
+        let patch = "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n four\n-five\n+FIVE\n six\n";
+        let (out, reports) = apply_patch(CONTENT, patch);
+        assert_eq!(out, "one\ntwo\nthree\nfour\nFIVE\nsix\nseven\n");
+        let placement = reports[0].result.as_ref().unwrap();
+        assert_eq!((placement.line, placement.offset, placement.fuzz), (4, 3, 0));
+    }
+
+    #[test]
+    fn drops_context_that_does_not_match() {
+        let patch = "--- a/f\n+++ b/f\n@@ -2,5 +2,5 @@\n changed\n three\n-four\n+FOUR\n five\n";
+        let (out, reports) = apply_patch(CONTENT, patch);
+        assert_eq!(out, "one\ntwo\nthree\nFOUR\nfive\nsix\nseven\n");
+        assert_eq!(reports[0].result.as_ref().unwrap().fuzz, 1);
+    }
+
+    #[test]
+    fn inserts_zero_context_additions_after_the_given_line() {
+        let patch = "--- a/f\n+++ b/f\n@@ -2,0 +3,2 @@\n+two and a half\n+two and three quarters\n";
+        let (out, reports) = apply_patch(CONTENT, patch);
+        assert_eq!(out, "one\ntwo\ntwo and a half\ntwo and three quarters\nthree\nfour\nfive\nsix\nseven\n");
+        assert!(reports[0].result.is_ok());
+
+        let patch = "--- a/f\n+++ b/f\n@@ -0,0 +1 @@\n+zero\n";
+        let (out, _) = apply_patch(CONTENT, patch);
+        assert!(out.starts_with("zero\none\n"));
+
+        let patch = "--- a/f\n+++ b/f\n@@ -7,0 +8 @@\n+eight\n";
+        let (out, _) = apply_patch(CONTENT, patch);
+        assert!(out.ends_with("seven\neight\n"));
+    }
+
+    #[test]
+    fn creates_files_from_an_empty_one() {
+        let patch = "--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,2 @@\n+hello\n+world\n";
+        let (out, reports) = apply_patch("", patch);
+        assert_eq!(out, "hello\nworld\n");
+        assert!(reports[0].result.is_ok());
+    }
+
+    #[test]
+    fn deletes_lines_at_the_end_of_the_file() {
+        let patch = "--- a/f\n+++ b/f\n@@ -5,3 +5,1 @@\n five\n-six\n-seven\n";
+        let (out, reports) = apply_patch(CONTENT, patch);
+        assert_eq!(out, "one\ntwo\nthree\nfour\nfive\n");
+        assert_eq!(reports[0].result.as_ref().unwrap().line, 5);
+    }
+
+    #[test]
+    fn reports_rejected_hunks_and_applies_the_rest() {
+        let patch = "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n-missing\n+gone\n@@ -6,2 +6,2 @@\n six\n-seven\n+SEVEN\n";
+        let (out, reports) = apply_patch(CONTENT, patch);
+        assert_eq!(out, "one\ntwo\nthree\nfour\nfive\nsix\nSEVEN\n");
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].result.as_ref().unwrap_err(), "context not found in file");
+        assert!(reports[0].to_string().ends_with("REJECTED: context not found in file"));
+        assert_eq!(reports[1].result.as_ref().unwrap().line, 6);
+    }
+}
@@ -0,0 +1,154 @@
+//! Plan mode: the model first writes a numbered plan for a task, the user
+//! edits and approves it, and Quill then sends the steps one at a time.
+
+use regex::Regex;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepState {
+    Todo,
+    Active,
+    Done,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlanStep {
+    pub text: String,
+    pub files: Vec<String>,
+    pub tools: Vec<String>,
+    pub state: StepState,
+    /// Tool calls made for this step, and how many of them failed.
+    pub calls: usize,
+    pub failed: usize,
+}
+
+impl PlanStep {
+    pub fn new(text: &str) -> Self {
+        PlanStep {
+            text: text.trim().to_string(),
+            files: Vec::new(),
+            tools: Vec::new(),
+            state: StepState::Todo,
+            calls: 0,
+            failed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Waiting for the model to reply with a plan.
+    Drafting,
+    /// Shown to the user for editing and approval.
+    Proposed,
+    /// Working on the active step.
+    Executing,
+    /// Execution stopped (Esc or a denied call); `/plan continue` resumes.
+    Paused,
+}
+
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub task: String,
+    pub steps: Vec<PlanStep>,
+    pub phase: Phase,
+}
+
+impl Plan {
+    pub fn new(task: &str) -> Self {
+        Plan {
+            task: task.to_string(),
+            steps: Vec::new(),
+            phase: Phase::Drafting,
+        }
+    }
+
+    /// Index of the step being worked on, or the next one to start.
+    pub fn current(&self) -> Option<usize> {
+        self.steps.iter().position(|s| s.state != StepState::Done)
+    }
+
+    /// The message asking the model for a plan.
+    pub fn planning_prompt(&self) -> String {
+        format!(
+            "[PLAN MODE] Before changing anything, write a plan for this task:\n{}\n\n\
+You may read files and search the codebase first, but do not write, move or delete anything and do not run commands yet.\n\
+When you are ready, reply with the plan in exactly this format and nothing after it:\n\
+PLAN:\n\
+1. <one concrete step>\n   files: <paths this step touches, comma separated, or none>\n   tools: <tools this step will call, comma separated>\n\
+2. ...\n\
+END PLAN",
+            self.task
+        )
+    }
+
+    /// The message asking the model to carry out step `index`.
+    pub fn step_prompt(&self, index: usize) -> String {
+        let mut checklist = String::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let mark = if step.state == StepState::Done { "x" } else { " " };
+            checklist.push_str(&format!("[{}] {}. {}\n", mark, i + 1, step.text));
+        }
+        let step = &self.steps[index];
+        let mut prompt = format!(
+            "[PLAN STEP {}/{}] Task: {}\nPlan:\n{}\nCarry out step {} now and only that step: {}\n",
+            index + 1,
+            self.steps.len(),
+            self.task,
+            checklist,
+            index + 1,
+            step.text
+        );
+        if !step.files.is_empty() {
+            prompt.push_str(&format!("Files: {}\n", step.files.join(", ")));
+        }
+        prompt.push_str("Use tools as needed. When the step is complete, reply with a one-line summary and no tool call.");
+        prompt
+    }
+}
+
+/// Reads the numbered steps out of the model's reply. Returns `None` if the
+/// reply has no numbered list.
+pub fn parse(reply: &str) -> Option<Vec<PlanStep>> {
+    // Only look between PLAN: and END PLAN if the model used the markers.
+    let body = match reply.rfind("PLAN:") {
+        Some(start) => {
+            let rest = &reply[start + "PLAN:".len()..];
+            rest.find("END PLAN").map_or(rest, |end| &rest[..end])
+        }
+        None => reply,
+    };
+    let numbered = Regex::new(r"^\s*(?:[-*]\s*)?\**(\d+)[.)]\**\s+(.+)$").ok()?;
+    let mut steps: Vec<PlanStep> = Vec::new();
+    for line in body.lines() {
+        if let Some(caps) = numbered.captures(line) {
+            steps.push(PlanStep::new(caps[2].trim_matches('*')));
+            continue;
+        }
+        let trimmed = line.trim().trim_start_matches(['-', '*']).trim();
+        let Some(step) = steps.last_mut() else {
+            continue;
+        };
+        let list = |value: &str| -> Vec<String> {
+            value
+                .split(',')
+                .map(|v| v.trim().trim_matches('`').to_string())
+                .filter(|v| !v.is_empty() && !v.eq_ignore_ascii_case("none"))
+                .collect()
+        };
+        if let Some(files) = strip_label(trimmed, "files:") {
+            step.files = list(files);
+        } else if let Some(tools) = strip_label(trimmed, "tools:") {
+            step.tools = list(tools);
+        }
+    }
+    if steps.is_empty() {
+        None
+    } else {
+        Some(steps)
+    }
+}
+
+fn strip_label<'a>(line: &'a str, label: &str) -> Option<&'a str> {
+    let head = line.get(..label.len())?;
+    head.eq_ignore_ascii_case(label).then(|| &line[label.len()..])
+}
@@ -0,0 +1,166 @@
+//! `grep` and `glob` tools. Both walk the workspace with the same rules as
+//! git: `.gitignore`, `.ignore` and hidden files are skipped.
+
+use globset::{Glob, GlobMatcher};
+use ignore::WalkBuilder;
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+
+/// Maximum number of matching lines returned by `grep`.
+const MAX_GREP_MATCHES: usize = 100;
+/// Maximum number of paths returned by `glob`.
+const MAX_GLOB_RESULTS: usize = 500;
+/// Longer lines are cut so minified files do not flood the context.
+const MAX_LINE_CHARS: usize = 300;
+/// Files larger than this are not searched.
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+/// Walks `root` the way git sees it, in path order.
+pub(crate) fn walker(root: &Path) -> ignore::Walk {
+    WalkBuilder::new(root)
+        .require_git(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .sort_by_file_path(|a, b| a.cmp(b))
+        .build()
+}
+
+fn compile_glob(pattern: &str) -> Result<GlobMatcher, String> {
+    Glob::new(pattern)
+        .map(|g| g.compile_matcher())
+        .map_err(|e| format!("invalid glob `{}`: {}", pattern, e))
+}
+
+fn relative<'a>(path: &'a Path, root: &Path) -> &'a Path {
+    path.strip_prefix(root).unwrap_or(path)
+}
+
+/// Matches a glob pattern against a path, also trying just the file name so
+/// that `*.rs` finds files in subdirectories.
+fn glob_matches(matcher: &GlobMatcher, pattern: &str, relative: &Path) -> bool {
+    matcher.is_match(relative)
+        || (!pattern.contains('/') && relative.file_name().is_some_and(|name| matcher.is_match(name)))
+}
+
+fn truncate_line(line: &str) -> String {
+    if line.chars().count() <= MAX_LINE_CHARS {
+        line.to_string()
+    } else {
+        let cut: String = line.chars().take(MAX_LINE_CHARS).collect();
+        format!("{}... [line truncated]", cut)
+    }
+}
+
+/// Searches files under `dir` for lines matching the regex `pattern`. Paths
+/// in the output are relative to `root`.
+///
+/// Output is in `path:line: text` form, with `context` lines before and after
+/// each match written as `path-line- text`, and groups separated by `--`.
+pub fn grep(root: &Path, dir: &Path, pattern: &str, glob: Option<&str>, context: usize) -> Result<String, String> {
+    let re = Regex::new(pattern).map_err(|e| format!("invalid regex: {}", e))?;
+    let matcher = glob.map(compile_glob).transpose()?;
+    let mut out = Vec::new();
+    let mut matches = 0;
+    let mut files_with_matches = 0;
+    let mut truncated = false;
+    for entry in walker(dir).flatten() {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let rel = relative(entry.path(), root);
+        if let (Some(matcher), Some(pattern)) = (&matcher, glob) {
+            if !glob_matches(matcher, pattern, rel) {
+                continue;
+            }
+        }
+        if entry.metadata().map(|m| m.len() > MAX_FILE_BYTES).unwrap_or(true) {
+            continue;
+        }
+        // Skips binary and non-UTF-8 files.
+        let Ok(content) = fs::read_to_string(entry.path()) else {
+            continue;
+        };
+        if content.contains('\0') {
+            continue;
+        }
+        let lines: Vec<&str> = content.lines().collect();
+        let hits: Vec<usize> = (0..lines.len()).filter(|&i| re.is_match(lines[i])).collect();
+        if hits.is_empty() {
+            continue;
+        }
+        files_with_matches += 1;
+        let mut last_printed: Option<usize> = None;
+        for &hit in &hits {
+            if matches == MAX_GREP_MATCHES {
+                truncated = true;
+                break;
+            }
+            matches += 1;
+            let start = hit.saturating_sub(context);
+            let end = (hit + context).min(lines.len() - 1);
+            if context > 0 && last_printed.is_some_and(|last| start > last + 1) {
+                out.push("--".to_string());
+            }
+            let from = last_printed.map_or(start, |last| start.max(last + 1));
+            for (i, line) in lines.iter().enumerate().take(end + 1).skip(from) {
+                let sep = if hits.binary_search(&i).is_ok() { ':' } else { '-' };
+                out.push(format!("{}{}{}{} {}", rel.display(), sep, i + 1, sep, truncate_line(line)));
+            }
+            last_printed = Some(end);
+        }
+        if truncated {
+            break;
+        }
+        if context > 0 {
+            out.push("--".to_string());
+        }
+    }
+    if matches == 0 {
+        return Ok("No matches found.".to_string());
+    }
+    if out.last().is_some_and(|l| l == "--") {
+        out.pop();
+    }
+    let mut summary = format!("{} matches in {} files", matches, files_with_matches);
+    if truncated {
+        summary.push_str(&format!(
+            " (stopped after {} matches; narrow the pattern, path or glob)",
+            MAX_GREP_MATCHES
+        ));
+    }
+    out.push(summary);
+    Ok(out.join("\n"))
+}
+
+/// Lists files under `root` whose relative path matches the glob `pattern`.
+pub fn glob(root: &Path, pattern: &str) -> Result<String, String> {
+    let matcher = compile_glob(pattern)?;
+    let mut found = Vec::new();
+    let mut truncated = false;
+    for entry in walker(root).flatten() {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            continue;
+        }
+        let rel = relative(entry.path(), root);
+        if glob_matches(&matcher, pattern, rel) {
+            if found.len() == MAX_GLOB_RESULTS {
+                truncated = true;
+                break;
+            }
+            found.push(rel.display().to_string());
+        }
+    }
+    if found.is_empty() {
+        return Ok("No files found.".to_string());
+    }
+    let count = found.len();
+    if truncated {
+        found.push(format!(
+            "(stopped after {} files; use a more specific pattern)",
+            MAX_GLOB_RESULTS
+        ));
+    } else {
+        found.push(format!("{} files", count));
+    }
+    Ok(found.join("\n"))
+}
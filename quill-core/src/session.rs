@@ -0,0 +1,111 @@
+//! Saved chat sessions, so a conversation can be picked up again with
+//! `--resume`. Each session is one JSON file under the data directory,
+//! rewritten after every message.
+
+use crate::conversation::{ChatTurn, Message};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub updated: chrono::DateTime<chrono::Utc>,
+    pub working_directory: String,
+    pub model: String,
+    /// Given with `/save NAME`; can be used instead of the ID to resume.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The conversation sent to the model, without the system prompt.
+    pub turns: Vec<ChatTurn>,
+    /// What the chat area showed.
+    pub messages: Vec<Message>,
+}
+
+impl Session {
+    pub fn new(working_directory: &str) -> Self {
+        let now = chrono::Utc::now();
+        Session {
+            id: format!("{}-{}", now.format("%Y%m%d-%H%M%S"), &uuid::Uuid::new_v4().simple().to_string()[..6]),
+            created: now,
+            updated: now,
+            working_directory: working_directory.to_string(),
+            model: String::new(),
+            name: None,
+            turns: Vec::new(),
+            messages: Vec::new(),
+        }
+    }
+
+    fn dir(data_dir: &Path) -> PathBuf {
+        data_dir.join("sessions")
+    }
+
+    /// The session's name, or the first line of the first user message.
+    pub fn title(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        self.messages
+            .iter()
+            .find_map(|message| match message {
+                Message::User { content, .. } => content.lines().next().map(str::to_string),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Writes the session to the sessions folder in `data_dir`.
+    pub fn save(&mut self, data_dir: &Path) -> Result<()> {
+        let dir = Self::dir(data_dir);
+        fs::create_dir_all(&dir).with_context(|| format!("cannot create {}", dir.display()))?;
+        self.updated = chrono::Utc::now();
+        // Write to a temporary file first so a crash never leaves half a session
+        let path = dir.join(format!("{}.json", self.id));
+        let temp = path.with_extension("json.tmp");
+        fs::write(&temp, serde_json::to_string(self)?).with_context(|| format!("cannot write {}", temp.display()))?;
+        fs::rename(&temp, &path).with_context(|| format!("cannot write {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Every saved session, most recently updated first. Files that cannot be
+    /// read are skipped.
+    pub fn list(data_dir: &Path) -> Result<Vec<Session>> {
+        let entries = match fs::read_dir(Self::dir(data_dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut sessions: Vec<Session> = entries
+            .flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+            .filter_map(|text| serde_json::from_str(&text).ok())
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.updated));
+        Ok(sessions)
+    }
+
+    /// The session with this ID or name, or the only one whose ID starts with
+    /// it. An empty ID means the latest session in `working_directory`.
+    pub fn find(data_dir: &Path, id: &str, working_directory: &str) -> Result<Session> {
+        let sessions = Self::list(data_dir)?;
+        if id.is_empty() {
+            return match sessions.into_iter().find(|s| s.working_directory == working_directory) {
+                Some(session) => Ok(session),
+                None => bail!("no saved session in {}", working_directory),
+            };
+        }
+        if let Some(session) = sessions.iter().find(|s| s.id == id || s.name.as_deref() == Some(id)) {
+            return Ok(session.clone());
+        }
+        let mut matches: Vec<Session> = sessions.into_iter().filter(|s| s.id.starts_with(id)).collect();
+        match matches.len() {
+            0 => bail!("no saved session {}", id),
+            1 => Ok(matches.remove(0)),
+            n => bail!("{} sessions start with {}; give more of the ID", n, id),
+        }
+    }
+}
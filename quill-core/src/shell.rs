@@ -0,0 +1,408 @@
+//! The `run_command` tool: runs a shell command in the working directory with
+//! a timeout, a scrubbed environment and, when bubblewrap is installed, a
+//! read-only view of the rest of the filesystem.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+use tokio::sync::{mpsc, oneshot};
+
+/// Timeout used when the model does not ask for one.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+/// Upper bound on the timeout the model may request.
+pub const MAX_TIMEOUT: Duration = Duration::from_secs(600);
+/// Output sent back to the model is cut to this size, keeping the first third
+/// and the last two thirds, where errors and summaries usually are. Only that
+/// much is kept while the command runs, so endless output cannot fill memory.
+const MAX_OUTPUT_BYTES: usize = 30 * 1024;
+const HEAD_BYTES: usize = MAX_OUTPUT_BYTES / 3;
+const TAIL_BYTES: usize = MAX_OUTPUT_BYTES - HEAD_BYTES;
+/// Chunks of output waiting for `poll`. Once this many are, the command's
+/// output is no longer read, so it blocks until the frontend catches up.
+const OUTPUT_BUFFER: usize = 256;
+
+/// Environment variables passed through to commands. Everything else,
+/// including API keys and tokens, is dropped.
+const ENV_ALLOWLIST: &[&str] = &[
+    "PATH", "HOME", "USER", "LOGNAME", "LANG", "LC_ALL", "LC_CTYPE", "TZ", "TMPDIR",
+    "CARGO_HOME", "RUSTUP_HOME", "GOPATH", "GOROOT", "JAVA_HOME", "PYTHONPATH", "VIRTUAL_ENV", "NODE_PATH",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SandboxMode {
+    /// Use bubblewrap if it is installed, otherwise run unsandboxed.
+    Auto,
+    Bubblewrap,
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShellOptions {
+    pub sandbox: SandboxMode,
+    /// Whether sandboxed commands may use the network.
+    pub allow_network: bool,
+}
+
+impl ShellOptions {
+    /// Reads `QUILL_SANDBOX` (`auto`, `bwrap` or `none`) and
+    /// `QUILL_SANDBOX_NETWORK` (`1` to allow network access).
+    pub fn from_env() -> Self {
+        let sandbox = match std::env::var("QUILL_SANDBOX").as_deref() {
+            Ok("none") | Ok("off") => SandboxMode::None,
+            Ok("bwrap") | Ok("bubblewrap") => SandboxMode::Bubblewrap,
+            _ => SandboxMode::Auto,
+        };
+        let allow_network = matches!(std::env::var("QUILL_SANDBOX_NETWORK").as_deref(), Ok("1") | Ok("true"));
+        ShellOptions { sandbox, allow_network }
+    }
+}
+
+/// The timeout for a call, defaulting to `DEFAULT_TIMEOUT` and capped at `MAX_TIMEOUT`.
+pub fn timeout(requested_secs: Option<u64>) -> Duration {
+    requested_secs
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TIMEOUT)
+        .min(MAX_TIMEOUT)
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(|candidate| candidate.is_file())
+    })
+}
+
+/// The bubblewrap binary commands run in, or `None` to run them unsandboxed.
+fn find_bwrap(options: &ShellOptions) -> Result<Option<PathBuf>> {
+    match options.sandbox {
+        SandboxMode::None => Ok(None),
+        SandboxMode::Auto => Ok(find_in_path("bwrap")),
+        SandboxMode::Bubblewrap => find_in_path("bwrap")
+            .map(Some)
+            .ok_or_else(|| anyhow::anyhow!("QUILL_SANDBOX=bwrap but bwrap is not installed")),
+    }
+}
+
+fn describe(sandboxed: bool, options: &ShellOptions) -> String {
+    match (sandboxed, options.sandbox) {
+        (true, _) => {
+            let network = if options.allow_network { "network allowed" } else { "no network" };
+            format!("bubblewrap, read-only outside the working directory, {}", network)
+        }
+        (false, SandboxMode::Auto) if !cfg!(windows) => {
+            "none, bubblewrap not found: the command can change any of your files".to_string()
+        }
+        (false, _) => "none".to_string(),
+    }
+}
+
+/// How commands run under `options`, for the approval prompt. Fails if they
+/// cannot run at all.
+pub fn sandbox_description(options: &ShellOptions) -> Result<String> {
+    Ok(describe(find_bwrap(options)?.is_some(), options))
+}
+
+/// Builds the process for `command`, returning it with a description of the
+/// sandbox that is used.
+fn build_command(command: &str, working_directory: &Path, options: &ShellOptions) -> Result<(Command, String)> {
+    let bwrap = find_bwrap(options)?;
+    let sandbox = describe(bwrap.is_some(), options);
+    let mut cmd = match bwrap {
+        Some(bwrap) => {
+            let mut cmd = Command::new(bwrap);
+            cmd.args(["--die-with-parent", "--new-session", "--unshare-all"]);
+            if options.allow_network {
+                cmd.arg("--share-net");
+            }
+            cmd.args(["--ro-bind", "/", "/", "--dev", "/dev", "--proc", "/proc", "--tmpfs", "/tmp"]);
+            cmd.arg("--bind").arg(working_directory).arg(working_directory);
+            cmd.arg("--chdir").arg(working_directory);
+            cmd.args(["--", "sh", "-c", command]);
+            cmd
+        }
+        None if cfg!(windows) => {
+            let mut cmd = Command::new("cmd");
+            cmd.args(["/C", command]);
+            cmd
+        }
+        None => {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", command]);
+            cmd
+        }
+    };
+    cmd.current_dir(working_directory)
+        .env_clear()
+        .envs(ENV_ALLOWLIST.iter().filter_map(|k| std::env::var_os(k).map(|v| (k, v))))
+        .env("TERM", "dumb")
+        .env("QUILL", "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    #[cfg(unix)]
+    cmd.process_group(0);
+    Ok((cmd, sandbox))
+}
+
+#[derive(Debug, Clone)]
+pub enum ExitReason {
+    Exited(Option<i32>),
+    TimedOut,
+    Cancelled,
+    Failed(String),
+}
+
+enum CommandEvent {
+    Output(String),
+    Finished(ExitReason),
+}
+
+/// A command running in the background. Output arrives through `poll`.
+pub struct RunningCommand {
+    pub command: String,
+    pub sandbox: String,
+    pub started: Instant,
+    /// The start of the combined stdout and stderr, up to `HEAD_BYTES`.
+    head: String,
+    /// The output after `head`, cut from the front once it is twice
+    /// `TAIL_BYTES` long.
+    tail: String,
+    /// Bytes dropped between `head` and `tail`.
+    omitted: usize,
+    events: mpsc::Receiver<CommandEvent>,
+    cancel: Option<oneshot::Sender<()>>,
+}
+
+impl RunningCommand {
+    /// Collects output received since the last call. Returns the exit reason
+    /// once the command has finished.
+    pub fn poll(&mut self) -> Option<ExitReason> {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                CommandEvent::Output(text) => self.collect(&text),
+                CommandEvent::Finished(reason) => return Some(reason),
+            }
+        }
+        None
+    }
+
+    fn collect(&mut self, mut text: &str) {
+        if self.tail.is_empty() && self.head.len() < HEAD_BYTES {
+            let mut end = (HEAD_BYTES - self.head.len()).min(text.len());
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.head.push_str(&text[..end]);
+            text = &text[end..];
+        }
+        self.tail.push_str(text);
+        if self.tail.len() > 2 * TAIL_BYTES {
+            let cut = ceil_char_boundary(&self.tail, self.tail.len() - TAIL_BYTES);
+            self.tail.drain(..cut);
+            self.omitted += cut;
+        }
+    }
+
+    /// Combined stdout and stderr received so far, with the middle left out
+    /// if it is longer than `MAX_OUTPUT_BYTES`.
+    pub fn output(&self) -> String {
+        let cut = ceil_char_boundary(&self.tail, self.tail.len().saturating_sub(TAIL_BYTES));
+        match self.omitted + cut {
+            0 => format!("{}{}", self.head, self.tail),
+            omitted => format!("{}\n[... {} bytes omitted ...]\n{}", self.head, omitted, &self.tail[cut..]),
+        }
+    }
+
+    pub fn cancel(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            let _ = cancel.send(());
+        }
+    }
+
+    /// Waits for the command to finish, collecting all of its output.
+    pub async fn wait(mut self) -> (String, ExitReason) {
+        while let Some(event) = self.events.recv().await {
+            match event {
+                CommandEvent::Output(text) => self.collect(&text),
+                CommandEvent::Finished(reason) => return (self.format_result(&reason), reason),
+            }
+        }
+        let reason = ExitReason::Failed("command task ended unexpectedly".to_string());
+        (self.format_result(&reason), reason)
+    }
+
+    /// The text sent back to the model.
+    pub fn format_result(&self, reason: &ExitReason) -> String {
+        let status = match reason {
+            ExitReason::Exited(Some(code)) => format!("exit code {}", code),
+            ExitReason::Exited(None) => "terminated by a signal".to_string(),
+            ExitReason::TimedOut => "killed after reaching the timeout".to_string(),
+            ExitReason::Cancelled => "cancelled by the user".to_string(),
+            ExitReason::Failed(e) => format!("failed to run: {}", e),
+        };
+        let tag = match reason {
+            ExitReason::Exited(Some(0)) => "TOOL RESULT",
+            _ => "TOOL ERROR",
+        };
+        format!(
+            "[{}: run_command]\nCommand: {}\nStatus: {} after {:.1}s\nSandbox: {}\n---\n{}",
+            tag,
+            self.command,
+            status,
+            self.started.elapsed().as_secs_f64(),
+            self.sandbox,
+            self.output()
+        )
+    }
+}
+
+/// Starts `command` in the background.
+pub fn spawn(command: &str, working_directory: &Path, timeout: Duration, options: &ShellOptions) -> Result<RunningCommand> {
+    let (mut cmd, sandbox) = build_command(command, working_directory, options)?;
+    let (tx, events) = mpsc::channel(OUTPUT_BUFFER);
+    let (cancel, cancelled) = oneshot::channel();
+    let mut child = cmd.spawn()?;
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    tokio::spawn(async move {
+        let readers = futures::future::join(forward(stdout, tx.clone()), forward(stderr, tx.clone()));
+        // Read while waiting, or a command filling the pipe never exits
+        let run = async {
+            let (status, _) = futures::future::join(child.wait(), readers).await;
+            status
+        };
+        let reason = tokio::select! {
+            status = run => match status {
+                Ok(status) => ExitReason::Exited(status.code()),
+                Err(e) => ExitReason::Failed(e.to_string()),
+            },
+            _ = tokio::time::sleep(timeout) => ExitReason::TimedOut,
+            _ = cancelled => ExitReason::Cancelled,
+        };
+        if !matches!(reason, ExitReason::Exited(_)) {
+            kill_tree(&mut child).await;
+        }
+        let _ = tx.send(CommandEvent::Finished(reason)).await;
+    });
+    Ok(RunningCommand {
+        command: command.to_string(),
+        sandbox,
+        started: Instant::now(),
+        head: String::new(),
+        tail: String::new(),
+        omitted: 0,
+        events,
+        cancel: Some(cancel),
+    })
+}
+
+/// Sends the output of `stream` as it arrives, a line at a time. Lines end
+/// with `\n` or `\r`, so progress bars that redraw one line show up too.
+async fn forward(stream: Option<impl AsyncRead + Unpin>, tx: mpsc::Sender<CommandEvent>) {
+    let Some(mut stream) = stream else { return };
+    let mut pending = Vec::new();
+    let mut buf = [0; 8192];
+    while let Ok(n) = stream.read(&mut buf).await {
+        if n == 0 {
+            break;
+        }
+        pending.extend_from_slice(&buf[..n]);
+        // Hold back a partial line unless it is getting long
+        let end = match pending.iter().rposition(|&b| b == b'\n' || b == b'\r') {
+            Some(i) => i + 1,
+            None if pending.len() >= buf.len() => match std::str::from_utf8(&pending) {
+                // Keep a character cut in two for the next read
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                _ => pending.len(),
+            },
+            None => continue,
+        };
+        let _ = tx.send(CommandEvent::Output(String::from_utf8_lossy(&pending[..end]).into_owned())).await;
+        pending.drain(..end);
+    }
+    if !pending.is_empty() {
+        let _ = tx.send(CommandEvent::Output(String::from_utf8_lossy(&pending).into_owned())).await;
+    }
+}
+
+/// Kills the command and everything it started.
+async fn kill_tree(child: &mut tokio::process::Child) {
+    #[cfg(unix)]
+    if let Some(pid) = child.id() {
+        // The child leads its own process group, so this reaches grandchildren too.
+        let _ = Command::new("kill").args(["-s", "KILL", "--", &format!("-{}", pid)]).status().await;
+    }
+    let _ = child.kill().await;
+}
+
+/// The first char boundary of `text` at or after `index`.
+fn ceil_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(command: &str) -> RunningCommand {
+        let options = ShellOptions { sandbox: SandboxMode::None, allow_network: false };
+        spawn(command, &std::env::temp_dir(), Duration::from_secs(30), &options).unwrap()
+    }
+
+    #[tokio::test]
+    async fn long_output_keeps_the_start_and_the_end() {
+        let mut running = run("echo first; head -c 2000000 /dev/zero | tr '\\0' x; echo; echo last");
+        let reason = loop {
+            if let Some(reason) = running.poll() {
+                break reason;
+            }
+            assert!(running.head.len() + running.tail.len() <= HEAD_BYTES + 2 * TAIL_BYTES + 8192);
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        };
+        assert!(matches!(reason, ExitReason::Exited(Some(0))), "{:?} {}", reason, running.output());
+        let output = running.output();
+        assert!(output.starts_with("first\n"));
+        assert!(output.ends_with("\nlast\n"));
+        assert!(output.contains("bytes omitted"));
+        assert!(output.len() < MAX_OUTPUT_BYTES + 100);
+    }
+
+    #[tokio::test]
+    async fn carriage_returns_end_a_line() {
+        let mut running = run("printf 'progress 50%%\\r'; sleep 5");
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while !running.output().contains("progress 50%") {
+            assert!(Instant::now() < deadline, "no output before the command exited");
+            running.poll();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        running.cancel();
+    }
+
+    #[tokio::test]
+    async fn output_waits_for_poll_instead_of_piling_up() {
+        let mut running = run("yes | head -c 100000000");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(running.events.len(), OUTPUT_BUFFER);
+        running.cancel();
+    }
+
+    #[test]
+    fn the_description_says_when_auto_runs_unsandboxed() {
+        let options = ShellOptions { sandbox: SandboxMode::Auto, allow_network: false };
+        assert_eq!(describe(true, &options), "bubblewrap, read-only outside the working directory, no network");
+        if !cfg!(windows) {
+            assert!(describe(false, &options).contains("bubblewrap not found"));
+        }
+        let options = ShellOptions { sandbox: SandboxMode::None, allow_network: false };
+        assert_eq!(describe(false, &options), "none");
+    }
+}
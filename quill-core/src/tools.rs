@@ -0,0 +1,1445 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use similar::TextDiff;
+use crate::{git, patch, search, shell};
+use crate::checkpoint::Checkpoints;
+use crate::memory::Scope;
+use crate::shell::ShellOptions;
+use crate::utils::format_file_size;
+
+/// Upper bound on the `context` argument of `grep`.
+const MAX_GREP_CONTEXT: usize = 10;
+/// Maximum size of the text returned by one `read_file` call.
+const MAX_READ_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ToolCall {
+    ReadFile { path: String, start_line: Option<usize>, end_line: Option<usize> },
+    ReadDirectory { path: String },
+    EditFile { path: String, edits: Vec<Edit> },
+    ReplaceInFile { path: String, old: String, new: String, occurrence: Option<usize> },
+    ApplyPatch { patch: String },
+    WriteFile { path: String, content: String },
+    CreateDirectory { path: String },
+    MovePath { from: String, to: String, overwrite: bool },
+    DeletePath { path: String, recursive: bool },
+    Grep { pattern: String, path: Option<String>, glob: Option<String>, context: usize },
+    Glob { pattern: String },
+    /// Finds code related to `query` in the embeddings index.
+    SemanticSearch { query: String, max_results: Option<usize> },
+    RunCommand { command: String, timeout_secs: Option<u64> },
+    GitStatus,
+    GitDiff { staged: bool, against: Option<String>, path: Option<String> },
+    GitLog { max_count: Option<usize>, path: Option<String> },
+    GitBlame { path: String, start_line: Option<usize>, end_line: Option<usize> },
+    GitCommit { message: String, paths: Option<Vec<String>>, all: bool },
+    /// Saves a fact to long-term memory.
+    Remember { text: String, scope: Scope },
+    /// A tool of an MCP server, named `server.tool`.
+    Mcp { name: String, arguments: serde_json::Map<String, Value> },
+    McpReadResource { server: String, uri: String },
+}
+
+/// How much damage a tool call can do, from least to most.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Risk {
+    /// Only reads from the workspace.
+    Read,
+    /// Adds new files or directories without touching existing ones.
+    Create,
+    /// Changes existing files in a way that shows up as a diff.
+    Modify,
+    /// Runs a program, which can do anything the user can inside the sandbox.
+    Execute,
+    /// Deletes or overwrites data. Always needs explicit approval.
+    Destructive,
+}
+
+impl Risk {
+    pub fn label(self) -> &'static str {
+        match self {
+            Risk::Read => "read",
+            Risk::Create => "create",
+            Risk::Modify => "modify",
+            Risk::Execute => "execute",
+            Risk::Destructive => "destructive",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Edit {
+    pub start_line: usize,
+    pub end_line: usize,
+    pub new_text: String,
+}
+
+impl ToolCall {
+    /// Name of the tool as the model invokes it.
+    pub fn name(&self) -> &str {
+        match self {
+            ToolCall::ReadFile { .. } => "read_file",
+            ToolCall::ReadDirectory { .. } => "read_directory",
+            ToolCall::EditFile { .. } => "edit_file",
+            ToolCall::ReplaceInFile { .. } => "replace_in_file",
+            ToolCall::ApplyPatch { .. } => "apply_patch",
+            ToolCall::WriteFile { .. } => "write_file",
+            ToolCall::CreateDirectory { .. } => "create_directory",
+            ToolCall::MovePath { .. } => "move_path",
+            ToolCall::DeletePath { .. } => "delete_path",
+            ToolCall::Grep { .. } => "grep",
+            ToolCall::Glob { .. } => "glob",
+            ToolCall::SemanticSearch { .. } => "semantic_search",
+            ToolCall::RunCommand { .. } => "run_command",
+            ToolCall::GitStatus => "git_status",
+            ToolCall::GitDiff { .. } => "git_diff",
+            ToolCall::GitLog { .. } => "git_log",
+            ToolCall::GitBlame { .. } => "git_blame",
+            ToolCall::GitCommit { .. } => "git_commit",
+            ToolCall::Remember { .. } => "remember",
+            ToolCall::Mcp { name, .. } => name,
+            ToolCall::McpReadResource { .. } => "mcp_read_resource",
+        }
+    }
+
+    /// Short description of what the tool operates on, for display.
+    pub fn target(&self) -> String {
+        match self {
+            ToolCall::ReadFile { path, .. }
+            | ToolCall::ReadDirectory { path }
+            | ToolCall::EditFile { path, .. }
+            | ToolCall::ReplaceInFile { path, .. }
+            | ToolCall::WriteFile { path, .. }
+            | ToolCall::CreateDirectory { path }
+            | ToolCall::DeletePath { path, .. } => path.clone(),
+            ToolCall::MovePath { from, to, .. } => format!("{} -> {}", from, to),
+            ToolCall::Grep { pattern, path, .. } => format!("/{}/ in {}", pattern, path.as_deref().unwrap_or(".")),
+            ToolCall::Glob { pattern } => pattern.clone(),
+            ToolCall::SemanticSearch { query, .. } => format!("\"{}\"", query),
+            ToolCall::RunCommand { command, .. } => format!("$ {}", command),
+            ToolCall::GitStatus => ".".to_string(),
+            ToolCall::GitDiff { staged, against, path } => {
+                let mut target = match (against, staged) {
+                    (Some(rev), _) => rev.clone(),
+                    (None, true) => "staged".to_string(),
+                    (None, false) => "unstaged".to_string(),
+                };
+                if let Some(path) = path {
+                    target.push_str(&format!(" -- {}", path));
+                }
+                target
+            }
+            ToolCall::GitLog { path, .. } => path.clone().unwrap_or_else(|| "HEAD".to_string()),
+            ToolCall::GitBlame { path, .. } => path.clone(),
+            ToolCall::GitCommit { message, .. } => message.lines().next().unwrap_or_default().to_string(),
+            ToolCall::Remember { text, scope } => format!("{} ({})", text.lines().next().unwrap_or_default(), scope.label()),
+            ToolCall::ApplyPatch { patch } => match patch::parse(patch) {
+                Ok(files) => files.iter().map(|f| f.path()).collect::<Vec<_>>().join(", "),
+                Err(_) => "(malformed patch)".to_string(),
+            },
+            ToolCall::Mcp { arguments, .. } => {
+                let args = Value::Object(arguments.clone()).to_string();
+                if args.chars().count() > 80 {
+                    format!("{}...", args.chars().take(80).collect::<String>())
+                } else {
+                    args
+                }
+            }
+            ToolCall::McpReadResource { server, uri } => format!("{}: {}", server, uri),
+        }
+    }
+
+    /// The call's arguments as a JSON object.
+    pub fn arguments(&self) -> serde_json::Value {
+        let mut value = serde_json::to_value(self).unwrap_or_default();
+        // Serialized as {"Variant": {...}}; keep just the fields
+        if let serde_json::Value::Object(map) = &mut value {
+            if map.len() == 1 {
+                return map.values_mut().next().map(serde_json::Value::take).unwrap_or_default();
+            }
+        }
+        value
+    }
+
+    /// The paths the call reads or writes, as given by the model. Used to match
+    /// path rules in the permission config.
+    pub fn paths(&self) -> Vec<String> {
+        match self {
+            ToolCall::ReadFile { path, .. }
+            | ToolCall::ReadDirectory { path }
+            | ToolCall::EditFile { path, .. }
+            | ToolCall::ReplaceInFile { path, .. }
+            | ToolCall::WriteFile { path, .. }
+            | ToolCall::CreateDirectory { path }
+            | ToolCall::DeletePath { path, .. }
+            | ToolCall::GitBlame { path, .. } => vec![path.clone()],
+            ToolCall::MovePath { from, to, .. } => vec![from.clone(), to.clone()],
+            ToolCall::Grep { path, .. } => vec![path.clone().unwrap_or_else(|| ".".to_string())],
+            ToolCall::GitDiff { path, .. } | ToolCall::GitLog { path, .. } => path.iter().cloned().collect(),
+            ToolCall::GitCommit { paths, .. } => paths.clone().unwrap_or_default(),
+            ToolCall::ApplyPatch { patch } => patch::parse(patch)
+                .map(|files| {
+                    files
+                        .iter()
+                        .flat_map(|f| [f.old_path.clone(), f.new_path.clone()])
+                        .flatten()
+                        .collect()
+                })
+                .unwrap_or_default(),
+            ToolCall::Glob { .. }
+            | ToolCall::SemanticSearch { .. }
+            | ToolCall::RunCommand { .. }
+            | ToolCall::GitStatus
+            | ToolCall::Remember { .. }
+            | ToolCall::Mcp { .. }
+            | ToolCall::McpReadResource { .. } => Vec::new(),
+        }
+    }
+
+    /// Whether the user reviews every such call, even when a rule or the
+    /// session allows the tool: destructive calls, and commits, which
+    /// checkpoints cannot undo.
+    pub fn always_asks(&self, risk: Risk) -> bool {
+        risk == Risk::Destructive || matches!(self, ToolCall::GitCommit { .. })
+    }
+
+    /// Classifies the call against the current state of the workspace: writing
+    /// to a path that already exists is treated as an overwrite.
+    pub fn risk(&self, working_directory: &Path) -> Risk {
+        let exists = |path: &str| {
+            resolve_in_workspace(working_directory, path)
+                .map(|pb| pb.exists())
+                .unwrap_or(false)
+        };
+        match self {
+            ToolCall::ReadFile { .. }
+            | ToolCall::ReadDirectory { .. }
+            | ToolCall::Grep { .. }
+            | ToolCall::Glob { .. }
+            | ToolCall::SemanticSearch { .. }
+            | ToolCall::GitStatus
+            | ToolCall::GitDiff { .. }
+            | ToolCall::GitLog { .. }
+            | ToolCall::GitBlame { .. }
+            | ToolCall::McpReadResource { .. } => Risk::Read,
+            ToolCall::GitCommit { .. } => Risk::Modify,
+            ToolCall::CreateDirectory { .. } | ToolCall::Remember { .. } => Risk::Create,
+            ToolCall::EditFile { path, .. } if !exists(path) => Risk::Create,
+            ToolCall::EditFile { .. } | ToolCall::ReplaceInFile { .. } => Risk::Modify,
+            ToolCall::ApplyPatch { patch } => match patch::parse(patch) {
+                Ok(files) if files.iter().any(|f| f.new_path.is_none()) => Risk::Destructive,
+                _ => Risk::Modify,
+            },
+            ToolCall::WriteFile { path, .. } if exists(path) => Risk::Destructive,
+            ToolCall::WriteFile { .. } => Risk::Create,
+            ToolCall::MovePath { to, .. } if exists(to) => Risk::Destructive,
+            ToolCall::MovePath { .. } => Risk::Modify,
+            ToolCall::DeletePath { .. } => Risk::Destructive,
+            // What an external tool does is unknown, so it is treated like a command.
+            ToolCall::RunCommand { .. } | ToolCall::Mcp { .. } => Risk::Execute,
+        }
+    }
+
+    /// Builds a tool call from a parsed `[tool_call: ...]` block.
+    pub fn from_invocation(invocation: &Invocation) -> Result<Self, String> {
+        let args = &invocation.args;
+        match invocation.name.as_str() {
+            "read_file" => Ok(ToolCall::ReadFile {
+                path: args.string("path", 0)?,
+                start_line: args.usize("start_line", 1)?,
+                end_line: args.usize("end_line", 2)?,
+            }),
+            "read_directory" => Ok(ToolCall::ReadDirectory { path: args.string("path", 0)? }),
+            "edit_file" => {
+                let edits = args.required("edits", 1)?.clone();
+                let edits: Vec<Edit> = serde_json::from_value(edits)
+                    .map_err(|e| format!("edit_file: invalid edits: {}", e))?;
+                Ok(ToolCall::EditFile { path: args.string("path", 0)?, edits })
+            }
+            "replace_in_file" => Ok(ToolCall::ReplaceInFile {
+                path: args.string("path", 0)?,
+                old: args.string("old", 1)?,
+                new: args.string("new", 2)?,
+                occurrence: args.usize("occurrence", 3)?,
+            }),
+            "apply_patch" => Ok(ToolCall::ApplyPatch { patch: args.string("patch", 0)? }),
+            "write_file" => Ok(ToolCall::WriteFile {
+                path: args.string("path", 0)?,
+                content: args.string("content", 1)?,
+            }),
+            "create_directory" => Ok(ToolCall::CreateDirectory { path: args.string("path", 0)? }),
+            "move_path" => Ok(ToolCall::MovePath {
+                from: args.string("from", 0)?,
+                to: args.string("to", 1)?,
+                overwrite: args.bool("overwrite", 2)?,
+            }),
+            "delete_path" => Ok(ToolCall::DeletePath {
+                path: args.string("path", 0)?,
+                recursive: args.bool("recursive", 1)?,
+            }),
+            "grep" => Ok(ToolCall::Grep {
+                pattern: args.string("pattern", 0)?,
+                path: args.optional_string("path", 1),
+                glob: args.optional_string("glob", 2),
+                context: args.usize("context", 3)?.unwrap_or(0).min(MAX_GREP_CONTEXT),
+            }),
+            "glob" => Ok(ToolCall::Glob { pattern: args.string("pattern", 0)? }),
+            "semantic_search" => Ok(ToolCall::SemanticSearch {
+                query: args.string("query", 0)?,
+                max_results: args.usize("max_results", 1)?,
+            }),
+            "run_command" => Ok(ToolCall::RunCommand {
+                command: args.string("command", 0)?,
+                timeout_secs: args.usize("timeout", 1)?.map(|t| t as u64),
+            }),
+            "git_status" => Ok(ToolCall::GitStatus),
+            "git_diff" => Ok(ToolCall::GitDiff {
+                staged: args.bool("staged", 0)?,
+                against: args.optional_string("against", 1),
+                path: args.optional_string("path", 2),
+            }),
+            "git_log" => Ok(ToolCall::GitLog {
+                max_count: args.usize("max_count", 0)?,
+                path: args.optional_string("path", 1),
+            }),
+            "git_blame" => Ok(ToolCall::GitBlame {
+                path: args.string("path", 0)?,
+                start_line: args.usize("start_line", 1)?,
+                end_line: args.usize("end_line", 2)?,
+            }),
+            "git_commit" => Ok(ToolCall::GitCommit {
+                message: args.string("message", 0)?,
+                paths: args.string_list("paths", 1)?,
+                all: args.bool("all", 2)?,
+            }),
+            "remember" => {
+                let scope = args.optional_string("scope", 1).unwrap_or_else(|| "project".to_string());
+                Ok(ToolCall::Remember {
+                    text: args.string("text", 0)?,
+                    scope: Scope::parse(&scope).ok_or_else(|| format!("remember: scope must be project or global, not {}", scope))?,
+                })
+            }
+            "mcp_read_resource" => Ok(ToolCall::McpReadResource {
+                server: args.string("server", 0)?,
+                uri: args.string("uri", 1)?,
+            }),
+            name if name.contains('.') => Ok(ToolCall::Mcp {
+                name: name.to_string(),
+                arguments: args.to_object()?,
+            }),
+            other => Err(format!("Unknown tool: {}", other)),
+        }
+    }
+}
+
+/// A `[tool_call: NAME(ARGS)]` block found in an assistant message.
+#[derive(Debug, Clone)]
+pub struct Invocation {
+    pub name: String,
+    pub args: Args,
+    /// The text between `[tool_call:` and the closing `]`, for display.
+    pub raw: String,
+}
+
+impl Invocation {
+    /// An invocation with named arguments from a JSON object, as sent by MCP clients.
+    pub fn from_json(name: &str, arguments: &serde_json::Map<String, Value>) -> Self {
+        Invocation {
+            name: name.to_string(),
+            args: Args {
+                positional: Vec::new(),
+                named: arguments.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            },
+            raw: format!("{}({})", name, Value::Object(arguments.clone())),
+        }
+    }
+}
+
+/// Positional and keyword arguments of a tool invocation.
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    positional: Vec<Value>,
+    named: HashMap<String, Value>,
+}
+
+impl Args {
+    /// The arguments as a JSON object, for tools with a schema Quill does not
+    /// know. Takes named arguments or a single positional object.
+    pub fn to_object(&self) -> Result<serde_json::Map<String, Value>, String> {
+        match self.positional.as_slice() {
+            [] => Ok(self.named.iter().map(|(k, v)| (k.clone(), v.clone())).collect()),
+            [Value::Object(object)] if self.named.is_empty() => Ok(object.clone()),
+            _ => Err("this tool takes named arguments, e.g. name=\"value\"".to_string()),
+        }
+    }
+
+    pub fn get(&self, name: &str, position: usize) -> Option<&Value> {
+        self.named.get(name).or_else(|| self.positional.get(position))
+    }
+
+    pub fn required(&self, name: &str, position: usize) -> Result<&Value, String> {
+        self.get(name, position)
+            .ok_or_else(|| format!("missing required argument `{}`", name))
+    }
+
+    pub fn string(&self, name: &str, position: usize) -> Result<String, String> {
+        match self.required(name, position)? {
+            Value::String(s) => Ok(s.clone()),
+            other => Ok(other.to_string()),
+        }
+    }
+
+    pub fn optional_string(&self, name: &str, position: usize) -> Option<String> {
+        match self.get(name, position)? {
+            Value::Null => None,
+            Value::String(s) => Some(s.clone()),
+            other => Some(other.to_string()),
+        }
+    }
+
+    pub fn string_list(&self, name: &str, position: usize) -> Result<Option<Vec<String>>, String> {
+        match self.get(name, position) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(vec![s.clone()])),
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|item| match item {
+                    Value::String(s) => Ok(s.clone()),
+                    _ => Err(format!("argument `{}` must be a list of strings", name)),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(Some),
+            Some(_) => Err(format!("argument `{}` must be a list of strings", name)),
+        }
+    }
+
+    pub fn bool(&self, name: &str, position: usize) -> Result<bool, String> {
+        match self.get(name, position) {
+            None | Some(Value::Null) => Ok(false),
+            Some(Value::Bool(b)) => Ok(*b),
+            Some(Value::String(s)) if s.eq_ignore_ascii_case("true") => Ok(true),
+            Some(Value::String(s)) if s.eq_ignore_ascii_case("false") => Ok(false),
+            Some(_) => Err(format!("argument `{}` must be true or false", name)),
+        }
+    }
+
+    pub fn usize(&self, name: &str, position: usize) -> Result<Option<usize>, String> {
+        match self.get(name, position) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::Number(n)) => n
+                .as_u64()
+                .map(|n| Some(n as usize))
+                .ok_or_else(|| format!("argument `{}` must be a positive integer", name)),
+            Some(Value::String(s)) => s
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("argument `{}` must be a positive integer", name)),
+            Some(_) => Err(format!("argument `{}` must be a positive integer", name)),
+        }
+    }
+}
+
+/// Finds the first `[tool_call: ...]` block in `message` and parses it.
+///
+/// Returns `None` if the message contains no tool call, and `Some(Err(..))` if
+/// one is present but malformed so the caller can report it.
+pub fn parse_tool_call(message: &str) -> Option<Result<Invocation, String>> {
+    let start = message.find("[tool_call:")?;
+    let body = &message[start + "[tool_call:".len()..];
+    Some(parse_invocation(body))
+}
+
+fn parse_invocation(body: &str) -> Result<Invocation, String> {
+    let mut p = Parser { src: body, pos: 0 };
+    p.skip_ws();
+    let name = p.tool_name().ok_or("expected a tool name")?;
+    p.skip_ws();
+    p.expect('(')?;
+    let mut args = Args::default();
+    loop {
+        p.skip_ws();
+        if p.eat(')') {
+            break;
+        }
+        let checkpoint = p.pos;
+        let key = match p.ident() {
+            Some(key) => {
+                p.skip_ws();
+                if p.eat('=') || p.eat(':') {
+                    Some(key)
+                } else {
+                    p.pos = checkpoint;
+                    None
+                }
+            }
+            None => None,
+        };
+        p.skip_ws();
+        let value = p.value()?;
+        match key {
+            Some(key) => {
+                args.named.insert(key, value);
+            }
+            None => args.positional.push(value),
+        }
+        p.skip_ws();
+        if p.eat(',') {
+            continue;
+        }
+        p.expect(')')?;
+        break;
+    }
+    let end = p.pos;
+    p.skip_ws();
+    p.eat(']');
+    Ok(Invocation {
+        name,
+        args,
+        raw: body[..end].trim().to_string(),
+    })
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str {
+        &self.src[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn skip_ws(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.src.len() - trimmed.len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(format!("expected `{}` at offset {}", c, self.pos))
+        }
+    }
+
+    /// Like `ident`, but also allows `.` and `-`, which appear in MCP tool names.
+    fn tool_name(&mut self) -> Option<String> {
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')))
+            .unwrap_or(self.rest().len());
+        if len == 0 || !self.rest().starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return None;
+        }
+        let name = self.rest()[..len].to_string();
+        self.pos += len;
+        Some(name)
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        let len = self
+            .rest()
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(self.rest().len());
+        if len == 0 || self.rest().starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+        let ident = self.rest()[..len].to_string();
+        self.pos += len;
+        Some(ident)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some(q @ ('"' | '\'')) => self.string(q).map(Value::String),
+            Some('[' | '{') => {
+                let mut stream = serde_json::Deserializer::from_str(self.rest()).into_iter::<Value>();
+                match stream.next() {
+                    Some(Ok(value)) => {
+                        self.pos += stream.byte_offset();
+                        Ok(value)
+                    }
+                    Some(Err(e)) => Err(format!("invalid JSON argument: {}", e)),
+                    None => Err("expected a value".to_string()),
+                }
+            }
+            Some(_) => {
+                let len = self.rest().find([',', ')']).unwrap_or(self.rest().len());
+                let token = self.rest()[..len].trim().to_string();
+                self.pos += len;
+                Ok(match token.as_str() {
+                    "true" | "True" => Value::Bool(true),
+                    "false" | "False" => Value::Bool(false),
+                    "null" | "None" => Value::Null,
+                    _ => serde_json::from_str::<serde_json::Number>(&token)
+                        .map(Value::Number)
+                        .unwrap_or(Value::String(token)),
+                })
+            }
+            None => Err("unexpected end of tool call".to_string()),
+        }
+    }
+
+    /// Reads a quoted string. Models often put raw newlines inside string
+    /// arguments, so this is more lenient than JSON.
+    fn string(&mut self, quote: char) -> Result<String, String> {
+        self.pos += quote.len_utf8();
+        let mut out = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((i, c)) = chars.next() {
+            if c == quote {
+                self.pos += i + c.len_utf8();
+                return Ok(out);
+            }
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some((_, 'n')) => out.push('\n'),
+                Some((_, 't')) => out.push('\t'),
+                Some((_, 'r')) => out.push('\r'),
+                Some((_, '0')) => out.push('\0'),
+                Some((_, 'u')) => {
+                    let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                        Some(c) => out.push(c),
+                        None => return Err(format!("invalid unicode escape `\\u{}`", hex)),
+                    }
+                }
+                Some((_, c @ ('\\' | '"' | '\'' | '/'))) => out.push(c),
+                Some((_, c)) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                None => break,
+            }
+        }
+        Err("unterminated string argument".to_string())
+    }
+}
+
+/// What a tool call produced.
+#[derive(Debug, Clone)]
+pub struct ToolOutput {
+    /// Text sent back to the model.
+    pub text: String,
+    /// Paths the call created, overwrote, moved or removed.
+    pub changed: Vec<PathBuf>,
+}
+
+impl From<String> for ToolOutput {
+    fn from(text: String) -> Self {
+        ToolOutput { text, changed: Vec::new() }
+    }
+}
+
+/// Runs a tool call against `working_directory`. Writes are snapshotted into
+/// `checkpoints` first.
+pub async fn execute(tool_call: ToolCall, working_directory: &Path, checkpoints: Option<&mut Checkpoints>) -> Result<ToolOutput> {
+    let Some(plan) = plan_changes(&tool_call, working_directory) else {
+        return run_tool(tool_call, working_directory).await.map(ToolOutput::from);
+    };
+    let name = tool_call.name();
+    match plan {
+        Ok(changes) => {
+            if let Some(checkpoints) = checkpoints {
+                if let Err(e) = checkpoints.record(name, &tool_call.target(), &changes) {
+                    return Ok(format!("[TOOL ERROR: {}]\nError: nothing was changed: {:#}", name, e).into());
+                }
+            }
+            changes.write()?;
+            Ok(ToolOutput {
+                text: format!("[TOOL RESULT: {}]\n{}", name, changes.summary),
+                changed: changes.paths(),
+            })
+        }
+        Err(e) => Ok(format!("[TOOL ERROR: {}]\n{}", name, e).into()),
+    }
+}
+
+/// Runs a tool that does not write to the workspace.
+async fn run_tool(tool_call: ToolCall, working_directory: &Path) -> Result<String> {
+    match tool_call {
+        ToolCall::ReadFile { path, start_line, end_line } => {
+            let pb = match resolve_in_workspace(working_directory, &path) {
+                Ok(pb) => pb,
+                Err(e) => return Ok(format!("[TOOL ERROR: read_file]\n{}", e)),
+            };
+            match read_file(&pb, start_line, end_line) {
+                Ok(content) => Ok(format!("[TOOL RESULT: read_file]\nPath: {}\n{}", pb.display(), content)),
+                Err(e) => Ok(format!("[TOOL ERROR: read_file]\nPath: {}\nError: {}", pb.display(), e)),
+            }
+        }
+        ToolCall::ReadDirectory { path } => {
+            let pb = match resolve_in_workspace(working_directory, &path) {
+                Ok(pb) => pb,
+                Err(e) => return Ok(format!("[TOOL ERROR: read_directory]\n{}", e)),
+            };
+            match fs::read_dir(&pb) {
+                Ok(entries) => {
+                    let mut list = Vec::new();
+                    for entry in entries.flatten() {
+                        let file_type = entry.file_type().ok();
+                        let name = entry.file_name().to_string_lossy().to_string();
+                        let kind = if let Some(ft) = file_type {
+                            if ft.is_dir() { "[DIR]" } else { "[FILE]" }
+                        } else { "[?]" };
+                        list.push(format!("{} {}", kind, name));
+                    }
+                    Ok(format!("[TOOL RESULT: read_directory]\nPath: {}\n---\n{}", pb.display(), list.join("\n")))
+                }
+                Err(e) => Ok(format!("[TOOL ERROR: read_directory]\nPath: {}\nError: {}", pb.display(), e)),
+            }
+        }
+        ToolCall::Grep { pattern, path, glob, context } => {
+            let pb = match resolve_in_workspace(working_directory, path.as_deref().unwrap_or(".")) {
+                Ok(pb) => pb,
+                Err(e) => return Ok(format!("[TOOL ERROR: grep]\n{}", e)),
+            };
+            match search::grep(working_directory, &pb, &pattern, glob.as_deref(), context) {
+                Ok(found) => Ok(format!("[TOOL RESULT: grep]\nPath: {}\n---\n{}", pb.display(), found)),
+                Err(e) => Ok(format!("[TOOL ERROR: grep]\nPath: {}\nError: {}", pb.display(), e)),
+            }
+        }
+        ToolCall::Glob { pattern } => match search::glob(working_directory, &pattern) {
+            Ok(found) => Ok(format!("[TOOL RESULT: glob]\nPattern: {}\n---\n{}", pattern, found)),
+            Err(e) => Ok(format!("[TOOL ERROR: glob]\nPattern: {}\nError: {}", pattern, e)),
+        },
+        ToolCall::RunCommand { command, timeout_secs } => {
+            let timeout = shell::timeout(timeout_secs);
+            match shell::spawn(&command, working_directory, timeout, &ShellOptions::from_env()) {
+                Ok(running) => Ok(running.wait().await.0),
+                Err(e) => Ok(format!("[TOOL ERROR: run_command]\nCommand: {}\nError: {}", command, e)),
+            }
+        }
+        ToolCall::GitStatus => Ok(git_result("git_status", git::status(working_directory))),
+        ToolCall::GitDiff { staged, against, path } => Ok(git_result(
+            "git_diff",
+            git::diff(working_directory, staged, against.as_deref(), path.as_deref()),
+        )),
+        ToolCall::GitLog { max_count, path } => Ok(git_result("git_log", git::log(working_directory, max_count, path.as_deref()))),
+        ToolCall::GitBlame { path, start_line, end_line } => Ok(git_result(
+            "git_blame",
+            git::blame(working_directory, &path, start_line, end_line),
+        )),
+        ToolCall::GitCommit { message, paths, all } => Ok(git_result(
+            "git_commit",
+            git::commit(working_directory, &message, paths.as_deref(), all),
+        )),
+        ToolCall::Remember { .. } => Ok("[TOOL ERROR: remember]\nError: memory is not available here".to_string()),
+        ToolCall::SemanticSearch { .. } => {
+            Ok("[TOOL ERROR: semantic_search]\nError: the code index is not available here".to_string())
+        }
+        ToolCall::Mcp { name, .. } => Ok(format!("[TOOL ERROR: {}]\nError: MCP servers are not available here", name)),
+        ToolCall::McpReadResource { .. } => {
+            Ok("[TOOL ERROR: mcp_read_resource]\nError: MCP servers are not available here".to_string())
+        }
+        _ => unreachable!("writing tools are handled by plan_changes"),
+    }
+}
+
+fn git_result(name: &str, result: Result<String, String>) -> String {
+    match result {
+        Ok(out) => format!("[TOOL RESULT: {}]\n---\n{}", name, out),
+        Err(e) => format!("[TOOL ERROR: {}]\nError: {}", name, e),
+    }
+}
+
+/// What the user is shown before approving a call: the diff for file
+/// changes, the staged changes for `git_commit` and the sandbox for
+/// `run_command`.
+pub fn preview(tool_call: &ToolCall, working_directory: &Path) -> Option<String> {
+    let preview = match tool_call {
+        ToolCall::GitCommit { message, paths, all } => {
+            git::commit_preview(working_directory, message, paths.as_deref(), *all).map_err(|e| format!("Error: {}", e))
+        }
+        ToolCall::RunCommand { .. } => shell::sandbox_description(&ShellOptions::from_env())
+            .map(|sandbox| format!("Sandbox: {}", sandbox))
+            .map_err(|e| format!("Error: {}", e)),
+        _ => plan_changes(tool_call, working_directory)?.map(|changes| changes.diff()),
+    };
+    Some(preview.unwrap_or_else(|e| format!("This call will fail:\n{}", e)))
+}
+
+/// Reads a file as numbered lines (`start_line..=end_line`, 1-based), stopping
+/// at `MAX_READ_BYTES`. Binary and non-UTF-8 files are summarized instead.
+pub(crate) fn read_file(path: &Path, start_line: Option<usize>, end_line: Option<usize>) -> Result<String, String> {
+    let mut file = fs::File::open(path).map_err(|e| e.to_string())?;
+    let size = file.metadata().map_err(|e| e.to_string())?.len();
+    // Sniff the start of the file so large binaries are never read in full.
+    let mut sniff = Vec::new();
+    (&mut file).take(8192).read_to_end(&mut sniff).map_err(|e| e.to_string())?;
+    if sniff.contains(&0) {
+        return Ok(binary_summary(&sniff, size, "binary file"));
+    }
+    let mut bytes = sniff;
+    file.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    let content = match std::str::from_utf8(&bytes) {
+        Ok(content) => content,
+        Err(_) => return Ok(binary_summary(&bytes, size, "not valid UTF-8 text")),
+    };
+    let lines: Vec<&str> = content.lines().collect();
+    let total = lines.len();
+    if total == 0 {
+        return Ok("Lines: 0 of 0\n---\n(empty file)".to_string());
+    }
+    let start = start_line.unwrap_or(1).max(1);
+    let end = end_line.unwrap_or(total).min(total);
+    if start > total {
+        return Err(format!("start_line {} is past the end of the file ({} lines)", start, total));
+    }
+    if end < start {
+        return Err(format!("end_line {} is before start_line {}", end, start));
+    }
+    let width = end.to_string().len();
+    let mut body = String::new();
+    let mut last = start - 1;
+    for (i, line) in lines.iter().enumerate().take(end).skip(start - 1) {
+        let numbered = format!("{:>width$}\t{}\n", i + 1, line, width = width);
+        if body.len() + numbered.len() > MAX_READ_BYTES && last >= start {
+            break;
+        }
+        body.push_str(&numbered);
+        last = i + 1;
+    }
+    let mut out = format!("Lines: {}-{} of {}\n---\n{}", start, last, total, body);
+    if last < end {
+        out.push_str(&format!(
+            "[truncated: output is limited to {}; pass start_line={} to read more]\n",
+            format_file_size(MAX_READ_BYTES as u64),
+            last + 1
+        ));
+    }
+    Ok(out)
+}
+
+fn binary_summary(bytes: &[u8], size: u64, reason: &str) -> String {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG", "PNG image"),
+        (b"\xFF\xD8\xFF", "JPEG image"),
+        (b"GIF8", "GIF image"),
+        (b"%PDF", "PDF document"),
+        (b"PK\x03\x04", "ZIP archive"),
+        (b"\x1F\x8B", "gzip archive"),
+        (b"\x7FELF", "ELF executable"),
+        (b"MZ", "Windows executable"),
+        (b"\0asm", "WebAssembly module"),
+        (b"SQLite format 3", "SQLite database"),
+    ];
+    let kind = SIGNATURES
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+        .map_or("unknown format", |(_, kind)| kind);
+    format!(
+        "---\n[not shown: {}, {}, {}]",
+        reason,
+        kind,
+        format_file_size(size)
+    )
+}
+
+/// The effect a writing tool would have on one file.
+#[derive(Debug, Clone)]
+pub struct FileChange {
+    pub path: PathBuf,
+    /// `None` if the file does not exist yet.
+    pub before: Option<String>,
+    /// `None` if the file is deleted.
+    pub after: Option<String>,
+}
+
+/// A change that is not a text edit of a single file.
+#[derive(Debug, Clone)]
+pub enum FsOp {
+    CreateDir(PathBuf),
+    Move { from: PathBuf, to: PathBuf },
+    /// Removes a directory tree, or a file that is not valid UTF-8.
+    Remove { path: PathBuf, is_dir: bool },
+}
+
+/// Everything a writing tool call will change, computed without touching the
+/// disk so it can be shown for approval before it is applied.
+#[derive(Debug, Clone)]
+pub struct Changes {
+    pub ops: Vec<FsOp>,
+    pub files: Vec<FileChange>,
+    /// Text sent back to the model after the changes are written.
+    pub summary: String,
+}
+
+impl Changes {
+    fn single(path: PathBuf, before: Option<String>, after: Option<String>, summary: String) -> Self {
+        Changes {
+            ops: Vec::new(),
+            files: vec![FileChange { path, before, after }],
+            summary,
+        }
+    }
+
+    fn op(op: FsOp, summary: String) -> Self {
+        Changes {
+            ops: vec![op],
+            files: Vec::new(),
+            summary,
+        }
+    }
+
+    /// Every path these changes create, overwrite, move or remove.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        for op in &self.ops {
+            match op {
+                FsOp::CreateDir(path) | FsOp::Remove { path, .. } => paths.push(path.clone()),
+                FsOp::Move { from, to } => {
+                    paths.push(from.clone());
+                    paths.push(to.clone());
+                }
+            }
+        }
+        paths.extend(self.files.iter().map(|change| change.path.clone()));
+        paths
+    }
+
+    pub fn write(&self) -> Result<()> {
+        for op in &self.ops {
+            match op {
+                FsOp::CreateDir(path) => fs::create_dir_all(path)?,
+                FsOp::Move { from, to } => {
+                    if let Some(parent) = to.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    if to.is_file() {
+                        fs::remove_file(to)?;
+                    }
+                    fs::rename(from, to)?;
+                }
+                FsOp::Remove { path, is_dir: true } => fs::remove_dir_all(path)?,
+                FsOp::Remove { path, is_dir: false } => fs::remove_file(path)?,
+            }
+        }
+        for change in &self.files {
+            match &change.after {
+                Some(content) => {
+                    if let Some(parent) = change.path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::write(&change.path, content)?;
+                }
+                None => fs::remove_file(&change.path)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Unified diff of every file, for the approval prompt.
+    pub fn diff(&self) -> String {
+        let mut out = String::new();
+        for op in &self.ops {
+            let line = match op {
+                FsOp::CreateDir(path) => format!("create directory {}\n", path.display()),
+                FsOp::Move { from, to } => format!("move {} -> {}\n", from.display(), to.display()),
+                FsOp::Remove { path, is_dir: true } => format!("-delete directory {} and everything in it\n", path.display()),
+                FsOp::Remove { path, is_dir: false } => format!("-delete {}\n", path.display()),
+            };
+            out.push_str(&line);
+        }
+        for change in &self.files {
+            let name = change.path.display().to_string();
+            let old_name = if change.before.is_some() { name.as_str() } else { "/dev/null" };
+            let new_name = if change.after.is_some() { name.as_str() } else { "/dev/null" };
+            let before = change.before.as_deref().unwrap_or_default();
+            let after = change.after.as_deref().unwrap_or_default();
+            let diff = TextDiff::from_lines(before, after);
+            out.push_str(&diff.unified_diff().context_radius(3).header(old_name, new_name).to_string());
+        }
+        out
+    }
+}
+
+/// Removes `.` and `..` components without touching the filesystem.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => {}
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// Resolves `path` against the working directory, rejecting paths that
+/// escape it, by `..` or through a symlink. Tools only read and write inside
+/// the workspace.
+pub fn resolve_in_workspace(working_directory: &Path, path: &str) -> Result<PathBuf, String> {
+    let resolved = normalize(&working_directory.join(path));
+    let root = working_directory.canonicalize().unwrap_or_else(|_| working_directory.to_path_buf());
+    let inside = resolved.starts_with(working_directory)
+        && canonicalize_existing(&resolved).is_ok_and(|real| real.starts_with(&root));
+    if inside {
+        Ok(resolved)
+    } else {
+        Err(format!("Path: {}\nError: path is outside the working directory {}", path, working_directory.display()))
+    }
+}
+
+/// Canonicalizes the longest part of `path` that exists and appends the rest,
+/// which writing the path would create.
+fn canonicalize_existing(path: &Path) -> std::io::Result<PathBuf> {
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(real) => return Ok(missing.iter().rev().fold(real, |real, name| real.join(name))),
+            // A dangling symlink: where it points cannot be checked.
+            Err(e) if fs::symlink_metadata(existing).is_ok() => return Err(e),
+            Err(e) => match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name);
+                    existing = parent;
+                }
+                _ => return Err(e),
+            },
+        }
+    }
+}
+
+/// Computes the file changes of a writing tool. Returns `None` for tools that
+/// do not write, and `Some(Err(..))` with the message for the model if the
+/// call cannot be applied.
+pub fn plan_changes(tool_call: &ToolCall, working_directory: &Path) -> Option<Result<Changes, String>> {
+    let plan = match tool_call {
+        ToolCall::ReadFile { .. }
+        | ToolCall::ReadDirectory { .. }
+        | ToolCall::Grep { .. }
+        | ToolCall::Glob { .. }
+        | ToolCall::SemanticSearch { .. }
+        | ToolCall::RunCommand { .. }
+        | ToolCall::GitStatus
+        | ToolCall::GitDiff { .. }
+        | ToolCall::GitLog { .. }
+        | ToolCall::GitBlame { .. }
+        | ToolCall::GitCommit { .. }
+        | ToolCall::Remember { .. }
+        | ToolCall::Mcp { .. }
+        | ToolCall::McpReadResource { .. } => return None,
+        ToolCall::EditFile { path, edits } => resolve_in_workspace(working_directory, path).and_then(|pb| {
+            let before = read_optional(&pb)?;
+            let after = apply_line_edits(before.as_deref().unwrap_or_default(), edits);
+            let summary = format!("Path: {}\n---\n{}", pb.display(), after);
+            Ok(Changes::single(pb, before, Some(after), summary))
+        }),
+        ToolCall::ReplaceInFile { path, old, new, occurrence } => resolve_in_workspace(working_directory, path).and_then(|pb| {
+            fs::read_to_string(&pb)
+                .map_err(|e| e.to_string())
+                .and_then(|content| {
+                    let replaced = replace_in_file(&content, old, new, *occurrence)?;
+                    let summary = format!(
+                        "Path: {}\n---\nReplaced occurrence {} of {} at line {}.",
+                        pb.display(),
+                        replaced.occurrence,
+                        replaced.total,
+                        replaced.line
+                    );
+                    Ok(Changes::single(pb.clone(), Some(content), Some(replaced.content), summary))
+                })
+                .map_err(|e| format!("Path: {}\nError: {}", pb.display(), e))
+        }),
+        ToolCall::ApplyPatch { patch } => plan_patch(patch, working_directory).map_err(|e| format!("Error: {}", e)),
+        ToolCall::WriteFile { path, content } => resolve_in_workspace(working_directory, path).and_then(|pb| {
+            if pb.is_dir() {
+                return Err(format!("Path: {}\nError: path is a directory", pb.display()));
+            }
+            let before = read_optional(&pb)?;
+            let verb = if before.is_some() { "Overwrote" } else { "Created" };
+            let summary = format!("Path: {}\n---\n{} file ({} lines).", pb.display(), verb, content.lines().count());
+            Ok(Changes::single(pb, before, Some(content.clone()), summary))
+        }),
+        ToolCall::CreateDirectory { path } => resolve_in_workspace(working_directory, path).and_then(|pb| {
+            if pb.exists() && !pb.is_dir() {
+                return Err(format!("Path: {}\nError: a file with this name already exists", pb.display()));
+            }
+            let summary = format!("Path: {}\n---\nCreated directory.", pb.display());
+            Ok(Changes::op(FsOp::CreateDir(pb), summary))
+        }),
+        ToolCall::MovePath { from, to, overwrite } => resolve_in_workspace(working_directory, from).and_then(|from| {
+            let to = resolve_in_workspace(working_directory, to)?;
+            if !from.exists() {
+                return Err(format!("Path: {}\nError: source does not exist", from.display()));
+            }
+            if to.is_dir() {
+                return Err(format!("Path: {}\nError: destination is an existing directory", to.display()));
+            }
+            if to.exists() && !overwrite {
+                return Err(format!("Path: {}\nError: destination exists; pass overwrite=true to replace it", to.display()));
+            }
+            let summary = format!("Path: {}\n---\nMoved to {}.", from.display(), to.display());
+            Ok(Changes::op(FsOp::Move { from, to }, summary))
+        }),
+        ToolCall::DeletePath { path, recursive } => resolve_in_workspace(working_directory, path).and_then(|pb| {
+            if pb == working_directory {
+                return Err("Error: refusing to delete the working directory".to_string());
+            }
+            let summary = format!("Path: {}\n---\nDeleted.", pb.display());
+            if pb.is_dir() {
+                let non_empty = fs::read_dir(&pb).map(|mut d| d.next().is_some()).unwrap_or(false);
+                if non_empty && !recursive {
+                    return Err(format!("Path: {}\nError: directory is not empty; pass recursive=true to delete it", pb.display()));
+                }
+                return Ok(Changes::op(FsOp::Remove { path: pb, is_dir: true }, summary));
+            }
+            match fs::read_to_string(&pb) {
+                Ok(before) => Ok(Changes::single(pb, Some(before), None, summary)),
+                Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                    Ok(Changes::op(FsOp::Remove { path: pb, is_dir: false }, summary))
+                }
+                Err(e) => Err(format!("Path: {}\nError: {}", pb.display(), e)),
+            }
+        }),
+    };
+    Some(plan)
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>, String> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Path: {}\nError: {}", path.display(), e)),
+    }
+}
+
+fn plan_patch(patch: &str, working_directory: &Path) -> Result<Changes, String> {
+    let file_patches = patch::parse(patch)?;
+    let mut files = Vec::new();
+    let mut summary = Vec::new();
+    let mut applied_any = false;
+    for file_patch in &file_patches {
+        let source = match &file_patch.old_path {
+            Some(p) => Some(resolve_in_workspace(working_directory, p)?),
+            None => None,
+        };
+        // Several sections of one patch may touch the same file; build on the
+        // earlier result instead of the copy on disk.
+        let earlier = source
+            .as_ref()
+            .and_then(|pb| files.iter().rposition(|c: &FileChange| c.path == *pb))
+            .map(|i| files.remove(i));
+        let before = match (&source, &earlier) {
+            (_, Some(change)) => change.before.clone(),
+            (Some(pb), None) => Some(fs::read_to_string(pb).map_err(|e| format!("{}: {}", pb.display(), e))?),
+            (None, None) => None,
+        };
+        let current = match &earlier {
+            Some(change) => change.after.clone().unwrap_or_default(),
+            None => before.clone().unwrap_or_default(),
+        };
+        let (content, reports) = patch::apply(&current, &file_patch.hunks);
+        let applied = reports.iter().filter(|r| r.result.is_ok()).count();
+        summary.push(format!(
+            "{}: {}/{} hunks applied",
+            file_patch.path(),
+            applied,
+            reports.len()
+        ));
+        summary.extend(reports.iter().map(|r| format!("  {}", r)));
+        if applied == 0 {
+            files.extend(earlier);
+            continue;
+        }
+        applied_any = true;
+        match (&file_patch.new_path, source) {
+            (Some(new_path), source) => {
+                let target = resolve_in_workspace(working_directory, new_path)?;
+                // A rename shows up as a delete of the old path and a write of the new one.
+                if let Some(source) = source.filter(|s| *s != target) {
+                    files.push(FileChange { path: source, before: before.clone(), after: None });
+                    files.push(FileChange { path: target.clone(), before: read_optional(&target)?, after: Some(content) });
+                } else {
+                    files.push(FileChange { path: target, before, after: Some(content) });
+                }
+            }
+            (None, Some(source)) => files.push(FileChange { path: source, before, after: None }),
+            (None, None) => {}
+        }
+    }
+    if !applied_any {
+        return Err(format!(
+            "no hunks could be applied; re-read the files and regenerate the patch\n{}",
+            summary.join("\n")
+        ));
+    }
+    Ok(Changes {
+        ops: Vec::new(),
+        files,
+        summary: summary.join("\n"),
+    })
+}
+
+/// Replaces lines `start_line..=end_line` (1-based) of each edit with its text.
+pub fn apply_line_edits(content: &str, edits: &[Edit]) -> String {
+    let mut new_content = content.to_string();
+    for edit in edits {
+        let start_line = edit.start_line.saturating_sub(1);
+        let end_line = edit.end_line.saturating_sub(1);
+        let lines: Vec<&str> = new_content.lines().collect();
+        let mut updated_lines = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            if i < start_line || i > end_line {
+                updated_lines.push(*line);
+            } else if i == start_line {
+                updated_lines.push(&edit.new_text);
+            }
+        }
+        // Edits past the end of the file (including a new file) append.
+        if start_line >= lines.len() {
+            updated_lines.push(&edit.new_text);
+        }
+        new_content = updated_lines.join("\n");
+    }
+    new_content
+}
+
+#[derive(Debug)]
+pub struct Replacement {
+    pub content: String,
+    /// 1-based index of the occurrence that was replaced.
+    pub occurrence: usize,
+    pub total: usize,
+    /// 1-based line where the replaced text started.
+    pub line: usize,
+}
+
+/// Replaces one exact occurrence of `old` in `content`.
+///
+/// Without `occurrence`, `old` must match exactly once; zero or several matches
+/// are reported as errors so the model has to anchor the edit more precisely.
+pub fn replace_in_file(content: &str, old: &str, new: &str, occurrence: Option<usize>) -> Result<Replacement, String> {
+    if old.is_empty() {
+        return Err("`old` must not be empty".to_string());
+    }
+    // Overlapping matches count too, so `aa` is ambiguous in `aaa`.
+    let matches: Vec<usize> = content
+        .char_indices()
+        .map(|(i, _)| i)
+        .filter(|&i| content[i..].starts_with(old))
+        .collect();
+    let total = matches.len();
+    let index = match (total, occurrence) {
+        (0, _) => return Err("`old` was not found in the file; read the file and copy the text exactly".to_string()),
+        (1, None) => 0,
+        (n, None) => {
+            return Err(format!(
+                "`old` is ambiguous: it matches {} times; add surrounding lines to make it unique or pass `occurrence`",
+                n
+            ))
+        }
+        (_, Some(0)) => return Err("`occurrence` is 1-based".to_string()),
+        (n, Some(k)) if k > n => return Err(format!("`occurrence` is {} but `old` only matches {} times", k, n)),
+        (_, Some(k)) => k - 1,
+    };
+    let start = matches[index];
+    let mut replaced = String::with_capacity(content.len() + new.len());
+    replaced.push_str(&content[..start]);
+    replaced.push_str(new);
+    replaced.push_str(&content[start + old.len()..]);
+    Ok(Replacement {
+        content: replaced,
+        occurrence: index + 1,
+        total,
+        line: content[..start].matches('\n').count() + 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_unique_match() {
+        let replaced = replace_in_file("fn a() {}\nfn b() {}\n", "fn b(", "fn c(", None).unwrap();
+        assert_eq!(replaced.content, "fn a() {}\nfn c() {}\n");
+        assert_eq!((replaced.occurrence, replaced.total, replaced.line), (1, 1, 2));
+    }
+
+    #[test]
+    fn replace_rejects_missing_and_ambiguous_matches() {
+        let err = replace_in_file("abc", "x", "y", None).unwrap_err();
+        assert!(err.contains("not found"), "{}", err);
+        let err = replace_in_file("x = 1;\nx = 1;\n", "x = 1;", "x = 2;", None).unwrap_err();
+        assert!(err.contains("matches 2 times"), "{}", err);
+    }
+
+    #[test]
+    fn replace_counts_overlapping_matches() {
+        let err = replace_in_file("aaa", "aa", "b", None).unwrap_err();
+        assert!(err.contains("matches 2 times"), "{}", err);
+        assert_eq!(replace_in_file("aaa", "aa", "b", Some(2)).unwrap().content, "ab");
+    }
+
+    #[test]
+    fn replace_picks_the_occurrence() {
+        let replaced = replace_in_file("x\ny\nx\n", "x", "z", Some(2)).unwrap();
+        assert_eq!(replaced.content, "x\ny\nz\n");
+        assert_eq!((replaced.occurrence, replaced.total, replaced.line), (2, 2, 3));
+        assert!(replace_in_file("x\nx\n", "x", "z", Some(0)).is_err());
+        let err = replace_in_file("x\nx\n", "x", "z", Some(3)).unwrap_err();
+        assert!(err.contains("only matches 2 times"), "{}", err);
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("quill-test-{}-{}", name, uuid::Uuid::new_v4().simple()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn run(tool_call: ToolCall, working_directory: &Path) -> String {
+        execute(tool_call, working_directory, None).await.unwrap().text
+    }
+
+    #[tokio::test]
+    async fn write_file_creates_parents_and_overwrites() {
+        let dir = temp_dir("write-file");
+
+        let text = run(ToolCall::WriteFile { path: "src/new.rs".to_string(), content: "one\n".to_string() }, &dir).await;
+        assert!(text.contains("Created file (1 lines)"), "{}", text);
+        let text = run(ToolCall::WriteFile { path: "src/new.rs".to_string(), content: "two\n".to_string() }, &dir).await;
+        assert!(text.contains("Overwrote file"), "{}", text);
+
+        assert_eq!(fs::read_to_string(dir.join("src/new.rs")).unwrap(), "two\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn create_directory_refuses_an_existing_file() {
+        let dir = temp_dir("create-directory");
+        fs::write(dir.join("taken"), "").unwrap();
+
+        run(ToolCall::CreateDirectory { path: "a/b".to_string() }, &dir).await;
+        assert!(dir.join("a/b").is_dir());
+        let text = run(ToolCall::CreateDirectory { path: "taken".to_string() }, &dir).await;
+        assert!(text.contains("a file with this name already exists"), "{}", text);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn move_path_needs_overwrite_for_an_existing_target() {
+        let dir = temp_dir("move-path");
+        fs::write(dir.join("from.txt"), "from").unwrap();
+        fs::write(dir.join("to.txt"), "to").unwrap();
+        let move_path = |overwrite| ToolCall::MovePath { from: "from.txt".to_string(), to: "to.txt".to_string(), overwrite };
+
+        let text = run(move_path(false), &dir).await;
+        assert!(text.contains("pass overwrite=true"), "{}", text);
+        assert_eq!(fs::read_to_string(dir.join("to.txt")).unwrap(), "to");
+        run(move_path(true), &dir).await;
+        assert!(!dir.join("from.txt").exists());
+        assert_eq!(fs::read_to_string(dir.join("to.txt")).unwrap(), "from");
+        let text = run(move_path(false), &dir).await;
+        assert!(text.contains("source does not exist"), "{}", text);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_path_needs_recursive_for_a_non_empty_directory() {
+        let dir = temp_dir("delete-path");
+        fs::create_dir_all(dir.join("tree/nested")).unwrap();
+        fs::write(dir.join("tree/nested/file.txt"), "").unwrap();
+        let delete = |path: &str, recursive| ToolCall::DeletePath { path: path.to_string(), recursive };
+
+        let text = run(delete("tree", false), &dir).await;
+        assert!(text.contains("pass recursive=true"), "{}", text);
+        run(delete("tree/nested/file.txt", false), &dir).await;
+        assert!(!dir.join("tree/nested/file.txt").exists());
+        run(delete("tree", true), &dir).await;
+        assert!(!dir.join("tree").exists());
+        let text = run(delete(".", true), &dir).await;
+        assert!(text.contains("refusing to delete the working directory"), "{}", text);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn risk_depends_on_what_exists() {
+        let dir = temp_dir("risk");
+        fs::write(dir.join("existing.txt"), "").unwrap();
+        let write = |path: &str| ToolCall::WriteFile { path: path.to_string(), content: String::new() };
+        let move_to = |to: &str| ToolCall::MovePath { from: "existing.txt".to_string(), to: to.to_string(), overwrite: true };
+
+        assert_eq!(write("existing.txt").risk(&dir), Risk::Destructive);
+        assert_eq!(write("new.txt").risk(&dir), Risk::Create);
+        assert_eq!(move_to("existing.txt").risk(&dir), Risk::Destructive);
+        assert_eq!(move_to("new.txt").risk(&dir), Risk::Modify);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolve_rejects_paths_outside_the_workspace() {
+        let dir = temp_dir("resolve");
+        fs::create_dir_all(dir.join("src")).unwrap();
+
+        assert_eq!(resolve_in_workspace(&dir, "src/../new/file.rs").unwrap(), dir.join("new/file.rs"));
+        assert!(resolve_in_workspace(&dir, "../outside").is_err());
+        assert!(resolve_in_workspace(&dir, "/etc/hosts").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_rejects_symlinks_that_escape_the_workspace() {
+        let dir = temp_dir("resolve-symlink");
+        let outside = temp_dir("resolve-outside");
+        std::os::unix::fs::symlink(&outside, dir.join("escape")).unwrap();
+        std::os::unix::fs::symlink(outside.join("missing"), dir.join("dangling")).unwrap();
+        std::os::unix::fs::symlink(dir.join("src"), dir.join("inside")).unwrap();
+        fs::create_dir_all(dir.join("src")).unwrap();
+
+        assert!(resolve_in_workspace(&dir, "escape").is_err());
+        assert!(resolve_in_workspace(&dir, "escape/new/file.txt").is_err());
+        assert!(resolve_in_workspace(&dir, "dangling").is_err());
+        assert!(resolve_in_workspace(&dir, "inside/main.rs").is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn line_edits_replace_the_range_once() {
+        let edit = Edit { start_line: 2, end_line: 3, new_text: "x".to_string() };
+        assert_eq!(apply_line_edits("a\nb\nc\nd", &[edit]), "a\nx\nd");
+    }
+
+    #[test]
+    fn line_edits_on_a_new_file_write_the_text() {
+        let edit = Edit { start_line: 1, end_line: 1, new_text: "fn main() {}".to_string() };
+        assert_eq!(apply_line_edits("", &[edit]), "fn main() {}");
+        let edit = Edit { start_line: 5, end_line: 5, new_text: "c".to_string() };
+        assert_eq!(apply_line_edits("a\nb", &[edit]), "a\nb\nc");
+    }
+}
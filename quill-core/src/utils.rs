@@ -1,5 +1,12 @@
+use std::path::PathBuf;
 use std::time::Duration;
 
+/// Where Quill keeps sessions, memories, checkpoints, the audit log and the
+/// code index by default.
+pub fn data_dir() -> PathBuf {
+    dirs::data_dir().unwrap_or_else(std::env::temp_dir).join("quill")
+}
+
 pub fn format_duration(duration: Duration) -> String {
     let secs = duration.as_secs();
     if secs < 60 {
@@ -19,6 +26,12 @@ pub fn truncate_string(s: &str, max_length: usize) -> String {
     }
 }
 
+/// A file name for data kept per working directory, such as its memories.
+pub fn path_key(path: &str) -> String {
+    let name: String = path.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect();
+    name.trim_matches('-').to_string()
+}
+
 pub fn format_file_size(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
@@ -1,483 +1,332 @@
-use crate::ollama::{OllamaClient, Model};
 use anyhow::Result;
 use crossterm::event::{KeyCode, KeyEvent};
-use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use futures::StreamExt;
-use std::pin::Pin;
-use std::env;
-use regex::Regex;
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Message {
-    User {
-        content: String,
-        timestamp: chrono::DateTime<chrono::Utc>,
-    },
-    Assistant {
-        content: String,
-        timestamp: chrono::DateTime<chrono::Utc>,
-    },
-    PendingToolCall {
-        tool_call: ToolCall,
-        original_message: String,
-        timestamp: chrono::DateTime<chrono::Utc>,
-    },
-    ToolCallResult {
-        result: String,
-        timestamp: chrono::DateTime<chrono::Utc>,
-    },
-    ToolCallDenied {
-        tool_call: ToolCall,
-        original_message: String,
-        timestamp: chrono::DateTime<chrono::Utc>,
-    },
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum ToolCall {
-    ReadFile { path: String },
-    ReadDirectory { path: String },
-    EditFile { path: String, edits: Vec<Edit> },
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Edit {
-    pub start_line: usize,
-    pub end_line: usize,
-    pub new_text: String,
-}
-
-#[derive(Debug, Clone)]
-pub struct PendingToolCall {
-    pub tool_call: ToolCall,
-    pub original_message: String, // The raw [tool_call: ...] string
+use quill_core::memory::{Author, Scope};
+use quill_core::{commands, mentions, Conversation};
+use std::path::Path;
+
+/// An entry of the completion popup.
+pub struct Completion {
+    /// What replaces the word being typed.
+    pub text: String,
+    pub args: String,
+    pub description: String,
 }
 
-#[derive(Debug, Clone)]
-pub struct ChatTurn {
-    pub role: String, // "user" or "assistant"
-    pub content: String,
+/// The memory manager opened with `/memory`.
+#[derive(Default)]
+pub struct MemoryScreen {
+    pub selected: usize,
+    /// Text being typed for the selected memory, or for a new one.
+    pub editing: Option<String>,
+    /// Whether `editing` is a new memory rather than the selected one.
+    pub adding: bool,
+    /// The outcome of the last change, shown at the bottom.
+    pub status: Option<String>,
 }
 
+/// The chat interface: a conversation plus what only the terminal needs,
+/// such as the input box and the log panel.
 pub struct App {
-    pub models: Vec<Model>,
-    pub selected_model_index: usize,
+    pub conversation: Conversation,
     pub input: String,
     pub input_cursor_position: usize,
-    pub messages: VecDeque<Message>,
-    pub ollama_client: OllamaClient,
-    pub is_loading: bool,
-    pub error_message: Option<String>,
-    pub streaming_message: Option<String>, // For in-progress assistant message
-    pub stream: Option<Pin<Box<dyn futures::Stream<Item = Result<String>> + Send>>>,
-    pub working_directory: String,
-    pub system_prompt: String,
     pub scroll_offset: usize,
-    pub memories: Vec<(String, String)>, // (user, assistant)
-    pub chat_history: Vec<ChatTurn>,
+    /// Whether the log panel (F2) is shown.
+    pub show_log: bool,
+    /// The highlighted entry of the completion popup.
+    pub completion: usize,
+    /// Set by `/quit`.
+    pub quit: bool,
+    /// Files offered after `@`, listed when an `@` is typed.
+    files: Vec<String>,
+    /// Set while the memory manager is open.
+    pub memory_screen: Option<MemoryScreen>,
 }
 
 impl App {
-    pub async fn new(system_prompt: String) -> Result<Self> {
-        let ollama_client = OllamaClient::new();
-        let models = ollama_client.list_models().await.unwrap_or_else(|_| {
-            vec![Model {
-                name: "llama2".to_string(),
-                modified_at: chrono::Utc::now(),
-                size: 0,
-            }]
-        });
-        let cwd = env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."));
-        Ok(App {
-            models,
-            selected_model_index: 0,
+    pub fn new(conversation: Conversation) -> Self {
+        App {
+            conversation,
             input: String::new(),
             input_cursor_position: 0,
-            messages: VecDeque::new(),
-            ollama_client,
-            is_loading: false,
-            error_message: None,
-            streaming_message: None,
-            stream: None,
-            working_directory: cwd.display().to_string(),
-            system_prompt: system_prompt.clone(),
             scroll_offset: 0,
-            memories: Vec::new(),
-            chat_history: vec![ChatTurn { role: "system".to_string(), content: system_prompt.clone() }],
-        })
+            show_log: false,
+            completion: 0,
+            quit: false,
+            files: Vec::new(),
+            memory_screen: None,
+        }
     }
 
-    fn scroll_to_bottom(&mut self) {
-        self.scroll_offset = self.messages.len().saturating_sub(1);
+    /// Where the word before the cursor starts.
+    fn word_start(&self) -> usize {
+        let before = self.input.get(..self.input_cursor_position).unwrap_or_default();
+        before.rfind(char::is_whitespace).map_or(0, |i| i + 1)
     }
 
-    // After each assistant response, push the (user, assistant) pair to memories
-    fn add_memory(&mut self, user: &str, assistant: &str) {
-        self.memories.push((user.to_string(), assistant.to_string()));
+    /// The word before the cursor.
+    fn current_word(&self) -> &str {
+        self.input.get(self.word_start()..self.input_cursor_position).unwrap_or_default()
     }
 
-    // After each user/assistant message, push to chat_history
-    fn add_user_message(&mut self, content: &str) {
-        self.chat_history.push(ChatTurn { role: "user".to_string(), content: content.to_string() });
-    }
-    fn add_assistant_message(&mut self, content: &str) {
-        self.chat_history.push(ChatTurn { role: "assistant".to_string(), content: content.to_string() });
+    /// The commands offered while the input is a partly typed `/name`, or the
+    /// files offered while the word before the cursor is a partly typed `@path`.
+    pub fn completions(&self) -> Vec<Completion> {
+        if self.conversation.is_loading {
+            return Vec::new();
+        }
+        if self.input.starts_with('/') && !self.input.contains(char::is_whitespace) {
+            return commands::matching(&self.input, &self.conversation.templates)
+                .into_iter()
+                .map(|command| Completion {
+                    text: command.name,
+                    args: command.args,
+                    description: command.description,
+                })
+                .collect();
+        }
+        match self.current_word().strip_prefix('@') {
+            // After a `:` the user is typing a line range
+            Some(query) if !query.contains(':') => mentions::complete(query, &self.files)
+                .into_iter()
+                .map(|path| Completion {
+                    text: format!("@{}", path),
+                    args: String::new(),
+                    description: String::new(),
+                })
+                .collect(),
+            _ => Vec::new(),
+        }
     }
 
-    // Build the messages array for the API call
-    fn build_messages(&self, user_message: &str) -> Vec<ChatTurn> {
-        let mut messages = self.chat_history.clone();
-        if !user_message.is_empty() {
-            messages.push(ChatTurn { role: "user".to_string(), content: user_message.to_string() });
-        }
-        messages
+    /// Replaces the word being typed with the highlighted completion.
+    fn complete(&mut self, completions: &[Completion]) {
+        let completion = &completions[self.completion.min(completions.len() - 1)];
+        let start = self.word_start();
+        // Commands are followed by their arguments; a path may get a line range
+        let text = if completion.text.starts_with('/') { format!("{} ", completion.text) } else { completion.text.clone() };
+        self.input.replace_range(start..self.input_cursor_position, &text);
+        self.input_cursor_position = start + text.len();
+        self.completion = 0;
     }
 
-    // When constructing the prompt for a new message, include all memories
-    fn build_prompt(&self, user_message: &str) -> String {
-        let mut prompt = String::new();
-        prompt.push_str(&self.system_prompt);
-        prompt.push_str("\n\n");
-        for (user, assistant) in &self.memories {
-            prompt.push_str(&format!("USER: {}\nASSISTANT: {}\n", user, assistant));
-        }
-        prompt.push_str(&format!("USER: {}\nASSISTANT:", user_message));
-        prompt
+    fn scroll_to_bottom(&mut self) {
+        self.scroll_offset = self.conversation.messages.len().saturating_sub(1);
     }
 
     pub async fn handle_input(&mut self, key: KeyEvent) -> Result<()> {
+        if key.code == KeyCode::F(2) {
+            self.show_log = !self.show_log;
+            return Ok(());
+        }
+        if self.memory_screen.is_some() {
+            self.handle_memory_key(key);
+            return Ok(());
+        }
+
+        // While a command runs, the only thing the user can do is cancel it
+        if self.conversation.running_command.is_some() {
+            if key.code == KeyCode::Esc {
+                self.conversation.stop();
+            }
+            return Ok(());
+        }
+
+        // Esc stops the reply being generated and pauses the agent run
+        if key.code == KeyCode::Esc && self.conversation.is_loading {
+            self.conversation.stop();
+            return Ok(());
+        }
+
         // If the last message is a pending tool call, handle accept/deny
-        if let Some(Message::PendingToolCall { tool_call, original_message, .. }) = self.messages.back().cloned() {
-            match key.code {
-                KeyCode::Right => {
-                    // Accept: execute the tool call and send the result as a new user message to the AI
-                    let result = self.execute_tool_call(tool_call.clone()).await?;
-                    self.messages.pop_back();
-                    // Do NOT push a user message with the result; instead, send it as a hidden user message to the AI
-                    self.start_message_sending_with_content(result).await?;
-                    return Ok(());
-                }
+        if self.conversation.pending_tool_call().is_some() {
+            let decided = match key.code {
+                KeyCode::Right => Some(self.conversation.accept_pending(false).await),
+                KeyCode::Char('a') if self.input.is_empty() => Some(self.conversation.accept_pending(true).await),
                 KeyCode::Left => {
-                    // Deny: replace the message with a denial
-                    self.messages.pop_back();
-                    self.messages.push_back(Message::ToolCallDenied {
-                        tool_call: tool_call.clone(),
-                        original_message: original_message.clone(),
-                        timestamp: chrono::Utc::now(),
-                    });
-                    return Ok(());
+                    self.conversation.deny_pending();
+                    Some(Ok(()))
                 }
-                _ => {}
+                _ => None,
+            };
+            if let Some(result) = decided {
+                self.scroll_to_bottom();
+                return result;
             }
         }
 
         // Don't process input if we're currently loading
-        if self.is_loading {
+        if self.conversation.is_loading {
             return Ok(());
         }
 
-        match key.code {
-            KeyCode::Up => {
-                if self.selected_model_index > 0 {
-                    self.selected_model_index -= 1;
+        let completions = self.completions();
+        if !completions.is_empty() {
+            let exact = completions.iter().any(|completion| completion.text == self.current_word());
+            match key.code {
+                KeyCode::Up => {
+                    self.completion = self.completion.min(completions.len() - 1).saturating_sub(1);
+                    return Ok(());
                 }
-            }
-            KeyCode::Down => {
-                if self.selected_model_index < self.models.len().saturating_sub(1) {
-                    self.selected_model_index += 1;
+                KeyCode::Down => {
+                    self.completion = (self.completion + 1).min(completions.len() - 1);
+                    return Ok(());
                 }
-            }
-            KeyCode::Char(c) => {
-                // Allow all printable characters except when Control is held
-                use crossterm::event::KeyModifiers;
-                if !key.modifiers.contains(KeyModifiers::CONTROL) {
-                    self.input.insert(self.input_cursor_position, c);
-                    self.input_cursor_position += 1;
+                KeyCode::Tab => {
+                    self.complete(&completions);
+                    return Ok(());
                 }
-            }
-            KeyCode::Backspace => {
-                if self.input_cursor_position > 0 {
-                    self.input.remove(self.input_cursor_position - 1);
-                    self.input_cursor_position -= 1;
+                KeyCode::Enter if !exact => {
+                    self.complete(&completions);
+                    return Ok(());
                 }
+                _ => self.completion = 0,
             }
-            KeyCode::Delete => {
-                if self.input_cursor_position < self.input.len() {
-                    self.input.remove(self.input_cursor_position);
-                }
+        }
+
+        let conversation = &mut self.conversation;
+        match key.code {
+            KeyCode::Up if conversation.selected_model_index > 0 => {
+                conversation.selected_model_index -= 1;
             }
-            KeyCode::Left => {
-                if self.input_cursor_position > 0 {
-                    self.input_cursor_position -= 1;
-                }
+            KeyCode::Down if conversation.selected_model_index < conversation.models.len().saturating_sub(1) => {
+                conversation.selected_model_index += 1;
             }
-            KeyCode::Right => {
-                if self.input_cursor_position < self.input.len() {
-                    self.input_cursor_position += 1;
+            // Allow all printable characters except when Control is held
+            KeyCode::Char(c) if !key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) => {
+                self.input.insert(self.input_cursor_position, c);
+                self.input_cursor_position += 1;
+                if c == '@' && self.current_word() == "@" {
+                    self.files = mentions::files(Path::new(&self.conversation.working_directory));
                 }
             }
-            KeyCode::Enter => {
-                if !self.input.trim().is_empty() {
-                    self.start_message_sending().await?;
-                }
+            KeyCode::Backspace if self.input_cursor_position > 0 => {
+                self.input.remove(self.input_cursor_position - 1);
+                self.input_cursor_position -= 1;
             }
-            _ => {}
-        }
-        Ok(())
-    }
-
-    async fn start_message_sending(&mut self) -> Result<()> {
-        let user_message = self.input.clone();
-        self.input.clear();
-        self.input_cursor_position = 0;
-
-        self.add_user_message(&user_message);
-        self.messages.push_back(Message::User {
-            content: user_message.clone(),
-            timestamp: chrono::Utc::now(),
-        });
-
-        if self.messages.len() > 50 {
-            self.messages.pop_front();
-        }
-
-        self.is_loading = true;
-        self.error_message = None;
-        self.streaming_message = Some(String::new());
-
-        let selected_model = &self.models[self.selected_model_index];
-        let messages = self.build_messages("");
-        match self.ollama_client.chat_stream(selected_model.name.clone(), messages).await {
-            Ok(stream) => {
-                self.stream = Some(stream);
+            KeyCode::Delete if self.input_cursor_position < self.input.len() => {
+                self.input.remove(self.input_cursor_position);
+            }
+            KeyCode::Left if self.input_cursor_position > 0 => {
+                self.input_cursor_position -= 1;
             }
-            Err(e) => {
-                self.error_message = Some(format!("Error: {}", e));
-                self.is_loading = false;
-                self.streaming_message = None;
+            KeyCode::Right if self.input_cursor_position < self.input.len() => {
+                self.input_cursor_position += 1;
             }
+            KeyCode::Enter if !self.input.trim().is_empty() => {
+                let text = std::mem::take(&mut self.input);
+                self.input_cursor_position = 0;
+                self.submit(&text).await?;
+            }
+            _ => {}
         }
         Ok(())
     }
 
-    pub async fn start_message_sending_with_content(&mut self, content: String) -> Result<()> {
-        let user_message = content;
-        self.input.clear();
-        self.input_cursor_position = 0;
-
-        self.add_user_message(&user_message);
-        if self.messages.len() > 50 {
-            self.messages.pop_front();
-        }
-
-        self.is_loading = true;
-        self.error_message = None;
-        self.streaming_message = Some(String::new());
-
-        let selected_model = &self.models[self.selected_model_index];
-        let messages = self.build_messages("");
-        match self.ollama_client.chat_stream(selected_model.name.clone(), messages).await {
-            Ok(stream) => {
-                self.stream = Some(stream);
+    /// Sends `text` as if it had been typed into the input box.
+    pub async fn submit(&mut self, text: &str) -> Result<()> {
+        match text.trim() {
+            "/quit" => {
+                self.quit = true;
+                return Ok(());
             }
-            Err(e) => {
-                self.error_message = Some(format!("Error: {}", e));
-                self.is_loading = false;
-                self.streaming_message = None;
+            "/memory" => {
+                self.memory_screen = Some(MemoryScreen::default());
+                return Ok(());
             }
+            _ => {}
+        }
+        self.conversation.submit(text).await?;
+        if let Some(draft) = self.conversation.draft.take() {
+            self.input_cursor_position = draft.len();
+            self.input = draft;
         }
+        self.scroll_to_bottom();
         Ok(())
     }
 
-    pub async fn process_streaming(&mut self) -> Result<()> {
-        if let Some(ref mut stream) = self.stream {
-            // Try to get the next chunk with a very short timeout
-            match tokio::time::timeout(std::time::Duration::from_millis(10), stream.next()).await {
-                Ok(Some(Ok(chunk))) => {
-                    // Process each line of the response
-                    for line in chunk.lines() {
-                        let trimmed = line.trim();
-                        if trimmed.is_empty() {
-                            continue;
-                        }
-                        // Try to parse as JSON
-                        if let Ok(json) = serde_json::from_str::<serde_json::Value>(trimmed) {
-                            // Only append if there is actual assistant message content
-                            if let Some(content) = json.get("message").and_then(|m| m.get("content")).and_then(|c| c.as_str()) {
-                                if !content.is_empty() {
-                                    if let Some(ref mut streaming) = self.streaming_message {
-                                        streaming.push_str(content);
-                                    }
-                                }
-                            } else if let Some(content) = json.get("response").and_then(|c| c.as_str()) {
-                                if !content.is_empty() {
-                                    if let Some(ref mut streaming) = self.streaming_message {
-                                        streaming.push_str(content);
-                                    }
-                                }
-                            } else if let Some(done) = json.get("done").and_then(|d| d.as_bool()) {
-                                if done {
-                                    self.finish_streaming();
-                                    return Ok(());
-                                }
-                            }
-                        }
-                        // Ignore lines that are not valid JSON or do not contain a message
-                    }
-                }
-                Ok(Some(Err(_e))) => {
-                    self.error_message = Some("Stream error".to_string());
-                    self.finish_streaming();
-                }
-                Ok(None) => {
-                    // Stream finished
-                    self.finish_streaming();
+    /// Keys in the memory manager: ↑/↓ select, Enter or `e` edits, `n` adds,
+    /// `g` moves a memory between project and global, `d` deletes and Esc
+    /// closes. While editing, Enter saves and Esc cancels.
+    fn handle_memory_key(&mut self, key: KeyEvent) {
+        let Some(screen) = self.memory_screen.as_mut() else {
+            return;
+        };
+        let store = &mut self.conversation.memory;
+        if let Some(text) = screen.editing.as_mut() {
+            match key.code {
+                KeyCode::Char(c) if !key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) => text.push(c),
+                KeyCode::Backspace => {
+                    text.pop();
                 }
-                Err(_) => {
-                    // Timeout - this is expected, just continue
+                KeyCode::Esc => {
+                    screen.editing = None;
+                    screen.status = None;
                 }
-            }
-        }
-        Ok(())
-    }
-
-    fn finish_streaming(&mut self) {
-        // When done, push the full message to history
-        if let Some(content) = self.streaming_message.take() {
-            if !content.trim().is_empty() {
-                self.add_assistant_message(&content);
-                self.messages.push_back(Message::Assistant {
-                    content: content.clone(),
-                    timestamp: chrono::Utc::now(),
-                });
-                self.scroll_to_bottom();
-                // Parse for tool calls in the assistant message
-                self.parse_tool_calls(&content);
-                // Add memory after each assistant response
-                if let Some(last_user_message) = self.chat_history.iter().rfind(|m| m.role == "user").cloned() {
-                    self.add_memory(&last_user_message.content, &content);
+                KeyCode::Enter => {
+                    let text = screen.editing.take().unwrap_or_default();
+                    let result = if screen.adding {
+                        store.add(&text, Scope::Project, Author::User).map(|memory| memory.id.clone())
+                    } else {
+                        store.edit(screen.selected, &text).map(|()| store.memories[screen.selected].id.clone())
+                    };
+                    screen.status = Some(match result {
+                        Ok(id) => {
+                            if let Some(index) = store.memories.iter().position(|memory| memory.id == id) {
+                                screen.selected = index;
+                            }
+                            format!("Saved {}.", id)
+                        }
+                        Err(e) => {
+                            // Let the user fix the text instead of losing it
+                            screen.editing = Some(text);
+                            format!("{:#}", e)
+                        }
+                    });
                 }
+                _ => {}
             }
+            return;
         }
-        self.is_loading = false;
-        self.stream = None;
-    }
-
-    pub fn parse_tool_calls(&mut self, message: &str) {
-        use regex::Regex;
-        use serde_json::Value;
-        // Regex for edit_file
-        let re_edit = Regex::new(r#"(?s)\[tool_call:\s*edit_file\(path=['"](.*?)['"],\s*edits=(\[.*\])\)\]"#).unwrap();
-        if let Some(cap) = re_edit.captures(message) {
-            let path = cap[1].trim().to_string();
-            let edits_json = &cap[2];
-            if let Ok(edits) = serde_json::from_str::<Vec<Edit>>(edits_json) {
-                self.messages.push_back(Message::PendingToolCall {
-                    tool_call: ToolCall::EditFile { path: path.clone(), edits },
-                    original_message: format!("edit_file(path=\"{}\", edits={})", path, edits_json),
-                    timestamp: chrono::Utc::now(),
-                });
-                return;
+        let count = store.memories.len();
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('q') => self.memory_screen = None,
+            KeyCode::Up => screen.selected = screen.selected.saturating_sub(1),
+            KeyCode::Down if screen.selected + 1 < count => screen.selected += 1,
+            KeyCode::Char('n') => {
+                screen.editing = Some(String::new());
+                screen.adding = true;
             }
-        }
-        // Only allow one pending tool call at a time
-        let re = Regex::new(r#"\[tool_call:\s*(read_file|read_directory)\((?:path\s*=\s*)?['"](.*?)['"]\)\]"#).unwrap();
-        if let Some(cap) = re.captures(message) {
-            let tool = &cap[1];
-            let path = cap[2].trim();
-            let tc = match tool {
-                "read_file" => ToolCall::ReadFile { path: path.to_string() },
-                "read_directory" => ToolCall::ReadDirectory { path: path.to_string() },
-                _ => return,
-            };
-            self.messages.push_back(Message::PendingToolCall {
-                tool_call: tc,
-                original_message: format!("{}(\"{}\")", tool, path),
-                timestamp: chrono::Utc::now(),
-            });
-            self.scroll_to_bottom();
-        }
-    }
-
-    pub async fn execute_tool_call(&self, tool_call: ToolCall) -> Result<String> {
-        use std::fs;
-        use std::path::PathBuf;
-        match tool_call {
-            ToolCall::ReadFile { path } => {
-                let mut pb = PathBuf::from(&self.working_directory);
-                pb.push(&path);
-                match fs::read_to_string(&pb) {
-                    Ok(content) => Ok(format!("[TOOL RESULT: read_file]\nPath: {}\n---\n{}", pb.display(), content)),
-                    Err(e) => Ok(format!("[TOOL ERROR: read_file]\nPath: {}\nError: {}", pb.display(), e)),
-                }
+            KeyCode::Enter | KeyCode::Char('e') if screen.selected < count => {
+                screen.editing = Some(store.memories[screen.selected].text.clone());
+                screen.adding = false;
             }
-            ToolCall::ReadDirectory { path } => {
-                let mut pb = PathBuf::from(&self.working_directory);
-                pb.push(&path);
-                match fs::read_dir(&pb) {
-                    Ok(entries) => {
-                        let mut list = Vec::new();
-                        for entry in entries.flatten() {
-                            let file_type = entry.file_type().ok();
-                            let name = entry.file_name().to_string_lossy().to_string();
-                            let kind = if let Some(ft) = file_type {
-                                if ft.is_dir() { "[DIR]" } else { "[FILE]" }
-                            } else { "[?]" };
-                            list.push(format!("{} {}", kind, name));
-                        }
-                        Ok(format!("[TOOL RESULT: read_directory]\nPath: {}\n---\n{}", pb.display(), list.join("\n")))
+            KeyCode::Char('g') if screen.selected < count => {
+                screen.status = Some(match store.toggle_scope(screen.selected) {
+                    Ok(index) => {
+                        screen.selected = index;
+                        format!("Moved to {} memories.", store.memories[index].scope.label())
                     }
-                    Err(e) => Ok(format!("[TOOL ERROR: read_directory]\nPath: {}\nError: {}", pb.display(), e)),
-                }
+                    Err(e) => format!("{:#}", e),
+                });
             }
-            ToolCall::EditFile { path, edits } => {
-                let mut pb = PathBuf::from(&self.working_directory);
-                pb.push(&path);
-                let mut current_content = String::new();
-                if pb.exists() {
-                    current_content = fs::read_to_string(&pb)?;
-                }
-
-                let mut new_content = current_content.clone();
-                for edit in edits {
-                    let start_line = edit.start_line.saturating_sub(1);
-                    let end_line = edit.end_line.saturating_sub(1);
-                    let new_text = edit.new_text;
-
-                    let lines: Vec<&str> = new_content.lines().collect();
-                    let mut updated_lines = Vec::new();
-                    for i in 0..lines.len() {
-                        if i < start_line {
-                            updated_lines.push(lines[i]);
-                        } else if i >= start_line && i <= end_line {
-                            updated_lines.push(&new_text);
-                        } else {
-                            updated_lines.push(lines[i]);
-                        }
-                    }
-                    new_content = updated_lines.join("\n");
-                }
-
-                fs::write(&pb, &new_content)?;
-                Ok(format!("[TOOL RESULT: edit_file]\nPath: {}\n---\n{}", pb.display(), new_content))
+            KeyCode::Char('d') | KeyCode::Delete if screen.selected < count => {
+                screen.status = Some(match store.remove(screen.selected) {
+                    Ok(memory) => format!("Deleted {}.", memory.id),
+                    Err(e) => format!("{:#}", e),
+                });
+                screen.selected = screen.selected.min(store.memories.len().saturating_sub(1));
             }
+            _ => {}
         }
     }
 
-    fn deny_tool_call(&mut self, pending: PendingToolCall) {
-        let msg = format!(
-            "[TOOL DENIED]\nTool call was denied by the user:\n[tool_call: {}]",
-            pending.original_message
-        );
-        self.messages.push_back(Message::Assistant {
-            content: msg,
-            timestamp: chrono::Utc::now(),
-        });
-        self.scroll_to_bottom();
-    }
-
-    pub fn get_selected_model(&self) -> Option<&Model> {
-        self.models.get(self.selected_model_index)
+    /// Streams the reply or command output, if any.
+    pub async fn poll(&mut self) -> Result<()> {
+        if self.conversation.is_busy() {
+            self.conversation.poll().await?;
+            self.scroll_to_bottom();
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,137 @@
+//! Command-line arguments.
+
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use quill_core::config::Config;
+use quill_core::ollama::OllamaClient;
+use std::path::PathBuf;
+
+/// Ollama's default address.
+pub const DEFAULT_BASE_URL: &str = "http://localhost:11434";
+
+#[derive(Debug, Parser)]
+#[command(name = "quill", version, about = "A terminal coding assistant for local models")]
+pub struct Cli {
+    #[command(flatten)]
+    pub settings: Settings,
+    /// Send PROMPT, plus anything piped to stdin, print the reply and exit
+    #[arg(short = 'p', long = "print", value_name = "PROMPT")]
+    pub print: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Options shared by every subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct Settings {
+    /// Model to use (defaults to the first one the provider lists)
+    #[arg(long, short, global = true)]
+    pub model: Option<String>,
+    /// Where models come from
+    #[arg(long, value_enum, default_value_t = Provider::Ollama, global = true)]
+    pub provider: Provider,
+    /// Address of the provider's API
+    #[arg(long, default_value = DEFAULT_BASE_URL, global = true)]
+    pub base_url: String,
+    /// Work in this directory instead of the current one
+    #[arg(long, global = true)]
+    pub cwd: Option<PathBuf>,
+    /// Use the contents of this file as the system prompt
+    #[arg(long, global = true)]
+    pub system_prompt_file: Option<PathBuf>,
+    /// Continue a saved session: the latest one in this directory, or --resume=ID
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "", global = true)]
+    pub resume: Option<String>,
+    /// Chat without offering any tools to the model
+    #[arg(long, global = true)]
+    pub no_tools: bool,
+    /// Do not add QUILL.md and AGENTS.md files to the system prompt
+    #[arg(long, global = true)]
+    pub no_instructions: bool,
+    /// Config file to read instead of the default one
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Keep sessions, memories, checkpoints, the audit log and the index here
+    #[arg(long, global = true)]
+    pub data_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Provider {
+    Ollama,
+}
+
+/// What `quill run --json` and `quill mcp-serve` do with tool calls nobody is
+/// there to approve. Allow and deny rules in the config apply first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Approve {
+    /// Refuse them
+    Never,
+    /// Run read-only calls, refuse the rest
+    Reads,
+    /// Run them all
+    All,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Open the chat interface (the default)
+    Chat,
+    /// Open the chat interface and send TASK as the first message
+    Run {
+        /// Run without the chat interface, writing JSON events to stdout
+        #[arg(long)]
+        json: bool,
+        /// Which calls that the config leaves at `ask` to run with --json
+        #[arg(long, value_enum, default_value_t = Approve::Never, requires = "json")]
+        approve: Approve,
+        #[arg(required = true, trailing_var_arg = true)]
+        task: Vec<String>,
+    },
+    /// List the models the provider has
+    Models,
+    /// List saved chat sessions
+    Sessions {
+        /// Include sessions from every directory
+        #[arg(long)]
+        all: bool,
+    },
+    /// Print a shell completion script
+    Completions { shell: clap_complete::Shell },
+    /// Offer Quill's file, search and git tools to MCP clients over stdio
+    McpServe {
+        /// Which calls that the config leaves at `ask` to run
+        #[arg(long, value_enum, default_value_t = Approve::Reads)]
+        approve: Approve,
+    },
+    /// Serve an HTTP API on localhost for editors and dashboards to drive conversations
+    Serve {
+        /// Port to listen on (0 picks a free one)
+        #[arg(long, default_value_t = 8484)]
+        port: u16,
+        /// Token clients must send; a random one is printed if not given
+        #[arg(long)]
+        token: Option<String>,
+    },
+}
+
+impl Settings {
+    pub fn client(&self) -> OllamaClient {
+        match self.provider {
+            Provider::Ollama => OllamaClient::new(&self.base_url),
+        }
+    }
+
+    /// `--data-dir`, or the default data directory.
+    pub fn data_dir(&self) -> PathBuf {
+        self.data_dir.clone().unwrap_or_else(quill_core::utils::data_dir)
+    }
+
+    /// Loads `--config`, or the default config file.
+    pub fn load_config(&self) -> Result<Config> {
+        match &self.config {
+            Some(path) => Config::load_from(path),
+            None => Config::load(),
+        }
+    }
+}
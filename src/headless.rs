@@ -0,0 +1,229 @@
+//! `quill run --json TASK`: runs an agent task with no terminal and writes
+//! every `Event` to stdout as one JSON object per line, ending with `final`.
+//!
+//! Tool calls follow the permission config as in the chat interface, with
+//! agent mode on. Calls the config leaves at `ask` are decided by `--approve`
+//! and refused calls are reported to the model so it can carry on.
+
+use crate::cli::Approve;
+use quill_core::conversation::Message;
+use quill_core::tools::Risk;
+use quill_core::{Conversation, Event};
+use anyhow::{bail, Result};
+use std::io::Write;
+use std::time::Duration;
+
+/// Runs `task`, writing the events to `out`. Fails, after the `final`
+/// event, when the run ends with an error or is stopped.
+pub async fn run(conversation: &mut Conversation, task: &str, approve: Approve, out: &mut impl Write) -> Result<()> {
+    let mut events = conversation.subscribe();
+    conversation.config.agent.enabled = true;
+    // Notices from startup, such as MCP servers that failed to start
+    for message in &conversation.messages {
+        if let Message::Info { content, .. } = message {
+            print_event(out, &Event::Info { message: content.clone() })?;
+        }
+    }
+
+    let mut error = None;
+    let mut stopped = None;
+    conversation.submit(task).await?;
+    loop {
+        while let Ok(event) = events.try_recv() {
+            if let Event::Error { message } = &event {
+                error = Some(message.clone());
+            }
+            print_event(out, &event)?;
+        }
+        if conversation.is_busy() {
+            conversation.poll().await?;
+            if conversation.running_command.is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            continue;
+        }
+        let Some((_, risk)) = conversation.pending_tool_call() else {
+            break;
+        };
+        if let Some(reason) = conversation.agent_pause_reason() {
+            stopped = Some(reason);
+            break;
+        }
+        let allowed = match approve {
+            Approve::Never => false,
+            Approve::Reads => risk == Risk::Read,
+            Approve::All => true,
+        };
+        if allowed {
+            conversation.accept_pending(false).await?;
+        } else {
+            let policy = format!("unattended run with --approve {:?}", approve).to_lowercase();
+            conversation.block_pending(policy).await?;
+        }
+    }
+    while let Ok(event) = events.try_recv() {
+        print_event(out, &event)?;
+    }
+
+    let status = match (&error, &stopped) {
+        (Some(_), _) => "error",
+        (None, Some(_)) => "stopped",
+        (None, None) => "done",
+    };
+    let answer = conversation
+        .chat_history
+        .iter()
+        .rfind(|turn| turn.role == "assistant")
+        .map(|turn| turn.content.clone())
+        .unwrap_or_default();
+    print_event(out, &Event::Final {
+        status: status.to_string(),
+        answer,
+        steps: conversation.run.steps,
+        tokens: conversation.run.tokens,
+    })?;
+    match (error, stopped) {
+        (Some(error), _) => bail!("{}", error),
+        (None, Some(reason)) => bail!("agent stopped: {}", reason),
+        (None, None) => Ok(()),
+    }
+}
+
+fn print_event(out: &mut impl Write, event: &Event) -> Result<()> {
+    writeln!(out, "{}", serde_json::to_string(event)?)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{agent, fake_ollama, TempDir};
+    use quill_core::ollama::OllamaClient;
+    use quill_core::Options;
+    use serde_json::{json, Value};
+
+    /// Runs `task` in `dir`/work against a model answering with `chat`, and
+    /// returns the events written, each checked to be one JSON object.
+    async fn run_task(dir: &TempDir, config: Value, chat: fn(Value) -> String, task: &str, approve: Approve) -> (Vec<Value>, Result<()>) {
+        let working_directory = dir.path().join("work");
+        std::fs::create_dir_all(&working_directory).unwrap();
+        let mut config = config;
+        config["index"] = json!({ "enabled": false });
+        let mut conversation = Conversation::new(Options {
+            client: OllamaClient::new(&fake_ollama(chat)),
+            system_prompt: String::new(),
+            model: None,
+            config: serde_json::from_value(config).unwrap(),
+            tools: true,
+            working_directory,
+            data_dir: dir.path().join("data"),
+            resume: None,
+        })
+        .await
+        .unwrap();
+        let mut out = Vec::new();
+        let result = tokio::time::timeout(Duration::from_secs(10), run(&mut conversation, task, approve, &mut out))
+            .await
+            .expect("the run did not finish");
+        let events: Vec<Value> = String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert!(events.iter().all(|event| event["type"].is_string()), "{:?}", events);
+        (events, result)
+    }
+
+    fn of_type<'a>(events: &'a [Value], kind: &str) -> Vec<&'a Value> {
+        events.iter().filter(|event| event["type"] == kind).collect()
+    }
+
+    #[tokio::test]
+    async fn a_finished_task_ends_with_a_done_final_event() {
+        let dir = TempDir::new("headless-done");
+        let (events, result) = run_task(&dir, json!({}), agent, "hi", Approve::Never).await;
+
+        assert!(result.is_ok());
+        assert_eq!(of_type(&events, "assistant_message")[0]["content"], "Hello there");
+        assert_eq!(
+            events.last().unwrap(),
+            &json!({ "type": "final", "status": "done", "answer": "Hello there", "steps": 0, "tokens": 0 })
+        );
+    }
+
+    #[tokio::test]
+    async fn approve_reads_runs_reads_and_refuses_writes() {
+        let dir = TempDir::new("headless-reads");
+        std::fs::create_dir_all(dir.path().join("work")).unwrap();
+        std::fs::write(dir.path().join("work").join("notes.txt"), "remember the milk").unwrap();
+
+        let (events, result) = run_task(&dir, json!({}), agent, r#"[tool_call: read_file(path="notes.txt")]"#, Approve::Reads).await;
+        assert!(result.is_ok());
+        let proposed = of_type(&events, "tool_call_proposed");
+        assert_eq!(proposed[0]["tool"], "read_file");
+        assert_eq!(proposed[0]["risk"], "Read");
+        assert_eq!(of_type(&events, "tool_decision")[0]["decision"], "accepted");
+        let results = of_type(&events, "tool_result");
+        assert_eq!(results[0]["error"], false);
+        assert!(results[0]["output"].as_str().unwrap().contains("remember the milk"));
+        assert_eq!(events.last().unwrap()["answer"], "Finished.");
+
+        let (events, result) =
+            run_task(&dir, json!({}), agent, r#"[tool_call: write_file(path="new.txt", content="x")]"#, Approve::Reads).await;
+        assert!(result.is_ok());
+        let decision = of_type(&events, "tool_decision");
+        assert_eq!(decision[0]["decision"], "blocked");
+        assert_eq!(decision[0]["reason"], "unattended run with --approve reads");
+        assert!(of_type(&events, "tool_result").is_empty());
+        assert!(!dir.path().join("work").join("new.txt").exists());
+        assert_eq!(events.last().unwrap()["status"], "done");
+    }
+
+    #[tokio::test]
+    async fn approve_never_refuses_every_call_the_config_leaves_at_ask() {
+        let dir = TempDir::new("headless-never");
+        let (events, result) = run_task(&dir, json!({}), agent, r#"[tool_call: read_file(path="notes.txt")]"#, Approve::Never).await;
+
+        assert!(result.is_ok());
+        let decision = of_type(&events, "tool_decision");
+        assert_eq!(decision[0]["decision"], "blocked");
+        assert_eq!(decision[0]["reason"], "unattended run with --approve never");
+        assert!(of_type(&events, "tool_result").is_empty());
+        assert_eq!(events.last().unwrap()["answer"], "Finished.");
+    }
+
+    #[tokio::test]
+    async fn approve_all_runs_writes() {
+        let dir = TempDir::new("headless-all");
+        let (events, result) =
+            run_task(&dir, json!({}), agent, r#"[tool_call: write_file(path="new.txt", content="x")]"#, Approve::All).await;
+
+        assert!(result.is_ok());
+        assert_eq!(of_type(&events, "tool_result")[0]["files_changed"], json!(["new.txt"]));
+        assert_eq!(std::fs::read_to_string(dir.path().join("work").join("new.txt")).unwrap(), "x");
+        assert_eq!(events.last().unwrap()["steps"], 1);
+    }
+
+    #[tokio::test]
+    async fn stopped_runs_fail_after_a_stopped_final_event() {
+        let dir = TempDir::new("headless-stopped");
+        let config = json!({ "agent": { "max_steps": 0 } });
+        let (events, result) = run_task(&dir, config, agent, r#"[tool_call: read_file(path="notes.txt")]"#, Approve::All).await;
+
+        assert_eq!(result.unwrap_err().to_string(), "agent stopped: reached the limit of 0 steps");
+        assert!(of_type(&events, "tool_decision").is_empty());
+        assert_eq!(events.last().unwrap()["status"], "stopped");
+    }
+
+    #[tokio::test]
+    async fn provider_errors_fail_after_an_error_final_event() {
+        let dir = TempDir::new("headless-error");
+        let chat = |_| format!("{}\n", json!({ "error": "model 'fake:1b' not found" }));
+        let (events, result) = run_task(&dir, json!({}), chat, "hi", Approve::Never).await;
+
+        assert!(result.unwrap_err().to_string().contains("model 'fake:1b' not found"));
+        assert_eq!(of_type(&events, "error").len(), 1);
+        assert_eq!(events.last().unwrap()["status"], "error");
+    }
+}
@@ -1,10 +1,25 @@
 mod app;
-mod ollama;
+mod cli;
+mod headless;
+mod mcp_server;
+mod oneshot;
+mod server;
+#[cfg(test)]
+mod testing;
 mod ui;
-mod utils;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use app::App;
+use clap::{CommandFactory, Parser};
+use cli::{Cli, Command, Settings};
+use quill_core::config::Config;
+use quill_core::instructions::{self, Instructions};
+use quill_core::logging;
+use quill_core::session::Session;
+use quill_core::utils::format_file_size;
+use quill_core::{Conversation, Options};
+use std::fs;
+use std::path::Path;
 use crossterm::{
     cursor::{Hide, Show},
     event::{self, DisableMouseCapture, Event, KeyCode, KeyEventKind},
@@ -14,11 +29,100 @@ use crossterm::{
 use std::io;
 use tui::{
     backend::{Backend, CrosstermBackend},
-    Frame, Terminal,
+    Terminal,
 };
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    logging::init();
+    let cli = Cli::parse();
+    let settings = cli.settings;
+    if let Some(dir) = &settings.cwd {
+        std::env::set_current_dir(dir).with_context(|| format!("cannot change to {}", dir.display()))?;
+    }
+    let cwd = std::env::current_dir()?;
+    if let Some(prompt) = &cli.print {
+        if cli.command.is_some() {
+            bail!("-p cannot be combined with a subcommand");
+        }
+        let (system_prompt, _) = system_prompt(&settings, &cwd, false)?;
+        return oneshot::run(&settings, system_prompt, prompt).await;
+    }
+    match cli.command.unwrap_or(Command::Chat) {
+        Command::Chat => run_tui(&settings, None).await,
+        Command::Run { task, json: false, .. } => run_tui(&settings, Some(task.join(" "))).await,
+        Command::Run { task, json: true, approve } => {
+            let mut conversation = new_conversation(&settings).await?;
+            headless::run(&mut conversation, &task.join(" "), approve, &mut io::stdout()).await
+        }
+        Command::Models => list_models(&settings).await,
+        Command::Sessions { all } => list_sessions(&settings.data_dir(), &cwd, all),
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "quill", &mut io::stdout());
+            Ok(())
+        }
+        Command::McpServe { approve } => mcp_server::serve(&cwd, &settings.data_dir(), &settings.load_config()?, approve).await,
+        Command::Serve { port, token } => server::serve(settings, port, token).await,
+    }
+}
+
+/// Starts a conversation with the system prompt and session the settings ask for.
+async fn new_conversation(settings: &Settings) -> Result<Conversation> {
+    let working_directory = std::env::current_dir()?;
+    let cwd = working_directory.display().to_string();
+    let (system_prompt, instruction_files) = system_prompt(settings, &working_directory, !settings.no_tools)?;
+    let resume = match &settings.resume {
+        Some(id) => Some(Session::find(&settings.data_dir(), id, &cwd)?),
+        None => None,
+    };
+    let (config, config_error) = match settings.load_config() {
+        Ok(config) => (config, None),
+        Err(e) => (Config::default(), Some(e)),
+    };
+    let mut conversation = Conversation::new(Options {
+        client: settings.client(),
+        system_prompt,
+        model: settings.model.clone(),
+        config,
+        tools: !settings.no_tools,
+        working_directory,
+        data_dir: settings.data_dir(),
+        resume,
+    })
+    .await?;
+    if let Some(e) = config_error {
+        conversation.push_info(format!("Using the default settings: {:#}", e));
+    }
+    if !instruction_files.is_empty() {
+        let paths: Vec<String> = instruction_files.iter().map(|file| file.path.display().to_string()).collect();
+        conversation.push_info(format!("Following the instructions in {}.", paths.join(", ")));
+    }
+    Ok(conversation)
+}
+
+/// The system prompt for a conversation in `working_directory`, followed by
+/// the project instructions that apply there unless `--no-instructions` is
+/// given. Also returns the instruction files, to tell the user which ones
+/// were read.
+fn system_prompt(settings: &Settings, working_directory: &Path, tools: bool) -> Result<(String, Vec<Instructions>)> {
+    let cwd = working_directory.display().to_string();
+    let base = match &settings.system_prompt_file {
+        Some(path) => fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?,
+        None if tools => default_system_prompt(&cwd),
+        None => chat_system_prompt(&cwd),
+    };
+    let files = if settings.no_instructions { Vec::new() } else { instructions::discover(working_directory) };
+    Ok((base + &instructions::prompt_section(&files), files))
+}
+
+/// Runs the chat interface, sending `task` first if given.
+async fn run_tui(settings: &Settings, task: Option<String>) -> Result<()> {
+    // Everything that can fail with a message happens before the screen is taken over
+    let mut app = App::new(new_conversation(settings).await?);
+    if let Some(task) = task {
+        app.submit(&task).await?;
+    }
+
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -26,11 +130,6 @@ async fn main() -> Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // Create app state
-    let cwd = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("."));
-    let system_prompt = default_system_prompt(&cwd.display().to_string());
-    let mut app = App::new(system_prompt).await?;
-
     // Run the app
     let res = run_app(&mut terminal, &mut app).await;
 
@@ -51,6 +150,63 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+async fn list_models(settings: &Settings) -> Result<()> {
+    let models = settings.client().list_models().await?;
+    for model in models {
+        println!(
+            "{:<40} {:>10}  {}",
+            model.name,
+            format_file_size(model.size),
+            model.modified_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+        );
+    }
+    Ok(())
+}
+
+/// Prints the saved sessions for `cwd`, or for every directory with `all`.
+fn list_sessions(data_dir: &Path, cwd: &Path, all: bool) -> Result<()> {
+    let cwd = cwd.display().to_string();
+    let sessions: Vec<Session> = Session::list(data_dir)?
+        .into_iter()
+        .filter(|s| all || s.working_directory == cwd)
+        .collect();
+    if sessions.is_empty() {
+        println!("No saved sessions{}.", if all { "" } else { " in this directory (--all shows every directory)" });
+        return Ok(());
+    }
+    for session in sessions {
+        print!(
+            "{}  {}  {:>3} msgs  {:<20}  {}",
+            session.id,
+            session.updated.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"),
+            session.turns.len(),
+            session.model,
+            session.title()
+        );
+        if all {
+            print!("  ({})", session.working_directory);
+        }
+        println!();
+    }
+    println!("\nResume one with quill --resume=ID");
+    Ok(())
+}
+
+/// The system prompt used with `--no-tools` and `-p`.
+fn chat_system_prompt(working_directory: &str) -> String {
+    format!(
+        "You are Quill, a helpful assistant for software development running in a terminal.\n\
+Environment context:\n\
+- Operating System: {}\n\
+- Architecture: {}\n\
+- Working Directory: {}\n\
+You have no tools in this conversation and cannot read or change files yourself; work from what the user shares and ask for anything else you need.",
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        working_directory
+    )
+}
+
 /// Returns a default system prompt for the chat model.
 fn default_system_prompt(working_directory: &str) -> String {
     use std::env;
@@ -68,8 +224,24 @@ Environment context:\n\
 All tools that require a path or a file should default to using the working directory as the default path.\n\
 Available tools and their precise functions:\n\
   - read_directory(path: str): Lists all files and directories within the specified directory path.\n\
-  - read_file(path: str): Reads and returns the contents of a single file at the given path.\n\
-  - edit_file(path: str, edits: list): Edits or creates the specified file at the given path by applying a list of edits, where each edit specifies the start and end lines to replace and the new text to insert.\n\
+  - read_file(path: str, start_line: int = none, end_line: int = none): Reads a file and returns its lines prefixed with their 1-based line number and a tab. Use start_line/end_line to read part of a large file; long output is truncated. The line number prefixes are not part of the file, never copy them into edits.\n\
+  - grep(pattern: str, path: str = \".\", glob: str = none, context: int = 0): Searches files for lines matching a regular expression, optionally only in files matching `glob` (e.g. \"*.rs\"), showing `context` lines around each match. Ignores files listed in .gitignore.\n\
+  - git_status(): Shows the current branch, its upstream and the changed files.\n\
+  - git_diff(staged: bool = false, against: str = none, path: str = none): Shows unstaged changes, staged changes (staged=true), or all changes since a revision such as \"HEAD~1\" or \"main\" (against=...), optionally limited to one path.\n\
+  - git_log(max_count: int = 10, path: str = none): Lists recent commits, optionally only those touching a path.\n\
+  - git_blame(path: str, start_line: int = none, end_line: int = none): Shows the commit and author that last changed each line of a file.\n\
+  - git_commit(message: str, paths: list = none, all: bool = false): Stages the given paths (or every change to tracked files with all=true) and commits them. Fails while a merge or rebase is in progress. The user always reviews the commit first.\n\
+  - run_command(command: str, timeout: int = 120): Runs a shell command in the working directory and returns its exit code and output (stdout and stderr, truncated if long). Use it to build, test and inspect the project, e.g. \"cargo check\". The command may run in a sandbox without network access; the timeout is in seconds (at most 600).\n\
+  - glob(pattern: str): Lists files whose path matches a glob pattern such as \"src/**/*.rs\". Ignores files listed in .gitignore.\n\
+  - semantic_search(query: str, max_results: int = 5): Finds the code most related in meaning to a description such as \"where are sessions saved\", using an embeddings index of the working directory. Use it when you do not know the names to grep for. Code related to a message may also be added to it under [RELEVANT CODE: ...].\n\
+  - edit_file(path: str, edits: list): Edits the specified file at the given path by applying a list of edits, where each edit specifies the start and end lines to replace and the new text to insert.\n\
+  - write_file(path: str, content: str): Creates a new file with the given content, or overwrites an existing one. Use this to create files.\n\
+  - create_directory(path: str): Creates a directory and any missing parent directories.\n\
+  - move_path(from: str, to: str, overwrite: bool = false): Moves or renames a file or directory. Fails if `to` exists unless overwrite is true.\n\
+  - delete_path(path: str, recursive: bool = false): Deletes a file, or a directory (non-empty directories need recursive=true).\n\
+  - apply_patch(patch: str): Applies a unified diff (with ---/+++ file headers and @@ hunks) to one or more files. Hunks are matched by their context lines, so include at least 3 unchanged lines around each change. Use /dev/null as the old path to create a file.\n\
+  - replace_in_file(path: str, old: str, new: str, occurrence: int = none): Replaces the exact text `old` with `new`. `old` must match the file exactly (including indentation) and exactly once, otherwise the call fails; pass the 1-based `occurrence` to pick one of several matches. Prefer this over edit_file for changing existing files.\n\
+  - remember(text: str, scope: str = \"project\"): Saves a short fact for later sessions, such as how to run the tests or a preference the user stated. Use scope=\"global\" for facts about the user that hold in every project. Saved memories that may be relevant to a message are added to it under [MEMORIES: ...].\n\
 Tool invocation format:\n\
   [tool_call: TOOL_NAME(ARGUMENTS)]\n\
 Guidelines for tool usage:\n\
@@ -77,12 +249,17 @@ Guidelines for tool usage:\n\
 - Only call one tool per [tool_call: ...] block.\n\
 - If a user request requires multiple steps, respond with each tool call in sequence, one per line.\n\
 - Do not attempt to perform actions outside the provided tools.\n\
+- Tools that write, move or delete can only touch paths inside the working directory.\n\
 - If you need clarification or additional information from the user, ask a clear and concise question before proceeding.\n\
 - When returning information to the user, summarize results clearly and concisely.\n\
 Example tool call:\n\
-  [tool_call: read_file(\"/home/user/notes.txt\")]\n\
+  [tool_call: read_file(\"notes.txt\")]\n\
+Example search call:\n\
+  [tool_call: grep(pattern=\"fn main\", glob=\"*.rs\", context=2)]\n\
 Example edit call:\n\
   [tool_call: edit_file(path=\"file.txt\", edits=[{{\"start_line\":10,\"end_line\":12,\"new_text\":\"replacement text\"}}])]\n\
+Example replace call:\n\
+  [tool_call: replace_in_file(path=\"src/lib.rs\", old=\"fn old_name(\", new=\"fn new_name(\")]\n\
 Also remember when calling tools you can call as much as you want but after tool calls you will stop all responses and wait for a confirmation from the user to run said tool.\n\
 Always strive for accuracy and clarity in both tool invocation and user communication.\n\
 and remember that if asked to use a directory or file you should use the working directory as the default path.\n\
@@ -102,9 +279,6 @@ async fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Resul
                 // Only process KeyEventKind::Press to avoid double-typing
                 if key.kind == KeyEventKind::Press {
                     match key.code {
-                        KeyCode::Char('q') => {
-                            return Ok(());
-                        }
                         KeyCode::Char('c') if key.modifiers.contains(event::KeyModifiers::CONTROL) => {
                             return Ok(());
                         }
@@ -116,9 +290,11 @@ async fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Resul
             }
         }
 
-        // Process streaming if active
-        if app.is_loading {
-            app.process_streaming().await?;
+        if app.quit {
+            return Ok(());
         }
+
+        // Stream the reply or the output of a running command
+        app.poll().await?;
     }
 }
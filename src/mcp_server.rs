@@ -0,0 +1,357 @@
+//! `quill mcp-serve`: offers Quill's file, search and git tools to other MCP
+//! clients over stdio.
+//!
+//! Calls go through the same code as in the TUI, so reads and writes stay
+//! inside the working directory and the permission rules in the config still
+//! apply. Nobody is here to answer an `ask`, so `--approve` decides those
+//! calls as in `quill run --json`: by default read-only ones run and the rest
+//! are refused. Clients may still ask their user before calling a tool.
+
+use crate::cli::Approve;
+use quill_core::audit::{AuditLog, Decision};
+use quill_core::config::{Action, Config};
+use quill_core::tools::{self, Invocation, Risk, ToolCall};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// Protocol versions this server can speak, newest last.
+const PROTOCOL_VERSIONS: &[&str] = &["2024-11-05", "2025-03-26", "2025-06-18"];
+
+/// Serves MCP requests on stdin/stdout until stdin closes.
+pub async fn serve(working_directory: &Path, data_dir: &Path, config: &Config, approve: Approve) -> Result<()> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    let audit = AuditLog::new(data_dir);
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => handle(&request, working_directory, config, approve, &audit).await,
+            Err(e) => Some(error(Value::Null, -32700, &format!("parse error: {}", e))),
+        };
+        if let Some(response) = response {
+            stdout.write_all(format!("{}\n", response).as_bytes()).await?;
+            stdout.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+async fn handle(request: &Value, working_directory: &Path, config: &Config, approve: Approve, audit: &AuditLog) -> Option<Value> {
+    // Notifications have no id and get no response.
+    let id = request.get("id")?.clone();
+    let params = request.get("params").cloned().unwrap_or_default();
+    let result = match request.get("method").and_then(Value::as_str).unwrap_or_default() {
+        "initialize" => {
+            let requested = params.get("protocolVersion").and_then(Value::as_str).unwrap_or_default();
+            let version = PROTOCOL_VERSIONS
+                .iter()
+                .find(|v| **v == requested)
+                .unwrap_or(&PROTOCOL_VERSIONS[PROTOCOL_VERSIONS.len() - 1]);
+            json!({
+                "protocolVersion": version,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "quill", "version": env!("CARGO_PKG_VERSION") },
+            })
+        }
+        "ping" => json!({}),
+        "tools/list" => json!({ "tools": tool_definitions() }),
+        "tools/call" => {
+            let name = params.get("name").and_then(Value::as_str).unwrap_or_default();
+            let arguments = params.get("arguments").and_then(Value::as_object).cloned().unwrap_or_default();
+            match call_tool(name, &arguments, working_directory, config, approve, audit).await {
+                Ok(text) => json!({ "content": [{ "type": "text", "text": text }], "isError": text.starts_with("[TOOL ERROR") }),
+                Err(e) => return Some(error(id, -32602, &e)),
+            }
+        }
+        method => return Some(error(id, -32601, &format!("method not found: {}", method))),
+    };
+    Some(json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+}
+
+fn error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+async fn call_tool(
+    name: &str,
+    arguments: &Map<String, Value>,
+    working_directory: &Path,
+    config: &Config,
+    approve: Approve,
+    audit: &AuditLog,
+) -> Result<String, String> {
+    if !tool_definitions().iter().any(|t| t["name"] == name) {
+        return Err(format!("unknown tool: {}", name));
+    }
+    let tool_call = ToolCall::from_invocation(&Invocation::from_json(name, arguments))?;
+    let decision = config.permissions.decide(&tool_call, working_directory, &HashSet::new());
+    let risk = tool_call.risk(working_directory);
+    let refused = match decision.action {
+        Action::Deny => Some(format!("this call is blocked by the permission settings ({})", decision.reason)),
+        Action::Allow if risk != Risk::Destructive => None,
+        // Destructive calls need approval even when a rule allows the tool,
+        // as they do in the chat interface.
+        Action::Allow | Action::Ask => {
+            let allowed = match approve {
+                Approve::Never => false,
+                Approve::Reads => risk == Risk::Read,
+                Approve::All => true,
+            };
+            let reason = match decision.action {
+                Action::Allow => "destructive".to_string(),
+                _ => decision.reason,
+            };
+            let policy = format!("mcp-serve with --approve {:?}", approve).to_lowercase();
+            (!allowed).then(|| format!("this call needs approval ({}), which {} does not give", reason, policy))
+        }
+    };
+    if let Some(reason) = refused {
+        // The log is best effort here; there is nowhere to show a failure
+        let _ = audit.write(&audit.entry(&tool_call, Decision::Blocked, Some(reason.clone())));
+        return Ok(format!("[TOOL ERROR: {}]\nError: {}", name, reason));
+    }
+    let mut entry = audit.entry(&tool_call, Decision::Client, None);
+    let output = tools::execute(tool_call, working_directory, None).await.map_err(|e| e.to_string())?;
+    entry.finish(&output.text, &output.changed, working_directory);
+    let _ = audit.write(&entry);
+    Ok(output.text)
+}
+
+/// A tool definition. `risk` fills in the behaviour hints clients use to
+/// decide whether to ask before calling.
+fn tool(name: &str, description: &str, risk: Risk, properties: Value, required: &[&str]) -> Value {
+    json!({
+        "name": name,
+        "description": description,
+        "inputSchema": { "type": "object", "properties": properties, "required": required },
+        "annotations": {
+            "readOnlyHint": risk == Risk::Read,
+            "destructiveHint": risk == Risk::Destructive,
+            "openWorldHint": false,
+        },
+    })
+}
+
+/// The tools offered to clients. Running commands and committing are left out.
+fn tool_definitions() -> Vec<Value> {
+    let path = json!({ "type": "string", "description": "Path relative to the working directory" });
+    let line = json!({ "type": "integer", "minimum": 1 });
+    vec![
+        tool(
+            "read_file",
+            "Reads a text file and returns its lines prefixed with their 1-based line number and a tab. Long output is truncated; use start_line/end_line to read part of a file.",
+            Risk::Read,
+            json!({ "path": path, "start_line": line, "end_line": line }),
+            &["path"],
+        ),
+        tool(
+            "read_directory",
+            "Lists the files and directories in a directory.",
+            Risk::Read,
+            json!({ "path": path }),
+            &["path"],
+        ),
+        tool(
+            "grep",
+            "Searches files for lines matching a regular expression, skipping files ignored by .gitignore.",
+            Risk::Read,
+            json!({
+                "pattern": { "type": "string", "description": "Regular expression" },
+                "path": path,
+                "glob": { "type": "string", "description": "Only search files matching this glob, e.g. *.rs" },
+                "context": { "type": "integer", "minimum": 0, "maximum": 10, "description": "Lines to show around each match" },
+            }),
+            &["pattern"],
+        ),
+        tool(
+            "glob",
+            "Lists files whose path matches a glob pattern such as src/**/*.rs, skipping files ignored by .gitignore.",
+            Risk::Read,
+            json!({ "pattern": { "type": "string" } }),
+            &["pattern"],
+        ),
+        tool(
+            "replace_in_file",
+            "Replaces the exact text `old` with `new`. `old` must match exactly once unless `occurrence` (1-based) picks one of several matches.",
+            Risk::Modify,
+            json!({
+                "path": path,
+                "old": { "type": "string" },
+                "new": { "type": "string" },
+                "occurrence": line,
+            }),
+            &["path", "old", "new"],
+        ),
+        tool(
+            "edit_file",
+            "Replaces line ranges of a file. Each edit replaces lines start_line..=end_line (1-based) with new_text.",
+            Risk::Modify,
+            json!({
+                "path": path,
+                "edits": {
+                    "type": "array",
+                    "items": {
+                        "type": "object",
+                        "properties": { "start_line": line, "end_line": line, "new_text": { "type": "string" } },
+                        "required": ["start_line", "end_line", "new_text"],
+                    },
+                },
+            }),
+            &["path", "edits"],
+        ),
+        tool(
+            "apply_patch",
+            "Applies a unified diff with ---/+++ file headers and @@ hunks to one or more files. Hunks are located by their context lines.",
+            Risk::Modify,
+            json!({ "patch": { "type": "string" } }),
+            &["patch"],
+        ),
+        tool(
+            "write_file",
+            "Creates a file with the given content, or overwrites an existing one.",
+            Risk::Destructive,
+            json!({ "path": path, "content": { "type": "string" } }),
+            &["path", "content"],
+        ),
+        tool(
+            "create_directory",
+            "Creates a directory and any missing parents.",
+            Risk::Create,
+            json!({ "path": path }),
+            &["path"],
+        ),
+        tool(
+            "move_path",
+            "Moves or renames a file or directory. Fails if `to` exists unless overwrite is true.",
+            Risk::Destructive,
+            json!({ "from": path, "to": path, "overwrite": { "type": "boolean" } }),
+            &["from", "to"],
+        ),
+        tool(
+            "delete_path",
+            "Deletes a file, or a directory (non-empty directories need recursive=true).",
+            Risk::Destructive,
+            json!({ "path": path, "recursive": { "type": "boolean" } }),
+            &["path"],
+        ),
+        tool("git_status", "Shows the current branch, its upstream and the changed files.", Risk::Read, json!({}), &[]),
+        tool(
+            "git_diff",
+            "Shows unstaged changes, staged changes (staged=true), or all changes since a revision (against=...), optionally for one path.",
+            Risk::Read,
+            json!({ "staged": { "type": "boolean" }, "against": { "type": "string" }, "path": path }),
+            &[],
+        ),
+        tool(
+            "git_log",
+            "Lists recent commits, optionally only those touching a path.",
+            Risk::Read,
+            json!({ "max_count": line, "path": path }),
+            &[],
+        ),
+        tool(
+            "git_blame",
+            "Shows the commit and author that last changed each line of a file.",
+            Risk::Read,
+            json!({ "path": path, "start_line": line, "end_line": line }),
+            &["path"],
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("quill-test-{}-{}", name, uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Config with `permissions` given as JSON, as in the config file.
+    /// Path rules are not compiled, so only `default` and `tools` apply.
+    fn config(permissions: Value) -> Config {
+        serde_json::from_value(json!({ "permissions": permissions })).unwrap()
+    }
+
+    async fn call(dir: &Path, config: &Config, approve: Approve, name: &str, arguments: Value) -> Value {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": name, "arguments": arguments },
+        });
+        let audit = AuditLog::new(&dir.join(".data"));
+        handle(&request, dir, config, approve, &audit).await.unwrap()["result"].clone()
+    }
+
+    fn text(result: &Value) -> &str {
+        result["content"][0]["text"].as_str().unwrap()
+    }
+
+    #[tokio::test]
+    async fn lists_the_tools_with_their_hints() {
+        let dir = temp_dir("mcp-list");
+        let audit = AuditLog::new(&dir);
+        let request = json!({ "jsonrpc": "2.0", "id": 7, "method": "tools/list" });
+        let response = handle(&request, &dir, &Config::default(), Approve::Reads, &audit).await.unwrap();
+
+        assert_eq!(response["id"], 7);
+        let tools = response["result"]["tools"].as_array().unwrap();
+        let read_file = tools.iter().find(|t| t["name"] == "read_file").unwrap();
+        assert_eq!(read_file["annotations"]["readOnlyHint"], true);
+        let delete_path = tools.iter().find(|t| t["name"] == "delete_path").unwrap();
+        assert_eq!(delete_path["annotations"]["destructiveHint"], true);
+        assert!(!tools.iter().any(|t| t["name"] == "run_command" || t["name"] == "git_commit"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn runs_calls_the_approve_policy_allows() {
+        let dir = temp_dir("mcp-allowed");
+        std::fs::write(dir.join("notes.txt"), "hello\n").unwrap();
+
+        let result = call(&dir, &Config::default(), Approve::Reads, "read_file", json!({ "path": "notes.txt" })).await;
+
+        assert_eq!(result["isError"], false);
+        assert!(text(&result).contains("hello"));
+        assert!(dir.join(".data").join("audit.jsonl").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuses_calls_the_config_denies() {
+        let dir = temp_dir("mcp-denied");
+        std::fs::write(dir.join("notes.txt"), "hello\n").unwrap();
+        let config = config(json!({ "tools": { "read_file": "deny" } }));
+
+        let result = call(&dir, &config, Approve::All, "read_file", json!({ "path": "notes.txt" })).await;
+
+        assert_eq!(result["isError"], true);
+        assert!(text(&result).contains("blocked by the permission settings"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn destructive_calls_need_approve_all_even_when_allowed() {
+        let dir = temp_dir("mcp-destructive");
+        std::fs::write(dir.join("notes.txt"), "keep me").unwrap();
+        let config = config(json!({ "default": "allow" }));
+
+        let result = call(&dir, &config, Approve::Reads, "delete_path", json!({ "path": "notes.txt" })).await;
+        assert_eq!(result["isError"], true);
+        assert!(text(&result).contains("needs approval (destructive)"));
+        assert!(dir.join("notes.txt").exists());
+
+        let result = call(&dir, &config, Approve::All, "delete_path", json!({ "path": "notes.txt" })).await;
+        assert_eq!(result["isError"], false);
+        assert!(!dir.join("notes.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
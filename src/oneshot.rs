@@ -0,0 +1,176 @@
+//! `quill -p PROMPT`: sends one message without the chat interface and
+//! streams the reply to stdout, for scripts, pipes and git hooks.
+//!
+//! Anything piped to stdin is appended to the prompt, and files mentioned in
+//! it as `@path` are attached. Tools are not offered, since nobody is there to
+//! approve them.
+
+use crate::cli::Settings;
+use quill_core::config::Config;
+use quill_core::conversation::ChatTurn;
+use quill_core::logging;
+use quill_core::mentions;
+use quill_core::ollama::ChatChunk;
+use anyhow::{bail, Context, Result};
+use futures::StreamExt;
+use serde_json::json;
+use std::collections::HashSet;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+
+pub async fn run(settings: &Settings, system_prompt: String, prompt: &str) -> Result<()> {
+    let config = settings.load_config()?;
+    let working_directory = std::env::current_dir().context("cannot read the working directory")?;
+    let mut input = String::new();
+    let mut stdin = io::stdin();
+    if !stdin.is_terminal() {
+        stdin.read_to_string(&mut input).context("cannot read stdin")?;
+    }
+    let message = compose(prompt, &input, &working_directory, &config)?;
+    send(settings, &config, system_prompt, message, &mut io::stdout()).await
+}
+
+/// The message to send: the prompt with the files it mentions, then the
+/// piped `input`.
+fn compose(prompt: &str, input: &str, working_directory: &Path, config: &Config) -> Result<String> {
+    // Only the prompt is searched for mentions; piped text is taken as is
+    let (attachments, notes) = mentions::attach(prompt, working_directory, config, &HashSet::new());
+    let mut message = format!("{}{}", prompt, attachments);
+    for note in notes {
+        eprintln!("{}", note);
+    }
+    if !input.trim().is_empty() {
+        if !message.is_empty() {
+            message.push_str("\n\n");
+        }
+        message.push_str(input);
+    }
+    if message.trim().is_empty() {
+        bail!("nothing to send: give a prompt with -p or pipe text to stdin");
+    }
+    Ok(message)
+}
+
+/// Sends `message` and writes the reply to `out` as it streams, ending it
+/// with a newline. Fails if the provider reports an error or the reply is
+/// cut short.
+async fn send(settings: &Settings, config: &Config, system_prompt: String, message: String, out: &mut impl Write) -> Result<()> {
+    let client = settings.client();
+    let model = match &settings.model {
+        Some(model) => model.clone(),
+        None => match client.list_models().await?.into_iter().next() {
+            Some(model) => model.name,
+            None => bail!("the provider has no models; pull one or pass --model"),
+        },
+    };
+    let turns = vec![
+        ChatTurn { role: "system".to_string(), content: system_prompt },
+        ChatTurn { role: "user".to_string(), content: message },
+    ];
+    let mut stream = client.chat_stream(model, turns, config.options.clone()).await?;
+
+    let mut buffer = String::new();
+    let mut ends_with_newline = true;
+    while let Some(bytes) = stream.next().await {
+        buffer.push_str(&bytes?);
+        // Chunks do not always end on a line boundary
+        while let Some(end) = buffer.find('\n') {
+            let line: String = buffer.drain(..=end).collect();
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let chunk = ChatChunk::parse(line).with_context(|| format!("unexpected response line: {}", line))?;
+            if let Some(error) = chunk.error {
+                bail!("{}", error);
+            }
+            if !chunk.content.is_empty() {
+                out.write_all(chunk.content.as_bytes())?;
+                out.flush()?;
+                ends_with_newline = chunk.content.ends_with('\n');
+            }
+            if chunk.done {
+                if !ends_with_newline {
+                    writeln!(out)?;
+                }
+                logging::info(
+                    "oneshot",
+                    "reply finished",
+                    json!({ "prompt_tokens": chunk.prompt_tokens, "reply_tokens": chunk.reply_tokens }),
+                );
+                return Ok(());
+            }
+        }
+    }
+    bail!("the response ended before the reply was complete")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Provider;
+    use crate::testing::{fake_ollama, reply, TempDir};
+
+    fn settings(base_url: String) -> Settings {
+        Settings {
+            model: None,
+            provider: Provider::Ollama,
+            base_url,
+            cwd: None,
+            system_prompt_file: None,
+            resume: None,
+            no_tools: true,
+            no_instructions: true,
+            config: None,
+            data_dir: None,
+        }
+    }
+
+    /// What `-p` prints for `chat`'s response, and whether it succeeds.
+    async fn output(chat: fn(serde_json::Value) -> String) -> (String, Result<()>) {
+        let mut out = Vec::new();
+        let result = send(&settings(fake_ollama(chat)), &Config::default(), String::new(), "hi".to_string(), &mut out).await;
+        (String::from_utf8(out).unwrap(), result)
+    }
+
+    #[tokio::test]
+    async fn prints_the_reply_and_ends_the_line() {
+        let (out, result) = output(|_| reply(&["Hel", "lo"])).await;
+        assert!(result.is_ok());
+        assert_eq!(out, "Hello\n");
+
+        let (out, result) = output(|_| reply(&["Hello\n"])).await;
+        assert!(result.is_ok());
+        assert_eq!(out, "Hello\n");
+    }
+
+    #[tokio::test]
+    async fn provider_errors_fail_the_run() {
+        let (out, result) = output(|_| format!("{}\n", json!({ "error": "model 'fake:1b' not found" }))).await;
+        assert_eq!(result.unwrap_err().to_string(), "model 'fake:1b' not found");
+        assert_eq!(out, "");
+    }
+
+    #[tokio::test]
+    async fn replies_cut_short_fail_the_run() {
+        let (out, result) = output(|_| format!("{}\n", json!({ "message": { "role": "assistant", "content": "Hel" } }))).await;
+        assert_eq!(result.unwrap_err().to_string(), "the response ended before the reply was complete");
+        assert_eq!(out, "Hel");
+    }
+
+    #[test]
+    fn compose_appends_piped_input_and_mentioned_files() {
+        let dir = TempDir::new("oneshot");
+        std::fs::write(dir.path().join("notes.txt"), "remember the milk").unwrap();
+        let config = Config::default();
+
+        let message = compose("summarize @notes.txt", "piped text\n", dir.path(), &config).unwrap();
+        assert!(message.starts_with("summarize @notes.txt"));
+        assert!(message.contains("remember the milk"));
+        assert!(message.ends_with("\n\npiped text\n"));
+
+        assert_eq!(compose("", "piped text", dir.path(), &config).unwrap(), "piped text");
+        let empty = compose(" ", " \n", dir.path(), &config).unwrap_err();
+        assert!(empty.to_string().starts_with("nothing to send"));
+    }
+}
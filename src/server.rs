@@ -0,0 +1,592 @@
+//! `quill serve`: an HTTP API on localhost so editors and dashboards can hold
+//! conversations with Quill, follow them as server-sent events and decide
+//! tool calls.
+//!
+//! Every conversation runs in its own task, which owns the `Conversation` and
+//! takes requests from the handlers over a channel. Its events are broadcast
+//! to any number of `GET /sessions/ID/events` streams. Clients authenticate
+//! with the token printed at startup, as `Authorization: Bearer TOKEN` or
+//! `?token=TOKEN` (for `EventSource`, which cannot set headers).
+
+use crate::cli::Settings;
+use axum::extract::{Path, Query, State};
+use axum::http::{self, header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::sse::{self, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::Stream;
+use quill_core::conversation::ChatTurn;
+use quill_core::logging;
+use quill_core::tools::Risk;
+use quill_core::{Conversation, Event};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+/// Events a slow `/events` client can fall behind by before it misses some.
+const EVENT_BUFFER: usize = 1024;
+
+struct Server {
+    settings: Settings,
+    token: String,
+    sessions: Mutex<HashMap<String, Handle>>,
+}
+
+/// How handlers reach a conversation's task.
+#[derive(Clone)]
+struct Handle {
+    requests: mpsc::UnboundedSender<Request>,
+    events: broadcast::Sender<Event>,
+}
+
+enum Request {
+    Message(String, Reply),
+    Approve { always: bool, reply: Reply },
+    Deny { reason: Option<String>, reply: Reply },
+    Stop(Reply),
+    Status(oneshot::Sender<Status>),
+}
+
+type Reply = oneshot::Sender<Result<(), ApiError>>;
+
+#[derive(Serialize)]
+struct Status {
+    id: String,
+    model: String,
+    working_directory: String,
+    busy: bool,
+    pending_tool_call: Option<PendingCall>,
+    /// The conversation so far, without the system prompt.
+    turns: Vec<ChatTurn>,
+}
+
+#[derive(Serialize)]
+struct PendingCall {
+    tool: String,
+    target: String,
+    arguments: Value,
+    risk: Risk,
+}
+
+/// An error response, as `{"error": "..."}`.
+struct ApiError(StatusCode, String);
+
+impl ApiError {
+    fn conflict(message: &str) -> Self {
+        ApiError(StatusCode::CONFLICT, message.to_string())
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+pub async fn serve(settings: Settings, port: u16, token: Option<String>) -> anyhow::Result<()> {
+    let token = token.unwrap_or_else(|| uuid::Uuid::new_v4().simple().to_string());
+    let state = Arc::new(Server {
+        settings,
+        token,
+        sessions: Mutex::new(HashMap::new()),
+    });
+    let app = router(state.clone());
+
+    // Only ever on the loopback interface: the API runs tools on this machine
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port)))?;
+    listener.set_nonblocking(true)?;
+    let address = listener.local_addr()?;
+    eprintln!("Quill is listening on http://{}", address);
+    eprintln!("Token: {}", state.token);
+    logging::info("server", "listening", json!({ "address": address.to_string() }));
+    axum::Server::from_tcp(listener)?.serve(app.into_make_service()).await?;
+    Ok(())
+}
+
+fn router(state: Arc<Server>) -> Router {
+    Router::new()
+        .route("/models", get(list_models))
+        .route("/sessions", get(list_sessions).post(create_session))
+        .route("/sessions/:id", get(session_status).delete(close_session))
+        .route("/sessions/:id/messages", post(send_message))
+        .route("/sessions/:id/events", get(session_events))
+        .route("/sessions/:id/approve", post(approve))
+        .route("/sessions/:id/deny", post(deny))
+        .route("/sessions/:id/stop", post(stop))
+        .route_layer(middleware::from_fn_with_state(state.clone(), authorize))
+        .with_state(state)
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+async fn authorize<B>(
+    State(server): State<Arc<Server>>,
+    Query(query): Query<TokenQuery>,
+    request: http::Request<B>,
+    next: Next<B>,
+) -> Response {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if !bearer.or(query.token.as_deref()).is_some_and(|given| same_token(given, &server.token)) {
+        return ApiError(StatusCode::UNAUTHORIZED, "missing or wrong token".to_string()).into_response();
+    }
+    next.run(request).await
+}
+
+/// Compares every byte whatever the first difference, so response times do
+/// not tell how much of a guessed token was right.
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+async fn list_models(State(server): State<Arc<Server>>) -> Result<Json<Value>, ApiError> {
+    let models = server.settings.client().list_models().await?;
+    Ok(Json(json!({ "models": models })))
+}
+
+#[derive(Deserialize, Default)]
+struct NewSession {
+    model: Option<String>,
+    /// A saved session to continue; empty for the latest one in the working directory.
+    resume: Option<String>,
+}
+
+async fn create_session(
+    State(server): State<Arc<Server>>,
+    body: Option<Json<NewSession>>,
+) -> Result<(StatusCode, Json<Status>), ApiError> {
+    let request = body.map(|Json(body)| body).unwrap_or_default();
+    let mut settings = server.settings.clone();
+    settings.model = request.model.or(settings.model);
+    settings.resume = request.resume;
+    let conversation = crate::new_conversation(&settings)
+        .await
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, format!("{:#}", e)))?;
+    let id = conversation.session.id.clone();
+    let (requests, receiver) = mpsc::unbounded_channel();
+    let (events, _) = broadcast::channel(EVENT_BUFFER);
+    let handle = Handle { requests, events: events.clone() };
+    server.sessions.lock().unwrap().insert(id.clone(), handle.clone());
+    tokio::spawn(drive(conversation, receiver, events));
+    logging::info("server", "session created", json!({ "id": id }));
+    let status = status_of(&handle).await?;
+    Ok((StatusCode::CREATED, Json(status)))
+}
+
+async fn list_sessions(State(server): State<Arc<Server>>) -> Result<Json<Value>, ApiError> {
+    let handles: Vec<Handle> = server.sessions.lock().unwrap().values().cloned().collect();
+    let mut sessions = Vec::new();
+    for handle in handles {
+        sessions.push(status_of(&handle).await?);
+    }
+    sessions.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(Json(json!({ "sessions": sessions })))
+}
+
+async fn session_status(State(server): State<Arc<Server>>, Path(id): Path<String>) -> Result<Json<Status>, ApiError> {
+    let handle = find(&server, &id)?;
+    Ok(Json(status_of(&handle).await?))
+}
+
+/// Ends a conversation. It stays saved and can be resumed.
+async fn close_session(State(server): State<Arc<Server>>, Path(id): Path<String>) -> Result<StatusCode, ApiError> {
+    match server.sessions.lock().unwrap().remove(&id) {
+        Some(_) => Ok(StatusCode::NO_CONTENT),
+        None => Err(not_found(&id)),
+    }
+}
+
+#[derive(Deserialize)]
+struct NewMessage {
+    text: String,
+}
+
+async fn send_message(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+    Json(message): Json<NewMessage>,
+) -> Result<StatusCode, ApiError> {
+    if message.text.trim().is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "the message is empty".to_string()));
+    }
+    let handle = find(&server, &id)?;
+    ask(&handle, |reply| Request::Message(message.text, reply)).await
+}
+
+#[derive(Deserialize, Default)]
+struct Approval {
+    /// Run later calls to the same tool without asking.
+    #[serde(default)]
+    always: bool,
+}
+
+async fn approve(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+    body: Option<Json<Approval>>,
+) -> Result<StatusCode, ApiError> {
+    let always = body.is_some_and(|Json(body)| body.always);
+    let handle = find(&server, &id)?;
+    ask(&handle, |reply| Request::Approve { always, reply }).await
+}
+
+#[derive(Deserialize, Default)]
+struct Denial {
+    /// Tell the model why and let it carry on; without one the conversation
+    /// waits for the next message, as when a call is denied in the TUI.
+    reason: Option<String>,
+}
+
+async fn deny(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+    body: Option<Json<Denial>>,
+) -> Result<StatusCode, ApiError> {
+    let reason = body.and_then(|Json(body)| body.reason);
+    let handle = find(&server, &id)?;
+    ask(&handle, |reply| Request::Deny { reason, reply }).await
+}
+
+async fn stop(State(server): State<Arc<Server>>, Path(id): Path<String>) -> Result<StatusCode, ApiError> {
+    let handle = find(&server, &id)?;
+    ask(&handle, Request::Stop).await
+}
+
+async fn session_events(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<sse::Event, Infallible>>>, ApiError> {
+    let receiver = find(&server, &id)?.events.subscribe();
+    let stream = futures::stream::unfold(receiver, |mut receiver| async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let kind = serde_json::to_value(&event).ok()?.get("type")?.as_str()?.to_string();
+                    let data = sse::Event::default().event(kind).json_data(&event).ok()?;
+                    return Some((Ok(data), receiver));
+                }
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    logging::warn("server", "event stream fell behind", json!({ "missed": missed }));
+                }
+                // The session was closed
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+fn find(server: &Server, id: &str) -> Result<Handle, ApiError> {
+    server.sessions.lock().unwrap().get(id).cloned().ok_or_else(|| not_found(id))
+}
+
+fn not_found(id: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("no session {}", id))
+}
+
+/// Sends a request to a conversation's task and waits for it to be handled.
+async fn ask(handle: &Handle, request: impl FnOnce(Reply) -> Request) -> Result<StatusCode, ApiError> {
+    let (reply, response) = oneshot::channel();
+    handle.requests.send(request(reply)).map_err(|_| gone())?;
+    response.await.map_err(|_| gone())??;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn status_of(handle: &Handle) -> Result<Status, ApiError> {
+    let (reply, response) = oneshot::channel();
+    handle.requests.send(Request::Status(reply)).map_err(|_| gone())?;
+    response.await.map_err(|_| gone())
+}
+
+fn gone() -> ApiError {
+    ApiError(StatusCode::GONE, "the session has ended".to_string())
+}
+
+/// Runs one conversation until its session is closed: handles requests,
+/// streams replies and commands, and broadcasts what happens.
+async fn drive(mut conversation: Conversation, mut requests: mpsc::UnboundedReceiver<Request>, events: broadcast::Sender<Event>) {
+    let mut updates = conversation.subscribe();
+    let mut idle = true;
+    'session: loop {
+        if conversation.is_busy() {
+            idle = false;
+            loop {
+                match requests.try_recv() {
+                    Ok(request) => handle(&mut conversation, request).await,
+                    Err(mpsc::error::TryRecvError::Empty) => break,
+                    Err(mpsc::error::TryRecvError::Disconnected) => break 'session,
+                }
+            }
+            if let Err(e) = conversation.poll().await {
+                let _ = events.send(Event::Error { message: format!("{:#}", e) });
+            }
+            if conversation.running_command.is_some() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        } else {
+            match requests.recv().await {
+                Some(request) => {
+                    // Anything but a status request ends with an idle event,
+                    // even a command that finishes without starting a reply.
+                    if !matches!(request, Request::Status(_)) {
+                        idle = false;
+                    }
+                    handle(&mut conversation, request).await
+                }
+                None => break,
+            }
+        }
+        while let Ok(event) = updates.try_recv() {
+            let _ = events.send(event);
+        }
+        if !idle && !conversation.is_busy() {
+            idle = true;
+            let pending_tool_call = conversation.pending_tool_call().is_some();
+            let _ = events.send(Event::Idle { pending_tool_call });
+        }
+    }
+    // Stop whatever is still running before the conversation is dropped
+    conversation.stop();
+    logging::info("server", "session closed", json!({ "id": conversation.session.id }));
+}
+
+async fn handle(conversation: &mut Conversation, request: Request) {
+    let (result, reply) = match request {
+        Request::Status(reply) => {
+            let _ = reply.send(status(conversation));
+            return;
+        }
+        Request::Message(text, reply) => {
+            let result = if conversation.is_busy() {
+                Err(ApiError::conflict("a reply or command is still running; stop it first"))
+            } else {
+                conversation.submit(&text).await.map_err(ApiError::from)
+            };
+            (result, reply)
+        }
+        Request::Approve { always, reply } => match conversation.pending_tool_call() {
+            Some(_) => (conversation.accept_pending(always).await.map_err(ApiError::from), reply),
+            None => (Err(ApiError::conflict("no tool call is waiting for approval")), reply),
+        },
+        Request::Deny { reason, reply } => match (conversation.pending_tool_call(), reason) {
+            (None, _) => (Err(ApiError::conflict("no tool call is waiting for approval")), reply),
+            (Some(_), Some(reason)) => (conversation.block_pending(reason).await.map_err(ApiError::from), reply),
+            (Some(_), None) => {
+                conversation.deny_pending();
+                (Ok(()), reply)
+            }
+        },
+        Request::Stop(reply) => {
+            conversation.stop();
+            (Ok(()), reply)
+        }
+    };
+    let _ = reply.send(result);
+}
+
+fn status(conversation: &Conversation) -> Status {
+    Status {
+        id: conversation.session.id.clone(),
+        model: conversation.models[conversation.selected_model_index].name.clone(),
+        working_directory: conversation.working_directory.clone(),
+        busy: conversation.is_busy(),
+        pending_tool_call: conversation.pending_tool_call().map(|(tool_call, risk)| PendingCall {
+            tool: tool_call.name().to_string(),
+            target: tool_call.target(),
+            arguments: tool_call.arguments(),
+            risk,
+        }),
+        turns: conversation.chat_history[1..].to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Provider;
+    use crate::testing::{fake_ollama, reply, start};
+    use futures::StreamExt;
+
+    const TOKEN: &str = "test-token";
+
+    /// A `quill serve` whose config and data directory are in `dir`, which
+    /// is removed when the test ends.
+    struct TestServer {
+        url: String,
+        dir: std::path::PathBuf,
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn quill_server() -> TestServer {
+        let dir = std::env::temp_dir().join(format!("quill-test-server-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join("config.json");
+        std::fs::write(&config, r#"{ "index": { "enabled": false } }"#).unwrap();
+        let settings = Settings {
+            model: None,
+            provider: Provider::Ollama,
+            base_url: fake_ollama(|_| reply(&["Hello there"])),
+            cwd: None,
+            system_prompt_file: None,
+            resume: None,
+            no_tools: false,
+            no_instructions: true,
+            config: Some(config),
+            data_dir: Some(dir.join("data")),
+        };
+        let url = start(router(Arc::new(Server {
+            settings,
+            token: TOKEN.to_string(),
+            sessions: Mutex::new(HashMap::new()),
+        })));
+        TestServer { url, dir }
+    }
+
+    /// Reads an event stream up to the first `idle` event.
+    async fn read_until_idle(events: reqwest::Response) -> String {
+        let mut stream = events.bytes_stream();
+        let mut received = String::new();
+        let read = async {
+            while !received.contains("event:idle") {
+                let chunk = stream.next().await.expect("the event stream ended").unwrap();
+                received.push_str(&String::from_utf8_lossy(&chunk));
+            }
+        };
+        let timed_out = tokio::time::timeout(Duration::from_secs(10), read).await.is_err();
+        assert!(!timed_out, "no idle event in {}", received);
+        received
+    }
+
+    async fn create_session(client: &reqwest::Client, url: &str) -> String {
+        let response = client.post(format!("{}/sessions", url)).bearer_auth(TOKEN).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let status: Value = response.json().await.unwrap();
+        assert_eq!(status["model"], "fake:1b");
+        status["id"].as_str().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn requests_need_the_token() {
+        let server = quill_server();
+        let url = &server.url;
+        let client = reqwest::Client::new();
+        let sessions = format!("{}/sessions", url);
+
+        let missing = client.get(&sessions).send().await.unwrap();
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        let wrong_header = client.get(&sessions).bearer_auth("wrong").send().await.unwrap();
+        assert_eq!(wrong_header.status(), StatusCode::UNAUTHORIZED);
+        let wrong_query = client.get(format!("{}?token=wrong", sessions)).send().await.unwrap();
+        assert_eq!(wrong_query.status(), StatusCode::UNAUTHORIZED);
+        let same_length = client.get(&sessions).bearer_auth("test-tokeX").send().await.unwrap();
+        assert_eq!(same_length.status(), StatusCode::UNAUTHORIZED);
+        let prefix = client.get(&sessions).bearer_auth("test-").send().await.unwrap();
+        assert_eq!(prefix.status(), StatusCode::UNAUTHORIZED);
+        let wrong_create = client.post(&sessions).send().await.unwrap();
+        assert_eq!(wrong_create.status(), StatusCode::UNAUTHORIZED);
+
+        let header = client.get(&sessions).bearer_auth(TOKEN).send().await.unwrap();
+        assert_eq!(header.status(), StatusCode::OK);
+        let query = client.get(format!("{}?token={}", sessions, TOKEN)).send().await.unwrap();
+        assert_eq!(query.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn messages_stream_events() {
+        let server = quill_server();
+        let url = &server.url;
+        let client = reqwest::Client::new();
+        let id = create_session(&client, url).await;
+
+        let events = client.get(format!("{}/sessions/{}/events?token={}", url, id, TOKEN)).send().await.unwrap();
+        assert_eq!(events.status(), StatusCode::OK);
+        assert_eq!(events.headers()[header::CONTENT_TYPE], "text/event-stream");
+        let sent = client
+            .post(format!("{}/sessions/{}/messages", url, id))
+            .bearer_auth(TOKEN)
+            .json(&json!({ "text": "hi" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(sent.status(), StatusCode::ACCEPTED);
+
+        let received = read_until_idle(events).await;
+        assert!(received.contains("event:assistant_message"));
+        assert!(received.contains(r#""content":"Hello there""#));
+
+        let status: Value = client
+            .get(format!("{}/sessions/{}", url, id))
+            .bearer_auth(TOKEN)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(status["busy"], false);
+        assert_eq!(status["turns"][1]["content"], "Hello there");
+        assert!(server.dir.join("data").join("sessions").exists());
+    }
+
+    #[tokio::test]
+    async fn commands_that_finish_at_once_end_with_idle() {
+        let server = quill_server();
+        let url = &server.url;
+        let client = reqwest::Client::new();
+        let id = create_session(&client, url).await;
+
+        let events = client.get(format!("{}/sessions/{}/events?token={}", url, id, TOKEN)).send().await.unwrap();
+        let sent = client
+            .post(format!("{}/sessions/{}/messages", url, id))
+            .bearer_auth(TOKEN)
+            .json(&json!({ "text": "/help" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(sent.status(), StatusCode::ACCEPTED);
+
+        let received = read_until_idle(events).await;
+        assert!(received.contains(r#""pending_tool_call":false"#), "{}", received);
+    }
+
+    #[tokio::test]
+    async fn approve_and_deny_need_a_pending_call() {
+        let server = quill_server();
+        let url = &server.url;
+        let client = reqwest::Client::new();
+        let id = create_session(&client, url).await;
+
+        for action in ["approve", "deny"] {
+            let response = client.post(format!("{}/sessions/{}/{}", url, id, action)).bearer_auth(TOKEN).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::CONFLICT);
+            let body: Value = response.json().await.unwrap();
+            assert_eq!(body["error"], "no tool call is waiting for approval");
+        }
+        let missing = client.post(format!("{}/sessions/nope/approve", url)).bearer_auth(TOKEN).send().await.unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,74 @@
+//! Helpers for the tests of the frontends that talk to a model: a fake
+//! Ollama server and temporary directories.
+
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+
+/// Serves `router` on a free port of the loopback interface.
+pub fn start(router: Router) -> String {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(axum::Server::from_tcp(listener).unwrap().serve(router.into_make_service()));
+    format!("http://{}", address)
+}
+
+/// Just enough of the Ollama API for one model, `fake:1b`. `chat` turns the
+/// body of a chat request into the lines of the response.
+pub fn fake_ollama(chat: fn(Value) -> String) -> String {
+    let models = json!({ "models": [{ "name": "fake:1b", "modified_at": "2024-01-01T00:00:00Z", "size": 1 }] });
+    start(
+        Router::new()
+            .route("/api/tags", get(move || async move { Json(models) }))
+            .route("/api/chat", post(move |Json(body): Json<Value>| async move { chat(body) })),
+    )
+}
+
+/// A complete streamed reply made of `parts`.
+pub fn reply(parts: &[&str]) -> String {
+    let mut lines = String::new();
+    for part in parts {
+        lines.push_str(&format!("{}\n", json!({ "message": { "role": "assistant", "content": part } })));
+    }
+    lines.push_str(&format!("{}\n", json!({ "message": { "role": "assistant", "content": "" }, "done": true })));
+    lines
+}
+
+/// A model for agent runs: when the last message holds a `[tool_call: ...]`
+/// block it makes that call, after a tool result it says "Finished." and
+/// otherwise it says hello.
+pub fn agent(body: Value) -> String {
+    let last = body["messages"].as_array().and_then(|messages| messages.last()).cloned().unwrap_or_default();
+    let content = last["content"].as_str().unwrap_or_default();
+    if content.starts_with("[TOOL") {
+        reply(&["Finished."])
+    } else if let Some(start) = content.find("[tool_call:") {
+        reply(&[content[start..].lines().next().unwrap_or_default()])
+    } else {
+        reply(&["Hello there"])
+    }
+}
+
+/// A directory under the system temp directory, removed when dropped.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("quill-test-{}-{}", name, uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
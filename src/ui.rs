@@ -1,13 +1,19 @@
-use crate::app::{App, Message, ToolCall};
+use crate::app::{App, MemoryScreen};
+use quill_core::conversation::{Message, StepStatus};
+use quill_core::logging::{self, Level};
+use quill_core::memory::Scope;
+use quill_core::plan::{Phase, Plan, StepState};
+use quill_core::shell::RunningCommand;
+use quill_core::tools::{Risk, ToolCall};
+use quill_core::utils::format_duration;
 use tui::{
     backend::Backend,
     layout::{Alignment, Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    text::{Span, Spans},
-    widgets::{Block, Borders, List, ListItem, Paragraph, Wrap, Clear},
+    text::{Span, Spans, Text},
+    widgets::{Block, Borders, Paragraph, Wrap, Clear},
     Frame,
 };
-use tui::text::Text;
 
 const TITLE_ART: [&str; 6] = [
     " ██████╗ ██╗   ██╗██╗██╗     ██╗     ",
@@ -18,15 +24,6 @@ const TITLE_ART: [&str; 6] = [
     " ╚══▀▀═╝  ╚═════╝ ╚═╝╚══════╝╚══════╝",
 ];
 
-fn required_height(text: &str, width: u16) -> u16 {
-    let mut lines = 0;
-    for line in text.lines() {
-        let len = line.chars().count() as u16;
-        lines += (len / width).max(1);
-    }
-    lines
-}
-
 pub fn draw<B: Backend>(f: &mut Frame<B>, app: &App) {
     let size = f.size();
 
@@ -43,8 +40,163 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &App) {
 
     draw_model_selector_bar(f, main_chunks[0], app);
     draw_title_art(f, main_chunks[1]);
-    draw_chat_area(f, main_chunks[2], app);
+    if app.show_log {
+        let chat_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(5), Constraint::Percentage(40)])
+            .split(main_chunks[2]);
+        draw_chat_area(f, chat_chunks[0], app);
+        draw_log_panel(f, chat_chunks[1]);
+    } else {
+        draw_chat_area(f, main_chunks[2], app);
+    }
     draw_input_area(f, main_chunks[3], app);
+    draw_completions(f, main_chunks[3], app);
+    if let Some(screen) = &app.memory_screen {
+        draw_memory_screen(f, main_chunks[2], app, screen);
+    }
+}
+
+/// The memory manager, over the chat area.
+fn draw_memory_screen<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App, screen: &MemoryScreen) {
+    let memories = &app.conversation.memory.memories;
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(3)])
+        .split(area);
+
+    let visible = chunks[0].height.saturating_sub(2) as usize;
+    let first = (screen.selected + 1).saturating_sub(visible);
+    let mut lines: Vec<Spans> = memories
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(visible)
+        .map(|(i, memory)| {
+            let style = if i == screen.selected {
+                Style::default().fg(Color::Black).bg(Color::Cyan)
+            } else {
+                Style::default().fg(Color::White)
+            };
+            let scope_color = match memory.scope {
+                Scope::Project => Color::Green,
+                Scope::Global => Color::Magenta,
+            };
+            Spans::from(vec![
+                Span::styled(format!(" {} ", memory.id), Style::default().fg(Color::DarkGray)),
+                Span::styled(format!("{:<8}", memory.scope.label()), Style::default().fg(scope_color)),
+                Span::styled(format!("{} ", memory.updated.with_timezone(&chrono::Local).format("%Y-%m-%d")), Style::default().fg(Color::DarkGray)),
+                Span::styled(format!(" {} ", memory.text), style),
+            ])
+        })
+        .collect();
+    if memories.is_empty() {
+        lines.push(Spans::from(Span::styled(
+            " No memories yet. Press n to add one, or let the model save them with the remember tool.",
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+    let list = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Cyan))
+            .title(format!("Memories ({})", memories.len())),
+    );
+    f.render_widget(Clear, chunks[0]);
+    f.render_widget(list, chunks[0]);
+
+    let (text, title) = match &screen.editing {
+        Some(text) if screen.adding => (text.clone(), "New project memory (Enter to save, Esc to cancel)"),
+        Some(text) => (text.clone(), "Edit memory (Enter to save, Esc to cancel)"),
+        None => (
+            screen.status.clone().unwrap_or_default(),
+            "↑/↓ select, Enter edit, n new, g project/global, d delete, Esc close",
+        ),
+    };
+    let footer = Paragraph::new(text)
+        .block(Block::default().borders(Borders::ALL).border_style(Style::default().fg(Color::Cyan)).title(title));
+    f.render_widget(Clear, chunks[1]);
+    f.render_widget(footer, chunks[1]);
+    if let Some(text) = &screen.editing {
+        f.set_cursor(chunks[1].x + 1 + text.chars().count() as u16, chunks[1].y + 1);
+    }
+}
+
+/// The slash commands or files matching what is being typed, in a popup
+/// above the input box.
+fn draw_completions<B: Backend>(f: &mut Frame<B>, input_area: Rect, app: &App) {
+    let completions = app.completions();
+    if completions.is_empty() {
+        return;
+    }
+    let visible = completions.len().min(10);
+    let selected = app.completion.min(completions.len() - 1);
+    // Scroll so the highlighted command stays in view
+    let first = (selected + 1).saturating_sub(visible);
+    let usage_width = completions
+        .iter()
+        .map(|completion| completion.text.len() + completion.args.len() + 1)
+        .max()
+        .unwrap_or(0);
+    let lines: Vec<Spans> = completions
+        .iter()
+        .enumerate()
+        .skip(first)
+        .take(visible)
+        .map(|(i, completion)| {
+            let style = if i == selected {
+                Style::default().fg(Color::Black).bg(Color::Cyan)
+            } else {
+                Style::default().fg(Color::White)
+            };
+            let usage = format!("{} {}", completion.text, completion.args);
+            Spans::from(vec![
+                Span::styled(format!(" {:<width$} ", usage, width = usage_width), style.add_modifier(Modifier::BOLD)),
+                Span::styled(format!(" {} ", completion.description), style),
+            ])
+        })
+        .collect();
+    let height = visible as u16 + 2;
+    let area = Rect {
+        x: input_area.x,
+        y: input_area.y.saturating_sub(height),
+        width: input_area.width.min(90),
+        height: height.min(input_area.y),
+    };
+    let popup = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Cyan))
+            .title("Tab or Enter to complete"),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(popup, area);
+}
+
+/// The most recent log records, newest at the bottom.
+fn draw_log_panel<B: Backend>(f: &mut Frame<B>, area: Rect) {
+    let records = logging::recent(area.height.saturating_sub(2) as usize);
+    let lines: Vec<Spans> = records
+        .iter()
+        .map(|record| {
+            let color = match record.level {
+                Level::Error => Color::Red,
+                Level::Warn => Color::Yellow,
+                Level::Info => Color::White,
+                Level::Debug => Color::Gray,
+                Level::Trace => Color::DarkGray,
+            };
+            Spans::from(Span::styled(record.line(), Style::default().fg(color)))
+        })
+        .collect();
+    let panel = Paragraph::new(lines).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::DarkGray))
+            .title("Log (F2 to hide)"),
+    );
+    f.render_widget(Clear, area);
+    f.render_widget(panel, area);
 }
 
 fn draw_title_art<B: Backend>(f: &mut Frame<B>, area: Rect) {
@@ -68,8 +220,8 @@ fn draw_title_art<B: Backend>(f: &mut Frame<B>, area: Rect) {
 fn draw_model_selector_bar<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
     // Minimal top bar with model selector
     let mut spans = vec![Span::styled("Model:", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD))];
-    for (i, model) in app.models.iter().enumerate() {
-        let style = if i == app.selected_model_index {
+    for (i, model) in app.conversation.models.iter().enumerate() {
+        let style = if i == app.conversation.selected_model_index {
             Style::default().fg(Color::Black).bg(Color::Cyan).add_modifier(Modifier::BOLD)
         } else {
             Style::default().fg(Color::White)
@@ -84,94 +236,248 @@ fn draw_model_selector_bar<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App)
     f.render_widget(bar, area);
 }
 
+/// Maximum number of diff lines shown in a pending tool call.
+const MAX_PREVIEW_LINES: usize = 40;
+
 fn draw_chat_area<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
-    use tui::text::Text;
-    let mut text = Text::default();
-    // Estimate how many lines each message will take (naive: 2 lines per message)
     let max_lines = area.height as usize;
+    let width = area.width.max(1) as usize;
     let mut lines_used = 0;
     let mut visible_msgs = Vec::new();
+    let mut items: Vec<Vec<Spans>> = app.conversation.messages.iter().map(message_spans).collect();
+    if let Some(plan) = &app.conversation.plan {
+        items.push(plan_spans(plan));
+    }
+    if let Some(running) = &app.conversation.running_command {
+        items.push(running_command_spans(running));
+    }
     // Walk backwards through messages, collecting as many as fit
-    for message in app.messages.iter().rev() {
-        // Estimate lines for each message (could be improved with real wrapping logic)
-        let lines = 3; // 1 for content, 1 for meta, 1 for spacing
-        if lines_used + lines > max_lines {
+    for spans in items.into_iter().rev() {
+        let lines: usize = spans.iter().map(|s| s.width().max(1).div_ceil(width)).sum();
+        if lines_used + lines > max_lines && !visible_msgs.is_empty() {
             break;
         }
-        visible_msgs.push(message);
+        visible_msgs.push(spans);
         lines_used += lines;
     }
     visible_msgs.reverse();
-    for message in visible_msgs {
-        match message {
-            Message::User { content, timestamp } => {
-                text.extend(vec![
-                    Spans::from(vec![
-                        Span::styled("> ", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
-                        Span::styled(content, Style::default().fg(Color::White)),
-                    ]),
-                    Spans::from(vec![
-                        Span::styled(
-                            format!("USER {}", timestamp.format("%H:%M")),
-                            Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
-                        ),
-                    ]),
-                    Spans::from("")
-                ]);
-            }
-            Message::Assistant { content, timestamp } => {
-                text.extend(vec![
-                    Spans::from(vec![
-                        Span::styled("< ", Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD)),
-                        Span::styled(content, Style::default().fg(Color::White)),
-                    ]),
-                    Spans::from(vec![
-                        Span::styled(
-                            format!("ASSISTANT {}", timestamp.format("%H:%M")),
-                            Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
-                        ),
-                    ]),
-                    Spans::from("")
-                ]);
-            }
-            Message::PendingToolCall { tool_call, original_message, timestamp: _ } => {
-                let (desc, color) = match tool_call {
-                    ToolCall::ReadFile { path } => (format!("[TOOL CALL] read_file: {}", path), Color::Green),
-                    ToolCall::ReadDirectory { path } => (format!("[TOOL CALL] read_directory: {}", path), Color::Blue),
-                    ToolCall::EditFile { path, .. } => (format!("[TOOL CALL] edit_file: {}", path), Color::Yellow),
-                };
-                text.extend(vec![
-                    Spans::from(vec![Span::styled(desc, Style::default().fg(color).add_modifier(Modifier::BOLD))]),
-                    Spans::from(vec![Span::styled(format!("[tool_call: {}]", original_message), Style::default().fg(Color::DarkGray))]),
-                    Spans::from(vec![Span::styled("→ Accept   ← Deny", Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))]),
-                    Spans::from("")
-                ]);
+    let mut text = Text::default();
+    for spans in visible_msgs {
+        text.extend(spans);
+    }
+    // Keep the newest lines visible if the last message alone overflows
+    let scroll = lines_used.saturating_sub(max_lines) as u16;
+    let para = Paragraph::new(text).wrap(Wrap { trim: false }).scroll((scroll, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(para, area);
+}
+
+fn message_spans(message: &Message) -> Vec<Spans<'_>> {
+    match message {
+        Message::User { content, timestamp } => vec![
+            Spans::from(vec![
+                Span::styled("> ", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+                Span::styled(content.as_str(), Style::default().fg(Color::White)),
+            ]),
+            Spans::from(vec![
+                Span::styled(
+                    format!("USER {}", timestamp.format("%H:%M")),
+                    Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+                ),
+            ]),
+            Spans::from("")
+        ],
+        Message::Assistant { content, timestamp } => vec![
+            Spans::from(vec![
+                Span::styled("< ", Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD)),
+                Span::styled(content.as_str(), Style::default().fg(Color::White)),
+            ]),
+            Spans::from(vec![
+                Span::styled(
+                    format!("ASSISTANT {}", timestamp.format("%H:%M")),
+                    Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+                ),
+            ]),
+            Spans::from("")
+        ],
+        Message::PendingToolCall { tool_call, original_message, preview, risk, timestamp: _ } => {
+            let color = match risk {
+                Risk::Read => Color::Green,
+                Risk::Create => Color::Blue,
+                Risk::Modify => Color::Yellow,
+                Risk::Execute => Color::Magenta,
+                Risk::Destructive => Color::Red,
+            };
+            let desc = format!("[TOOL CALL] {}: {}  ({})", tool_call.name(), tool_call.target(), risk.label());
+            let mut spans = vec![
+                Spans::from(vec![Span::styled(desc, Style::default().fg(color).add_modifier(Modifier::BOLD))]),
+            ];
+            match preview {
+                // The sandbox the command runs in, which is not a diff
+                Some(preview) if matches!(tool_call, ToolCall::RunCommand { .. }) => {
+                    spans.push(Spans::from(vec![Span::styled(preview.as_str(), Style::default().fg(Color::Yellow))]))
+                }
+                Some(preview) => spans.extend(diff_spans(preview)),
+                None => spans.push(Spans::from(vec![Span::styled(
+                    format!("[tool_call: {}]", original_message),
+                    Style::default().fg(Color::DarkGray),
+                )])),
             }
-            Message::ToolCallResult { result, timestamp } => {
-                text.extend(vec![
-                    Spans::from(vec![Span::styled(result, Style::default().fg(Color::White))]),
-                    Spans::from(vec![Span::styled(format!("{}", timestamp.format("%H:%M")), Style::default().fg(Color::DarkGray))]),
-                    Spans::from("")
-                ]);
+            spans.push(Spans::from(vec![Span::styled(
+                format!("→ Accept   ← Deny   a Always allow {} this session", tool_call.name()),
+                Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD),
+            )]));
+            spans.push(Spans::from(""));
+            spans
+        }
+        Message::ToolCallResult { result, timestamp } => {
+            // The header lines, then the end of the output
+            let lines: Vec<&str> = result.lines().collect();
+            let header = lines.iter().position(|l| *l == "---").unwrap_or(lines.len());
+            let tail = lines.len().saturating_sub(MAX_LIVE_OUTPUT_LINES).max(header);
+            let mut spans: Vec<Spans> = lines[..header]
+                .iter()
+                .map(|l| Spans::from(vec![Span::styled(*l, Style::default().fg(Color::White))]))
+                .collect();
+            if tail > header + 1 {
+                spans.push(Spans::from(vec![Span::styled(
+                    format!("... {} lines", tail - header - 1),
+                    Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+                )]));
             }
-            Message::ToolCallDenied { tool_call, original_message, timestamp } => {
-                let (desc, color) = match tool_call {
-                    ToolCall::ReadFile { path } => (format!("[TOOL CALL DENIED] read_file: {}", path), Color::Red),
-                    ToolCall::ReadDirectory { path } => (format!("[TOOL CALL DENIED] read_directory: {}", path), Color::Red),
-                    ToolCall::EditFile { path, .. } => (format!("[TOOL CALL DENIED] edit_file: {}", path), Color::Red),
-                };
-                text.extend(vec![
-                    Spans::from(vec![Span::styled(desc, Style::default().fg(color).add_modifier(Modifier::BOLD))]),
-                    Spans::from(vec![Span::styled(format!("[tool_call: {}]", original_message), Style::default().fg(Color::DarkGray))]),
-                    Spans::from(vec![Span::styled(format!("{}", timestamp.format("%H:%M")), Style::default().fg(Color::DarkGray))]),
-                    Spans::from("")
-                ]);
+            spans.extend(lines[tail..].iter().filter(|l| **l != "---").map(|l| Spans::from(vec![Span::styled(*l, Style::default().fg(Color::Gray))])));
+            spans.push(Spans::from(vec![Span::styled(format!("{}", timestamp.format("%H:%M")), Style::default().fg(Color::DarkGray))]));
+            spans.push(Spans::from(""));
+            spans
+        }
+        Message::ToolCallDenied { tool_call, original_message, timestamp } => {
+            let desc = format!("[TOOL CALL DENIED] {}: {}", tool_call.name(), tool_call.target());
+            vec![
+                Spans::from(vec![Span::styled(desc, Style::default().fg(Color::Red).add_modifier(Modifier::BOLD))]),
+                Spans::from(vec![Span::styled(format!("[tool_call: {}]", original_message), Style::default().fg(Color::DarkGray))]),
+                Spans::from(vec![Span::styled(format!("{}", timestamp.format("%H:%M")), Style::default().fg(Color::DarkGray))]),
+                Spans::from("")
+            ]
+        }
+        Message::AgentStep { number, tool, target, auto, status, timestamp: _ } => {
+            let (mark, color) = match status {
+                StepStatus::Running => ("…", Color::Yellow),
+                StepStatus::Done => ("✓", Color::Green),
+                StepStatus::Failed => ("✗", Color::Red),
+            };
+            let mut spans = vec![
+                Span::styled(format!("{} {:>2}. ", mark, number), Style::default().fg(color).add_modifier(Modifier::BOLD)),
+                Span::styled(format!("{}: {}", tool, target), Style::default().fg(Color::White)),
+            ];
+            if let Some(reason) = auto {
+                spans.push(Span::styled(format!("  auto: {}", reason), Style::default().fg(Color::DarkGray)));
             }
+            vec![Spans::from(spans)]
+        }
+        Message::Info { content, timestamp } => {
+            let mut spans: Vec<Spans> = content
+                .lines()
+                .map(|l| Spans::from(vec![Span::styled(l, Style::default().fg(Color::Cyan))]))
+                .collect();
+            spans.push(Spans::from(vec![Span::styled(format!("{}", timestamp.format("%H:%M")), Style::default().fg(Color::DarkGray))]));
+            spans.push(Spans::from(""));
+            spans
         }
     }
-    let para = Paragraph::new(text).wrap(Wrap { trim: true });
-    f.render_widget(Clear, area);
-    f.render_widget(para, area);
+}
+
+/// Maximum number of output lines shown while a command runs.
+const MAX_LIVE_OUTPUT_LINES: usize = 15;
+
+fn running_command_spans(running: &RunningCommand) -> Vec<Spans<'_>> {
+    let mut spans = vec![Spans::from(vec![Span::styled(
+        format!("[RUNNING] $ {}  ({}, Esc to cancel)", running.command, format_duration(running.started.elapsed())),
+        Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD),
+    )])];
+    let output = running.output();
+    let lines: Vec<&str> = output.lines().collect();
+    let skip = lines.len().saturating_sub(MAX_LIVE_OUTPUT_LINES);
+    spans.extend(lines[skip..].iter().map(|line| {
+        // A progress bar redraws its line after each `\r`; show the last state
+        let line = line.trim_end_matches('\r').rsplit('\r').next().unwrap_or_default();
+        Spans::from(vec![Span::styled(line.to_string(), Style::default().fg(Color::Gray))])
+    }));
+    spans.push(Spans::from(""));
+    spans
+}
+
+/// The plan as a checklist, kept below the chat while plan mode is active.
+fn plan_spans(plan: &Plan) -> Vec<Spans<'_>> {
+    let phase = match plan.phase {
+        Phase::Drafting => "drafting",
+        Phase::Proposed => "waiting for approval",
+        Phase::Executing => "running",
+        Phase::Paused => "paused",
+    };
+    let mut spans = vec![Spans::from(vec![Span::styled(
+        format!("[PLAN] {}  ({})", plan.task, phase),
+        Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+    )])];
+    for (i, step) in plan.steps.iter().enumerate() {
+        let (mark, color) = match step.state {
+            StepState::Todo => ("[ ]", Color::White),
+            StepState::Active => ("[>]", Color::Yellow),
+            StepState::Done => ("[x]", Color::Green),
+        };
+        let mut line = vec![
+            Span::styled(format!("{} {}. ", mark, i + 1), Style::default().fg(color).add_modifier(Modifier::BOLD)),
+            Span::styled(step.text.as_str(), Style::default().fg(color)),
+        ];
+        if step.calls > 0 {
+            line.push(Span::styled(format!("  ({} calls, {} failed)", step.calls, step.failed), Style::default().fg(Color::DarkGray)));
+        }
+        spans.push(Spans::from(line));
+        let mut details = Vec::new();
+        if !step.files.is_empty() {
+            details.push(format!("files: {}", step.files.join(", ")));
+        }
+        if !step.tools.is_empty() {
+            details.push(format!("tools: {}", step.tools.join(", ")));
+        }
+        if !details.is_empty() {
+            spans.push(Spans::from(vec![Span::styled(
+                format!("      {}", details.join("  ")),
+                Style::default().fg(Color::DarkGray),
+            )]));
+        }
+    }
+    spans.push(Spans::from(""));
+    spans
+}
+
+/// Colors a unified diff line by line, truncated to `MAX_PREVIEW_LINES`.
+fn diff_spans(diff: &str) -> Vec<Spans<'_>> {
+    let total = diff.lines().count();
+    let mut spans: Vec<Spans> = diff
+        .lines()
+        .take(MAX_PREVIEW_LINES)
+        .map(|line| {
+            let color = if line.starts_with("+++") || line.starts_with("---") {
+                Color::White
+            } else if line.starts_with('+') {
+                Color::Green
+            } else if line.starts_with('-') {
+                Color::Red
+            } else if line.starts_with("@@") {
+                Color::Cyan
+            } else {
+                Color::DarkGray
+            };
+            Spans::from(vec![Span::styled(line, Style::default().fg(color))])
+        })
+        .collect();
+    if total > MAX_PREVIEW_LINES {
+        spans.push(Spans::from(vec![Span::styled(
+            format!("... {} more lines", total - MAX_PREVIEW_LINES),
+            Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+        )]));
+    }
+    spans
 }
 
 fn draw_input_area<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
@@ -182,16 +488,23 @@ fn draw_input_area<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
         Spans::from(vec![Span::styled(&app.input, Style::default().fg(Color::White))])
     };
     
-    let border_style = if app.is_loading {
+    let border_style = if app.conversation.is_loading {
         Style::default().fg(Color::Yellow) // Yellow border when loading
     } else {
         Style::default().fg(Color::Cyan) // Normal cyan border
     };
     
-    let title = if app.is_loading {
-        "Input (processing...) - Enter to send, Ctrl+C to quit"
+    let title = if app.conversation.running_command.is_some() {
+        "Input (running command...) - Esc to cancel, Ctrl+C to quit".to_string()
+    } else if app.conversation.is_loading && app.conversation.run.steps > 0 {
+        format!(
+            "Input (agent step {}/{}, {} tokens...) - Esc to stop, Ctrl+C to quit",
+            app.conversation.run.steps, app.conversation.config.agent.max_steps, app.conversation.run.tokens
+        )
+    } else if app.conversation.is_loading {
+        "Input (processing...) - Esc to stop, Ctrl+C to quit".to_string()
     } else {
-        "Input (Enter to send, Ctrl+C to quit)"
+        "Input (Enter to send, / for commands, F2 for the log, Ctrl+C to quit)".to_string()
     };
     
     let input = Paragraph::new(input_text)
@@ -206,7 +519,7 @@ fn draw_input_area<B: Backend>(f: &mut Frame<B>, area: Rect, app: &App) {
     f.render_widget(input, area);
     
     // Show cursor in input only if not loading
-    if !app.is_loading {
+    if !app.conversation.is_loading {
         let cursor_x = area.x + 1 + app.input_cursor_position as u16;
         let cursor_y = area.y + 1;
         f.set_cursor(cursor_x, cursor_y);
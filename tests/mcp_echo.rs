@@ -0,0 +1,80 @@
+//! Runs Quill's MCP client against `examples/mcp_echo.rs`.
+
+use quill_core::mcp::{McpServers, ServerConfig};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Builds the example and returns its path, next to the test binaries.
+fn echo_server() -> PathBuf {
+    let status = std::process::Command::new(env!("CARGO"))
+        .args(["build", "--quiet", "--example", "mcp_echo"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .unwrap();
+    assert!(status.success(), "cannot build the mcp_echo example");
+    let deps = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    deps.parent().unwrap().join("examples").join(format!("mcp_echo{}", std::env::consts::EXE_SUFFIX))
+}
+
+async fn start() -> McpServers {
+    let config = ServerConfig { command: echo_server().display().to_string(), args: Vec::new(), env: HashMap::new() };
+    let (servers, errors) = McpServers::start(&HashMap::from([("echo".to_string(), config)]), &std::env::temp_dir()).await;
+    assert!(errors.is_empty(), "{:?}", errors);
+    servers
+}
+
+fn arguments(value: Value) -> Map<String, Value> {
+    value.as_object().unwrap().clone()
+}
+
+#[tokio::test]
+async fn starts_and_lists_tools_and_resources() {
+    let servers = start().await;
+
+    assert_eq!(servers.servers.len(), 1);
+    let server = &servers.servers[0];
+    assert_eq!(server.name, "echo");
+    let tools: Vec<&str> = server.tools.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(tools, ["echo", "add", "exit"]);
+    assert_eq!(server.tools[0].input_schema["required"], json!(["text"]));
+    assert_eq!(server.resources[0].uri, "echo://readme");
+    assert!(servers.prompt_section().contains("echo.add"));
+}
+
+#[tokio::test]
+async fn calls_tools() {
+    let servers = start().await;
+
+    let output = servers.call_tool("echo.echo", &arguments(json!({ "text": "hello" }))).await;
+    assert_eq!(output, "[TOOL RESULT: echo.echo]\n---\nhello");
+    let output = servers.call_tool("echo.add", &arguments(json!({ "a": 2, "b": 3 }))).await;
+    assert!(output.ends_with("\n5"), "{}", output);
+    let output = servers.call_tool("echo.add", &arguments(json!({ "a": "two" }))).await;
+    assert!(output.starts_with("[TOOL ERROR: echo.add]"), "{}", output);
+    let output = servers.call_tool("echo.missing", &Map::new()).await;
+    assert!(output.contains("has no tool `missing`"), "{}", output);
+}
+
+#[tokio::test]
+async fn reads_resources() {
+    let servers = start().await;
+
+    let output = servers.read_resource("echo", "echo://readme").await;
+    assert!(output.ends_with("The echo server repeats text and adds numbers."), "{}", output);
+    let output = servers.read_resource("echo", "echo://missing").await;
+    assert!(output.starts_with("[TOOL ERROR: mcp_read_resource]"), "{}", output);
+}
+
+#[tokio::test]
+async fn a_server_that_exits_fails_calls_instead_of_hanging() {
+    let servers = start().await;
+
+    let output = tokio::time::timeout(Duration::from_secs(10), servers.call_tool("echo.exit", &Map::new())).await.unwrap();
+    assert!(output.contains("server exited"), "{}", output);
+    let output = tokio::time::timeout(Duration::from_secs(10), servers.call_tool("echo.echo", &arguments(json!({ "text": "hi" }))))
+        .await
+        .unwrap();
+    assert!(output.starts_with("[TOOL ERROR: echo.echo]"), "{}", output);
+}